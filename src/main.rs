@@ -6,9 +6,11 @@
 
 
 // --- 依赖引入 ---
-use clap::{CommandFactory, Parser}; // 用于命令行参数解析
-use once_cell::sync::Lazy; // 用于惰性初始化静态变量 (如 Regex)
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum}; // 用于命令行参数解析
+use once_cell::sync::{Lazy, OnceCell}; // 用于惰性初始化静态变量 (如 Regex) 和只设置一次的全局配置
 use regex::Regex; // 用于正则表达式操作
+use std::borrow::Cow; // 用于按需转换的 ASS 事件行
+use serde::Deserialize; // 用于解析 TOML 配置文件
 use std::error::Error; // 标准库错误处理 Trait
 use std::fmt; // 标准库格式化 Trait
 use std::fs::File; // 文件操作
@@ -21,6 +23,8 @@ use std::collections::HashMap;
 
 // 文件扩展名常量
 const ASS_EXTENSION: &str = ".ass";
+/// 未通过 --config 指定配置文件时，在程序所在目录查找的默认配置文件名
+const DEFAULT_CONFIG_FILE_NAME: &str = "lyrics_config.toml";
 const QRC_EXTENSION: &str = ".qrc";
 const LYRICIFY_EXTENSION: &str = ".lys";
 
@@ -53,6 +57,42 @@ const MILLISECONDS_PER_HOUR: usize = 60 * MILLISECONDS_PER_MINUTE;
 const CENTISECONDS_TO_MILLISECONDS: usize = 10;
 /// ASS 卡拉OK标签 {\kX} 中的 X (厘秒) 转换为毫秒的乘数
 const K_TAG_MULTIPLIER: usize = 10;
+/// ASS Effect 字段中精确毫秒时间记录的前缀 (见 `format_exact_timing_effect`)
+const EXACT_TIMING_EFFECT_PREFIX: &str = "x-ms:";
+/// ASS Name 字段中记录原始 LYS 属性值的标签前缀 (例如 "左 x-lys:1")，优先于演唱者规则
+const LYS_PROPERTY_NAME_PREFIX: &str = "x-lys:";
+/// ASS Name 字段中的段落 (song part) 标签前缀，例如 "左 itunes:song-part=Verse"
+const SONG_PART_NAME_PREFIX: &str = "itunes:song-part=";
+/// QRC 文件头中的注音标签名
+const QRC_KANA_TAG: &str = "kana";
+/// Aegisub 卡拉 OK 模板中原始歌词行 (Comment) 的 Effect
+const KARAOKE_EFFECT: &str = "karaoke";
+/// Aegisub 卡拉 OK 模板为注音生成的 Style 名后缀 (例如 "Default-furigana")
+const FURIGANA_STYLE_SUFFIX: &str = "-furigana";
+/// Aegisub 卡拉 OK 模板生成的特效行 (Dialogue) 的 Effect
+const TEMPLATER_FX_EFFECT: &str = "fx";
+/// Aegisub 卡拉 OK 模板和代码行 (Comment) 的 Effect 前缀 (例如 "template syl"、"code once")
+const TEMPLATER_EFFECT_PREFIXES: &[&str] = &["template", "code"];
+/// --infer-duet 中视为背景人声的 Style 名关键字 (不区分大小写，例如 "BG"、"Back Vocal")
+const BACKGROUND_STYLE_KEYWORDS: &[&str] = &["bg", "back"];
+/// QRC/LYS/LRC 中标记段落的标签名，写作 `[song-part:Verse]`，对其后的行有效 (值为空表示之后的行没有段落)
+const SONG_PART_TAG: &str = "song-part";
+
+// ASS `meta` 键与 LRC/QRC/LYS 文件头中原生标签的对应关系
+// (ncmMusicId, qqMusicId, spotifyId, appleMusicId, isrc, songwriters 等没有原生标签的键以键名本身作为标签写入 QRC/LYS)
+const METADATA_TAG_MAP: &[(&str, &str)] = &[
+    ("musicName", "ti"),             // Title / 歌曲名
+    ("artists", "ar"),               // Artist / 艺术家
+    ("album", "al"),                 // Album / 专辑
+    ("ttmlAuthorGithubLogin", "by"), // Editor / 编辑者
+    ("offset", "offset"),            // 整体时间偏移 (毫秒)
+    ("length", "length"),            // 歌曲长度
+];
+// 可以有多个值的元数据键，写入文件头时合并为一个标签，值之间用 METADATA_VALUE_SEPARATOR 分隔
+const MULTI_VALUE_METADATA_KEYS: &[&str] = &[
+    "artists", "songwriters", "ncmMusicId", "qqMusicId", "spotifyId", "appleMusicId", "isrc",
+];
+const METADATA_VALUE_SEPARATOR: &str = "/";
 
 // 进度条显示相关常量
 const PROGRESS_BAR_LENGTH: usize = 20; // 进度条的字符显示长度
@@ -69,7 +109,7 @@ const CYAN: &str = "\x1b[36m";  // 青色 (通常用于提示信息)
 const LYS_PROPERTY_UNSET: usize = 0; // 默认对齐
 const LYS_PROPERTY_LEFT: usize = 1; // 仅左对齐
 const LYS_PROPERTY_RIGHT: usize = 2; // 仅右对齐
-// const LYS_PROPERTY_NO_BACK_UNSET: usize = 3; // (未使用)
+const LYS_PROPERTY_NO_BACK_UNSET: usize = 3; // 无背景，对齐方式未定
 const LYS_PROPERTY_NO_BACK_LEFT: usize = 4; // 无背景，左对齐 (对应 ASS Name="左")
 const LYS_PROPERTY_NO_BACK_RIGHT: usize = 5; // 无背景，右对齐 (对应 ASS Name="右")
 const LYS_PROPERTY_BACK_UNSET: usize = 6; // 有背景，对齐方式待定 (对应 ASS Name="背"，需看前一行)
@@ -159,38 +199,416 @@ impl From<ParseIntError> for ConversionError {
 
 // --- 数据结构定义 ---
 
-/// 存储从 ASS Dialogue 行解析出的关键信息。
-/// 主要用于 ASS -> LYS 的两遍扫描，避免重复解析。
-#[derive(Clone)] // 需要 Clone trait 因为 LYS 转换逻辑需要访问前一个元素
-struct ParsedDialogue {
-    line_number: usize,             // 该 Dialogue 在原始 ASS 文件中的行号 (用于报错)
-    start_ms: usize,                // Dialogue 开始时间 (毫秒)
-    name: Option<String>,           // Dialogue 的 Name 字段内容 (例如 "左", "右", "背")
-    segments: Vec<(String, usize)>, // 从 {\k} 标签解析出的文本段及其持续时间 (毫秒) 列表
-    duration_ms: usize,             // Dialogue 行的总持续时间 (End - Start) (毫秒)
-    sum_k_ms: usize,                // 行内所有 {\k} 标签解析出的时长总和 (毫秒)
-    style: String,
+/// 歌词模型中的单个音节 (逐字/逐段时间单元)。
+/// 所有时间均为绝对时间 (毫秒)，与源格式无关。
+#[derive(Clone, Debug)]
+struct Syllable {
+    text: String,       // 音节文本 (停顿的文本为空)
+    start_ms: usize,    // 音节开始时间 (毫秒)
+    duration_ms: usize, // 音节持续时间 (毫秒)
+    is_gap: bool,       // 是否为停顿 (例如 ASS 中仅有 {\k} 标签、没有文本的分段)，而不是真正的歌词音节
+    ass_tags: String,   // 与 {\k} 同属该音节的其他 ASS 覆盖标签 (例如 "\1c&HFF0000&")，只在写入 ASS 时使用
+    ruby: Option<String>, // 注音 (振り仮名)，来自 Aegisub 卡拉 OK 的 "漢字|かんじ"；空字符串表示属于前一个音节的注音 ("|<")
 }
 
-/// 定义 ASS Name 字段的逻辑分类，用于简化 LYS 属性计算。
-#[derive(PartialEq, Eq, Debug, Clone, Copy)] // 派生必要的 Trait
-enum AssNameCategory {
-    LeftV1,     // 代表 "" (空), "v1", "左", 以及 None (无 Name 字段)
-    RightV2,    // 代表 "右", "v2", "x-duet", "x-anti"
-    Background, // 代表 "背", "x-bg"
-    Other,      // 代表任何其他非空的 Name 字段
+impl Syllable {
+    /// 写入 ASS 的音节文本: 有注音时按 Aegisub 卡拉 OK 语法写为 "漢字|かんじ" (并入前一个注音时为 "|<")。
+    fn karaoke_text(&self) -> String {
+        match self.ruby.as_deref() {
+            Some("") => format!("{}|<", self.text),
+            Some(ruby) => format!("{}|{}", self.text, ruby),
+            None => self.text.clone(),
+        }
+    }
+
+    /// 音节的结束时间 (毫秒)。
+    fn end_ms(&self) -> usize {
+        self.start_ms.saturating_add(self.duration_ms)
+    }
+}
+
+/// 歌词模型中的一行。
+/// 由各格式的读取器 (ASS/QRC/LYS) 生成，再交给各格式的写入器输出。
+#[derive(Clone, Debug)]
+struct LyricLine {
+    line_number: usize,       // 该行在原始文件中的行号 (用于报错)
+    start_ms: usize,          // 行开始时间 (毫秒)
+    end_ms: usize,            // 行结束时间 (毫秒)
+    style: String,            // ASS Style 字段 (QRC/LYS 读取时为 "Default")
+    name: Option<String>,     // ASS Name 字段内容 (例如 "左", "右", "背")
+    effect: Option<String>,   // ASS Effect 字段内容 (精确毫秒记录 x-ms:... 除外)，用于演唱者规则匹配
+    song_part: Option<String>, // 所属段落 (例如 "Verse", "Chorus")，来自 Name 中的 itunes:song-part=... 或 [song-part:...] 标签
+    syllables: Vec<Syllable>, // 行内的音节列表，按时间顺序排列
+    untimed: bool,            // 是否为没有逐字时间的纯文本行 (例如没有 {\k} 标签的翻译行)，此时只有一个覆盖整行的音节
+    position: Option<DialoguePosition>, // ASS 行的定位信息 (用于 --infer-duet)，其他格式为 None
+}
+
+impl LyricLine {
+    /// 行的总持续时间 (End - Start) (毫秒)。
+    fn duration_ms(&self) -> usize {
+        self.end_ms.saturating_sub(self.start_ms)
+    }
+
+    /// 行内所有音节时长之和 (毫秒)，对于 ASS 即所有 {\k} 标签时长的总和。
+    fn sum_syllables_ms(&self) -> usize {
+        self.syllables.iter().map(|s| s.duration_ms).sum()
+    }
+
+    /// 写入 ASS 的 Name: 原 Name 加上段落标签 (如果有)。
+    fn ass_name(&self) -> String {
+        let name = self.name.as_deref().unwrap_or("");
+        match &self.song_part {
+            Some(part) if name.is_empty() => format!("{}{}", SONG_PART_NAME_PREFIX, part),
+            Some(part) => format!("{} {}{}", name, SONG_PART_NAME_PREFIX, part),
+            None => name.to_string(),
+        }
+    }
+
+    /// 行的完整文本 (所有音节文本拼接，不含停顿)。
+    fn text(&self) -> String {
+        self.syllables.iter().filter(|s| !s.is_gap).map(|s| s.text.as_str()).collect()
+    }
+}
+
+/// 所有转换共享的歌词模型：先由读取器把源文件解析为该结构，再由写入器输出为目标格式。
+#[derive(Clone, Debug, Default)]
+struct Lyrics {
+    metadata: Vec<MetadataEntry>, // 元数据，按源文件中的顺序排列
+    lines: Vec<LyricLine>,        // 歌词行，按源文件中的顺序排列
+}
+
+impl Lyrics {
+    /// 添加一条元数据。键和值都相同的元数据只保留一条。
+    fn add_metadata(&mut self, key: &str, value: &str) {
+        let (key, value) = (key.trim(), value.trim());
+        if key.is_empty() || value.is_empty() {
+            return;
+        }
+        if !self.metadata.iter().any(|entry| entry.key == key && entry.value == value) {
+            self.metadata.push(MetadataEntry { key: key.to_string(), value: value.to_string() });
+        }
+    }
+
+    /// 设置元数据，替换该键原有的所有值。
+    /// `key` 可以是 ASS `meta` 键 (例如 "musicName") 或 LRC 风格标签 (例如 "ti")；
+    /// 多值键的值可以用 `METADATA_VALUE_SEPARATOR` 分隔，值为空时等同于删除该键。
+    fn set_metadata(&mut self, key: &str, value: &str) {
+        let key = metadata_key_for_tag(key.trim()).to_string();
+        // 新值放在该键原来第一次出现的位置，保持元数据的顺序
+        let position = self.metadata.iter().position(|entry| entry.key == key).unwrap_or(self.metadata.len());
+        self.remove_metadata(&key);
+        let mut values: Vec<&str> = split_metadata_values(&key, value);
+        values.dedup();
+        let entries = values.into_iter().map(|value| MetadataEntry { key: key.clone(), value: value.to_string() });
+        self.metadata.splice(position..position, entries);
+    }
+
+    /// 删除指定键 (ASS `meta` 键或 LRC 风格标签) 的所有元数据。
+    fn remove_metadata(&mut self, key: &str) {
+        let key = metadata_key_for_tag(key.trim());
+        self.metadata.retain(|entry| entry.key != key);
+    }
+
+    /// 删除所有元数据。
+    fn clear_metadata(&mut self) {
+        self.metadata.clear();
+    }
+
+    /// 合并其他来源的元数据：多值键追加新值，其他键只在尚未存在时添加 (已有的值优先)。
+    fn merge_metadata(&mut self, entries: impl IntoIterator<Item = MetadataEntry>) {
+        for entry in entries {
+            let is_multi_value = MULTI_VALUE_METADATA_KEYS.contains(&entry.key.as_str());
+            if is_multi_value || !self.metadata.iter().any(|existing| existing.key == entry.key) {
+                self.add_metadata(&entry.key, &entry.value);
+            }
+        }
+    }
+}
+
+/// 歌词模型中的一条元数据。
+/// 键统一使用 ASS `meta` Comment 行中的键名 (例如 "musicName")，写入 QRC/LYS 时再映射为 LRC 风格的标签。
+/// 无法识别的键原样保留。
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct MetadataEntry {
+    key: String,   // 元数据键 (例如 "musicName", "artists")
+    value: String, // 元数据值
+}
+
+/// 对唱中演唱者所在的一侧。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum DuetSide {
+    Left,  // 左 (对应 LYS 属性 1/4/7)
+    Right, // 右 (对应 LYS 属性 2/5/8)
+}
+
+/// 由演唱者规则得到的一行的演唱者信息，与 LYS 属性一一对应。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+struct SingerInfo {
+    side: Option<DuetSide>,  // 对唱方向，None 表示未定
+    background: Option<bool>, // 是否为背景人声，None 表示未定
+}
+
+/// 演唱者规则: Name 中的某个词、Style 或 Effect 匹配时，设置对唱方向和/或背景人声标记。
+/// 配置文件中以 [[singer_rules]] 给出，排在内置规则之前。
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SingerRule {
+    names: Vec<String>,       // Name 中以空格分隔的任意一个词与其中之一相同时匹配 ("" 匹配空 Name)
+    styles: Vec<String>,      // Style 与其中之一相同时匹配 (不区分大小写)
+    effects: Vec<String>,     // Effect 中以空格分隔的任意一个词与其中之一相同时匹配 (不区分大小写)
+    side: Option<DuetSide>,   // 匹配时设置的对唱方向
+    background: Option<bool>, // 匹配时设置的背景人声标记
+}
+
+/// ASS Style 对应的行角色，决定各转换和提取如何处理该行。
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum StyleRole {
+    Main,                         // 主歌词 (未配置的 Style 默认为主歌词)
+    Translation(Option<String>),  // 翻译，可带语言代码
+    Romanization(Option<String>), // 罗马音，可带语言代码
+    Ignore,                       // 读取 ASS 时直接丢弃
+}
+
+/// 配置文件 (TOML) 的原始内容，字段见 `LyricsConfig`。
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    default_language: Option<String>, // 翻译行没有 x-lang: Name 时使用的语言代码
+    styles: HashMap<String, String>,  // Style -> 角色 ("main", "translation[:lang]", "romanization[:lang]", "ignore")
+    singer_rules: Vec<SingerRule>,     // 演唱者规则，排在内置规则之前
+    include_comments: bool,            // 读取 ASS 时把被注释掉的歌词行 (Comment) 也作为歌词
+    layers: Vec<u32>,                  // 读取 ASS 时只使用这些 Layer 的行 (为空表示全部)
+    karaoke_syntax: Option<KaraokeSyntax>, // 读取 ASS 时是否处理 Aegisub 卡拉 OK 的多段高亮和注音语法
+}
+
+/// 解析后的全局配置 (--config 或程序目录下的 lyrics_config.toml)，启动时设置一次。
+#[derive(Debug)]
+struct LyricsConfig {
+    style_roles: HashMap<String, StyleRole>, // 键为小写的 Style 名
+    style_names: Vec<(String, StyleRole)>,   // 配置中的 Style 名 (保留原大小写，按名称排序) 及其角色 (语言代码为小写)
+    default_language: Option<String>,        // 翻译行的默认语言代码
+    singer_rules: Vec<SingerRule>,           // 配置的演唱者规则 + 内置规则 (按顺序匹配)
+    include_comments: bool,                  // 是否读取被注释掉的歌词行 (--include-comments)
+    layers: Vec<u32>,                        // 只读取这些 Layer 的行，为空表示全部 (--layer)
+    karaoke_syntax: KaraokeSyntax,           // 是否处理 Aegisub 卡拉 OK 的 # 和 漢字|かんじ 语法 (--karaoke-syntax)
+}
+
+impl Default for LyricsConfig {
+    fn default() -> Self {
+        LyricsConfig {
+            style_roles: HashMap::new(),
+            style_names: Vec::new(),
+            default_language: None,
+            singer_rules: default_singer_rules(),
+            include_comments: false,
+            layers: Vec::new(),
+            karaoke_syntax: KaraokeSyntax::default(),
+        }
+    }
+}
+
+/// 可读取的歌词文件格式。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum LyricFormat {
+    Ass,
+    Qrc,
+    Lys,
+}
+
+impl LyricFormat {
+    /// 根据文件扩展名 (不区分大小写) 判断格式。
+    fn from_path(path: &Path) -> Option<Self> {
+        let extension = path.extension()?.to_str()?.to_lowercase();
+        match extension.as_str() {
+            "ass" => Some(LyricFormat::Ass),
+            "qrc" => Some(LyricFormat::Qrc),
+            "lys" => Some(LyricFormat::Lys),
+            _ => None,
+        }
+    }
+
+    /// 格式在日志中显示的名称。
+    fn name(self) -> &'static str {
+        match self {
+            LyricFormat::Ass => "ASS",
+            LyricFormat::Qrc => "QRC",
+            LyricFormat::Lys => "LYS",
+        }
+    }
+}
+
+/// 转换时的附加选项 (由命令行参数构建，交互模式下使用默认值)。
+#[derive(Debug, Default)]
+struct ConversionOptions {
+    time_warp: Option<TimeWarp>, // 分段线性时间扭曲 (--anchor / --anchor-file)
+    rounding: RoundingStrategy,  // 写入 ASS 时毫秒到厘秒的取整策略 (--rounding)
+    fix_timing: Option<TimingFixStrategy>, // 修复 K 标签与行时长不一致的策略 (--fix-timing)
+    exact_ms: bool,              // 写入 ASS 时在 Effect 字段中嵌入精确毫秒时间 (--exact-ms)
+    gap_mode: GapMode,           // 写入 QRC/LYS 时停顿的处理方式 (--gap-mode)
+    strip_metadata: bool,        // 写入前删除源文件中的所有元数据 (--strip-meta)
+    metadata_overrides: Vec<(String, String)>, // 设置或覆盖的元数据 (键, 值) (--meta)
+    extract_format: ExtractFormat,         // 提取翻译和罗马音的输出格式 (--extract-format)
+    merged_lrc: bool,                      // 额外生成主歌词、翻译和罗马音合并的 LRC (--merged-lrc)
+    merged_lrc_order: Vec<String>,         // 合并 LRC 中的轨道顺序 (--lrc-order)
+    background_mode: BackgroundMode,       // QRC 和合并 LRC 中背景人声行的处理方式 (--bg-mode)
+    inline_background: InlineBackgroundMode, // 读取 QRC 时行内 (...) 的处理方式 (--inline-bg)
+    song_part_tags: bool,                  // 在 QRC/LYS/合并 LRC 中写出 [song-part:...] 段落标签 (--song-part-tags)
+    infer_duet: bool,                      // ASS 转 QRC/LYS 时按定位推断 Name 为空的行的对唱方向 (--infer-duet)
+    furigana: FuriganaMode,                // 注音的处理方式 (--furigana)
+}
+
+/// 转换时对注音 (来自 ASS 的 "漢字|かんじ") 的处理方式。LYS 和 LRC 没有注音，总是只写出正文。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum FuriganaMode {
+    /// 读取后即丢弃注音，所有输出格式都只写出正文
+    #[default]
+    Strip,
+    /// 保留注音，写入 QRC 时在文件头写出 [kana:...] 标签
+    Kana,
+}
+
+/// 读取 ASS 时是否处理 Aegisub 卡拉 OK 的多段高亮 (#) 和注音 (漢字|かんじ) 语法。
+/// 不处理时 "|" 和 "#" 按普通歌词文本保留。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum KaraokeSyntax {
+    /// 只在文件看起来是 Aegisub 卡拉 OK 时处理 (有 Effect 为 karaoke 的行或 "-furigana" 样式)
+    #[default]
+    Auto,
+    /// 总是处理
+    On,
+    /// 从不处理
+    Off,
+}
+
+impl KaraokeSyntax {
+    /// 按设置和文件是否为 Aegisub 卡拉 OK 判断是否处理该语法。
+    fn enabled(self, is_karaoke_file: bool) -> bool {
+        match self {
+            KaraokeSyntax::Auto => is_karaoke_file,
+            KaraokeSyntax::On => true,
+            KaraokeSyntax::Off => false,
+        }
+    }
+}
+
+/// 写入没有背景人声概念的格式 (QRC、合并 LRC) 时背景人声行的处理方式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum BackgroundMode {
+    /// 作为独立的行写出 (与主歌词行时间重叠)
+    #[default]
+    Separate,
+    /// 以括号包裹的音节合并到前一个主歌词行 (QQ 音乐的惯例)
+    Merge,
+    /// 丢弃背景人声行
+    Drop,
+}
+
+/// 读取 QRC 时对行内以括号包裹的背景人声 (例如 `BackgroundMode::Merge` 写出的 "(ooh)") 的处理方式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum InlineBackgroundMode {
+    /// 按原样保留括号
+    #[default]
+    Keep,
+    /// 把行内的 (...) 拆分为背景人声行
+    Split,
+    /// 删除行内的 (...)
+    Drop,
+}
+
+/// 提取翻译和罗马音时的输出格式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum ExtractFormat {
+    #[default]
+    Lrc, // 只有行级时间
+    Qrc, // 保留逐字时间
+    Lys, // 保留逐字时间
+    Ass, // 保留 K 标签、Style 和 Name
+    Srt, // 只有行级时间
+}
+
+impl ExtractFormat {
+    /// 输出文件的扩展名 (不含点)。
+    fn extension(self) -> &'static str {
+        match self {
+            ExtractFormat::Lrc => "lrc",
+            ExtractFormat::Qrc => "qrc",
+            ExtractFormat::Lys => "lys",
+            ExtractFormat::Ass => "ass",
+            ExtractFormat::Srt => "srt",
+        }
+    }
+}
+
+/// 写入 QRC/LYS 等带绝对时间戳的格式时，对停顿 (没有文本的音节) 的处理方式。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum GapMode {
+    /// 按原样写出为没有文本的分段，例如 (1000,200)
+    Emit,
+    /// 合并到前一个音节，延长其时长
+    Merge,
+    /// 丢弃，下一个音节直接从其实际的绝对时间开始
+    #[default]
+    Drop,
+}
+
+/// 修复行时长与音节 (K 标签) 时长总和不一致时使用的策略。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+enum TimingFixStrategy {
+    /// 将行结束时间改为最后一个音节的结束时间
+    Stretch,
+    /// 延长或截短最后一个音节，使其结束于行结束时间 (最后一个音节开始于行结束之后时改为修改行结束时间)
+    Pad,
+    /// 按比例缩放所有音节，使其恰好填满整行
+    Scale,
+    /// 根据音节重新计算行的开始和结束时间
+    Tighten,
+}
+
+/// 写入 ASS 时将毫秒转换为厘秒 (ASS 时间戳和 {\k} 标签的精度) 的取整策略。
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, ValueEnum)]
+enum RoundingStrategy {
+    /// 所有时间和时长都向下取整
+    Truncate,
+    /// 行起止时间和每段时长各自四舍五入
+    Nearest,
+    /// 对音节边界的累计位置四舍五入，保证 K 值之和等于行时长
+    #[default]
+    Cumulative,
+}
+
+impl RoundingStrategy {
+    /// 按当前策略将毫秒数转换为厘秒数。
+    /// 对于 `Cumulative`，传入的应是绝对时间位置而不是时长。
+    fn to_centiseconds(self, ms: usize) -> usize {
+        match self {
+            RoundingStrategy::Truncate => ms / CENTISECONDS_TO_MILLISECONDS,
+            RoundingStrategy::Nearest | RoundingStrategy::Cumulative => {
+                (ms + CENTISECONDS_TO_MILLISECONDS / 2) / CENTISECONDS_TO_MILLISECONDS
+            }
+        }
+    }
+}
+
+/// 时间扭曲：根据用户提供的锚点对 (旧时间 -> 新时间)，对所有时间做分段线性插值。
+/// 锚点按旧时间升序排列，且新时间单调不减。
+/// 第一个锚点之前和最后一个锚点之后按对应锚点的恒定偏移平移。
+#[derive(Debug, Clone)]
+struct TimeWarp {
+    anchors: Vec<(usize, usize)>, // (旧时间ms, 新时间ms)
 }
 
 /// 定义转换函数的类型别名，提高可读性
-type ConversionFnSig = fn(&Path, &Path) -> Result<bool, ConversionError>;
+type ConversionFnSig = fn(&Path, &Path, &ConversionOptions) -> Result<bool, ConversionError>;
 
 // --- 静态正则表达式定义 ---
 // 使用 once_cell::sync::Lazy 确保正则表达式只在首次使用时编译一次，提高性能。
 
-/// 匹配 ASS 卡拉OK (Karaoke) 时间标签 {\kX} 或 {\kfX}，捕获时长 X (厘秒) 和紧随其后的文本。
+/// 匹配单个 ASS 卡拉OK (Karaoke) 覆盖标签 \kX、\KX、\kfX 或 \koX，捕获时长 X (厘秒)。
 static K_TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
-    // Captures: (Group 1: Duration in cs) (Group 2: Text segment)
-    Regex::new(r"\{\\k[f]?(\d+)\}([^\\{]*)").expect("未能编译 K_TAG_REGEX") // [f]? 匹配可选的 'f'
+    // Captures: (Group 1: Duration in cs)
+    Regex::new(r"^\\(?:k|K|kf|ko)(\d+)$").expect("未能编译 K_TAG_REGEX")
 });
 /// 匹配 QRC 行时间戳 `[start_ms,duration_ms]`。
 static QRC_TIMESTAMP_REGEX: Lazy<Regex> = Lazy::new(|| {
@@ -207,18 +625,28 @@ static LYS_PROPERTY_REGEX: Lazy<Regex> = Lazy::new(|| {
     // Captures: (Group 1: Property value) (Group 2: Remaining content)
     Regex::new(r"\[(\d+)\](.*)").expect("未能编译 LYS_PROPERTY_REGEX")
 });
-/// 匹配特定格式的 ASS Comment 行，用于提取元数据。
-static META_COMMENT_REGEX: Lazy<Regex> = Lazy::new(|| {
-    // 要求: Comment 行, 起始结束时间为0, Style 为 "meta", 最后捕获元数据键值对文本
-    // Captures: (Group 1: Metadata Key:Value text)
-    Regex::new(r"^Comment:\s*\d+,0:00:00\.00,0:00:00\.00,meta,,0,0,0,,(.*)")
-        .expect("未能编译 META_COMMENT_REGEX")
+/// 匹配 LRC 行首的时间标签 `[mm:ss]`、`[mm:ss.xx]` 或 `[mm:ss.xxx]`。
+static LRC_TIMESTAMP_REGEX: Lazy<Regex> = Lazy::new(|| {
+    // Captures: (Group 1: Minutes) (Group 2: Seconds) (Group 3: Fraction, optional)
+    Regex::new(r"^\[(\d+):(\d{1,2})(?:[.:](\d+))?\]").expect("未能编译 LRC_TIMESTAMP_REGEX")
+});
+/// 匹配 QRC/LYS 文件头中的 LRC 风格元数据标签 `[tag:value]`。
+static LRC_METADATA_TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
+    // Captures: (Group 1: Tag) (Group 2: Value)
+    Regex::new(r"^\[([A-Za-z][A-Za-z0-9_-]*):(.*)\]\s*$").expect("未能编译 LRC_METADATA_TAG_REGEX")
+});
+/// 匹配 ASS 的 Comment 或 Dialogue 事件行，用于提取元数据。
+/// 不限制时间、Name 和边距，Aegisub 中整体平移或重新计时过的元数据行也能识别。
+static META_EVENT_REGEX: Lazy<Regex> = Lazy::new(|| {
+    // Format: Comment/Dialogue: Layer,Start,End,Style,Name,MarginL,MarginR,MarginV,Effect,Text
+    Regex::new(r"^(?P<kind>Comment|Dialogue):\s*[^,]*,[^,]*,[^,]*,(?P<style>[^,]*),[^,]*,[^,]*,[^,]*,[^,]*,[^,]*,(?P<text>.*)")
+        .expect("未能编译 META_EVENT_REGEX")
 });
 static ASS_DIALOGUE_REGEX: Lazy<Regex> = Lazy::new(|| {
     // Format: Dialogue: Layer,Start,End,Style,Name,MarginL,MarginR,MarginV,Effect,Text
     Regex::new(
         //  ^Dialogue: Layer,   Start Time        ,    End Time         , Style    , Name     , ML , MR , MV , Effect, Text
-        r"^Dialogue:\s*[^,]+,(?P<start_time>\d+:\d+:\d+\.\d+),(?P<end_time>\d+:\d+:\d+\.\d+),(?P<style>[^,]*),(?P<name>[^,]*),[^,]*,[^,]*,[^,]*,[^,]*,(?P<text>.*)"
+        r"^Dialogue:\s*[^,]+,(?P<start_time>\d+:\d+:\d+\.\d+),(?P<end_time>\d+:\d+:\d+\.\d+),(?P<style>[^,]*),(?P<name>[^,]*),(?P<margin_l>[^,]*),(?P<margin_r>[^,]*),(?P<margin_v>[^,]*),(?P<effect>[^,]*),(?P<text>.*)"
     ).expect("未能编译 ASS_DIALOGUE_REGEX")
 });
 /// 匹配 ASS Name 字段中的语言标签 "x-lang:<code>" 并捕获语言代码。
@@ -227,15 +655,93 @@ static LANG_TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
     Regex::new(r"^x-lang:(?P<lang_code>.+)$").expect("未能编译 LANG_TAG_REGEX")
 });
 
-/// 匹配常见的 ASS 标签（如 {\...}）以方便移除。
-static ASS_TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
-    // 匹配花括号及其内部所有非花括号的内容
-    Regex::new(r"\{[^}]*\}").expect("未能编译 ASS_TAG_REGEX")
+/// 匹配 ASS 对齐标签 \anN (单个覆盖标签，见 `ass_override_tags`) 并捕获对齐值。
+static ALIGNMENT_TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\\an([1-9])$").expect("未能编译 ALIGNMENT_TAG_REGEX")
+});
+/// 匹配 ASS 主颜色标签 \c&H...& 或 \1c&H...& (单个覆盖标签) 并捕获颜色值。
+static COLOUR_TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\\1?c&H([0-9A-Fa-f]+)&?$").expect("未能编译 COLOUR_TAG_REGEX")
+});
+/// 匹配 ASS 字号标签 \fsN (单个覆盖标签) 并捕获字号。
+static FONT_SIZE_TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
+    Regex::new(r"^\\fs(\d+(?:\.\d+)?)$").expect("未能编译 FONT_SIZE_TAG_REGEX")
 });
 
+/// 全局配置，由 `main` 在处理任何文件之前设置；未设置时使用默认配置。
+static LYRICS_CONFIG: OnceCell<LyricsConfig> = OnceCell::new();
+
 
 // --- Clap 命令行接口定义 ---
 
+/// 格式转换选项，由直接转换 (位置参数) 和 `assemble` 子命令共用。
+#[derive(Args, Debug)]
+struct ConversionArgs {
+    /// 背景人声行 (背/x-bg) 在 QRC 和合并 LRC 中的处理方式。
+    /// separate: 作为独立的行; merge: 以 (...) 合并到前一个主歌词行; drop: 丢弃。
+    #[arg(long, value_enum, default_value_t = BackgroundMode::Separate)]
+    bg_mode: BackgroundMode,
+
+    /// QRC 转 ASS 时对行内 (...) (例如以 --bg-mode merge 写出的背景人声) 的处理方式。
+    /// keep: 按原样保留; split: 拆分为紧跟在该行之后的背景人声行; drop: 删除。
+    #[arg(long, value_enum, default_value_t = InlineBackgroundMode::Keep)]
+    inline_bg: InlineBackgroundMode,
+
+    /// 在 QRC、LYS 和合并 LRC 中段落 (ASS Name 中的 itunes:song-part=...) 变化处写出 [song-part:段落名] 标签行。
+    /// 读取 QRC/LYS 时总会识别该标签。
+    #[arg(long)]
+    song_part_tags: bool,
+
+    /// ASS 转 QRC/LYS 时，按 \an 标签、Style 对齐方式、MarginL/MarginR 和颜色推断 Name 为空的主歌词行的对唱方向，
+    /// 按 Style 名、字号、位置和颜色推断背景人声。推断结果和与前一行重叠的行会逐行列出以便检查。
+    #[arg(long)]
+    infer_duet: bool,
+
+    /// 对注音 (ASS 中 Aegisub 卡拉 OK 的 漢字|かんじ 语法) 的处理方式。
+    /// strip: 读取后丢弃注音，只写出正文; kana: 保留注音，转换为 QRC 时在文件头写出 [kana:...] 标签。
+    #[arg(long, value_enum, default_value_t = FuriganaMode::Strip)]
+    furigana: FuriganaMode,
+
+    /// 时间扭曲锚点，格式为 旧时间->新时间 (或 旧时间=新时间)，可重复指定。
+    /// 时间可以是毫秒数或 ASS 时间格式 (H:MM:SS.cs)。
+    /// 所有行和音节时间会在锚点之间做分段线性插值，适用于所有转换方向。
+    #[arg(long = "anchor", value_name = "OLD->NEW")]
+    anchors: Vec<String>,
+
+    /// 从文件读取时间扭曲锚点，每行一个 (格式同 --anchor，'#' 开头的行为注释)。
+    #[arg(long, value_name = "FILE")]
+    anchor_file: Option<PathBuf>,
+
+    /// 转换为 ASS 时毫秒到厘秒的取整策略。
+    /// cumulative 对累计位置取整，保证每行 K 值之和等于 End - Start。
+    #[arg(long, value_enum, default_value_t = RoundingStrategy::Cumulative)]
+    rounding: RoundingStrategy,
+
+    /// 自动修复 K 标签总和与行时长不一致的行 (翻译和罗马音行除外)，并报告修改内容。
+    /// stretch: 修改行结束时间; pad: 延长/截短最后一个音节; scale: 按比例缩放所有音节; tighten: 根据音节重算行起止时间。
+    #[arg(long, value_enum, value_name = "STRATEGY")]
+    fix_timing: Option<TimingFixStrategy>,
+
+    /// 转换为 ASS 时在每行的 Effect 字段中记录精确的毫秒时间 (x-ms:...)。
+    /// 之后将该 ASS 转回 QRC/LYS 时，未在 Aegisub 中修改过的时间会按毫秒精确还原。
+    #[arg(long)]
+    exact_ms: bool,
+
+    /// 转换为 QRC/LYS 时对停顿 (ASS 中没有文本的 {\k} 标签) 的处理方式。
+    /// drop: 丢弃; merge: 合并到前一个音节; emit: 写出为没有文本的分段。
+    #[arg(long, value_enum, default_value_t = GapMode::Drop)]
+    gap_mode: GapMode,
+
+    /// 设置或覆盖元数据，格式为 key=value，可重复指定 (例如 --meta ti="歌曲名" --meta ar="歌手")。
+    /// key 可以是 LRC 标签 (ti/ar/al/by/offset/length) 或 ASS meta 键 (musicName/artists/...)；同一个键指定多次时保留所有值，值为空时删除该键。
+    #[arg(long = "meta", value_name = "KEY=VALUE")]
+    metadata: Vec<String>,
+
+    /// 删除源文件中的所有元数据 (之后仍会应用 --meta)。
+    #[arg(long)]
+    strip_meta: bool,
+}
+
 /// 定义程序的命令行参数和选项。
 /// 使用 `clap` 的 `derive` 宏自动生成解析器和帮助信息。
 #[derive(Parser, Debug)]
@@ -245,7 +751,32 @@ static ASS_TAG_REGEX: Lazy<Regex> = Lazy::new(|| {
     about = "ASS/QRC/LYS 字幕/歌词格式转换工具",
     long_about = "一个简单的工具，用于在 ASS, QRC 和 Lyricify Syllable (.lys) 格式之间转换文件。"
 )]
+#[command(args_conflicts_with_subcommands = true)]
 struct CliArgs {
+    /// 子命令 (例如 validate)。不使用子命令时按下面的位置参数进行转换。
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    /// 配置文件 (TOML)，可在 [styles] 中指定各 Style 的角色 (main / translation[:语言代码] / romanization[:语言代码] / ignore)，
+    /// 用 default_language 指定翻译行没有 x-lang: Name 时的语言，并用 [[singer_rules]] 把 Name/Style/Effect 映射为对唱方向和背景人声。
+    /// 未指定时读取程序所在目录下的 lyrics_config.toml (如果存在)。
+    #[arg(long, global = true, value_name = "FILE")]
+    config: Option<PathBuf>,
+
+    /// 读取 ASS 时把被注释掉的歌词行 (Comment) 也作为歌词 (卡拉 OK 模板和代码行除外)。
+    /// Effect 为 karaoke 的 Comment (Aegisub 应用模板后保留的原始歌词) 总会被读取。
+    #[arg(long, global = true)]
+    include_comments: bool,
+
+    /// 读取 ASS 时只使用指定 Layer 的行，可重复指定 (默认使用全部 Layer)。覆盖配置文件中的 layers。
+    #[arg(long = "layer", global = true, value_name = "LAYER")]
+    layers: Vec<u32>,
+
+    /// 读取 ASS 时是否处理 Aegisub 卡拉 OK 的多段高亮 (#) 和注音 (漢字|かんじ) 语法。覆盖配置文件中的 karaoke_syntax。
+    /// auto: 只在文件中有 Effect 为 karaoke 的行或 "-furigana" 样式时处理; on: 总是处理; off: "|" 和 "#" 按普通文本保留。
+    #[arg(long, global = true, value_enum, value_name = "MODE")]
+    karaoke_syntax: Option<KaraokeSyntax>,
+
     /// 运行交互模式，此选项会忽略其他所有位置参数。
     #[arg(short, long)] // -i, --interactive 标志
     interactive: bool,
@@ -268,6 +799,178 @@ struct CliArgs {
     /// 如果输入是 ASS 文件，则额外提取翻译行到 LRC 文件。
     #[arg(long)] // 定义 --extract-lrc 标志
     extract_lrc: bool,
+
+    /// 提取翻译和罗马音时使用的输出格式 (默认 LRC)，带 K 标签的行在 QRC/LYS/ASS 中保留逐字时间。
+    /// 输出文件名为 <名称>.<语言代码>.<扩展名> 或 <名称>.roma.<扩展名>。指定此选项时隐含 --extract-lrc。
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    extract_format: Option<ExtractFormat>,
+
+    /// 额外生成 <名称>.merged.lrc：每个主歌词行与其翻译、罗马音使用同一个时间标签 (按时间重叠配对)。指定此选项时隐含 --extract-lrc。
+    #[arg(long)]
+    merged_lrc: bool,
+
+    /// 合并 LRC 中各轨道的顺序，用逗号分隔，main 为主歌词、roma 为罗马音、其他为语言代码 (例如 main,roma,zh)。
+    /// 未列出的轨道不写出；默认为 main、roma 以及所有翻译语言。
+    #[arg(long, value_delimiter = ',', value_name = "TRACKS")]
+    lrc_order: Vec<String>,
+
+    /// 转换选项。
+    #[command(flatten)]
+    conversion: ConversionArgs,
+}
+
+/// 除格式转换之外的子命令。
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// 检查歌词文件 (ASS/QRC/LYS) 中的常见问题，不进行转换。发现问题时以非零退出码退出。
+    Validate(ValidateArgs),
+    /// 对齐两个歌词文件 (可以是不同格式) 的行和音节，报告时间偏差和文本变化。存在差异时以非零退出码退出。
+    Diff(DiffArgs),
+    /// 把歌词文件输出为便于逐行对比的规范文本，可作为 git 的 textconv diff 驱动。
+    Textconv(TextconvArgs),
+    /// 在内存中把文件转换为每种目标格式再转换回来，报告文本、时间、角色或元数据的任何变化。存在损失时以非零退出码退出。
+    Verify(VerifyArgs),
+    /// 把翻译 (<名称>.<语言代码>.lrc) 或罗马音 (<名称>.roma.lrc) LRC 按时间合并到已有的 ASS 中。
+    Import(ImportArgs),
+    /// 以 QRC/LYS (或 ASS) 为主歌词，合并翻译/罗马音 LRC 和元数据，生成一个 ASS 工程。
+    Assemble(AssembleArgs),
+    /// 列出歌曲的段落结构 (Verse、Chorus 等) 及每段的时间范围。
+    Sections(SectionsArgs),
+}
+
+/// `validate` 子命令的参数。
+#[derive(Args, Debug)]
+struct ValidateArgs {
+    /// 要检查的歌词文件。
+    input_file: PathBuf,
+
+    /// 禁用指定规则，可重复指定。
+    #[arg(long, value_enum, value_name = "RULE")]
+    disable: Vec<ValidationRule>,
+
+    /// 只启用指定规则，可重复指定 (默认启用全部规则)。
+    #[arg(long, value_enum, value_name = "RULE")]
+    only: Vec<ValidationRule>,
+
+    /// long-syllable 规则的音节时长上限 (毫秒)。
+    #[arg(long, default_value_t = 10000)]
+    max_syllable_ms: usize,
+
+    /// overlapping-lines 规则允许的重叠时长 (毫秒)。
+    #[arg(long, default_value_t = 0)]
+    overlap_tolerance_ms: usize,
+}
+
+/// `diff` 子命令的参数。
+#[derive(Args, Debug)]
+struct DiffArgs {
+    /// 作为基准的歌词文件 (a)。
+    old_file: PathBuf,
+
+    /// 要对比的歌词文件 (b)。
+    new_file: PathBuf,
+
+    /// 不报告小于等于此值的时间偏差 (毫秒)，例如对比 ASS 与 QRC 时可设为 10 以忽略百分秒取整误差。
+    #[arg(long, default_value_t = 0)]
+    tolerance_ms: usize,
+}
+
+/// `textconv` 子命令的参数。
+#[derive(Args, Debug)]
+struct TextconvArgs {
+    /// 要输出的歌词文件。
+    input_file: PathBuf,
+}
+
+/// `sections` 子命令的参数。
+#[derive(Args, Debug)]
+struct SectionsArgs {
+    /// 要列出段落的歌词文件。
+    input_file: PathBuf,
+}
+
+/// `verify` 子命令的参数。
+#[derive(Args, Debug)]
+struct VerifyArgs {
+    /// 要校验的歌词文件。
+    input_file: PathBuf,
+
+    /// 只校验指定的目标格式，可重复指定 (默认校验除源格式外的所有格式)。
+    #[arg(long = "target", value_enum, value_name = "FORMAT")]
+    targets: Vec<LyricFormat>,
+
+    /// 写入 ASS 时的取整策略 (与转换时的 --rounding 相同)。
+    #[arg(long, value_enum, default_value_t = RoundingStrategy::Cumulative)]
+    rounding: RoundingStrategy,
+
+    /// 写入 ASS 时在 Effect 字段中嵌入精确毫秒时间 (与转换时的 --exact-ms 相同)。
+    #[arg(long)]
+    exact_ms: bool,
+
+    /// 写入 QRC/LYS 时停顿的处理方式 (与转换时的 --gap-mode 相同)。
+    #[arg(long, value_enum, default_value_t = GapMode::Drop)]
+    gap_mode: GapMode,
+
+    /// 写入 QRC/LYS 时写出段落标签 (与转换时的 --song-part-tags 相同)。
+    #[arg(long)]
+    song_part_tags: bool,
+}
+
+/// `import` 子命令的参数。
+#[derive(Args, Debug)]
+struct ImportArgs {
+    /// 要合并到的 ASS 文件。
+    ass_file: PathBuf,
+
+    /// 要导入的 LRC 文件，轨道由文件名判断 (<名称>.<语言代码>.lrc 为翻译，<名称>.roma.lrc 为罗马音)。
+    #[arg(required = true)]
+    lrc_files: Vec<PathBuf>,
+
+    /// 输出 ASS 文件路径 (不使用 --in-place 时必需)。
+    #[arg(short, long, required_unless_present = "in_place")]
+    output: Option<PathBuf>,
+
+    /// 直接覆盖输入的 ASS 文件。
+    #[arg(long, conflicts_with = "output")]
+    in_place: bool,
+
+    /// LRC 行与主歌词行开始时间允许的最大偏差 (毫秒)。
+    #[arg(long, default_value_t = 1000)]
+    tolerance_ms: usize,
+}
+
+/// `assemble` 子命令的参数。
+#[derive(Args, Debug)]
+struct AssembleArgs {
+    /// 提供逐字时间的主歌词文件 (QRC/LYS/ASS)。
+    main_file: PathBuf,
+
+    /// 翻译或罗马音 LRC 文件，轨道由文件名判断 (<名称>.<语言代码>.lrc 为翻译，<名称>.roma.lrc 为罗马音)。
+    lrc_files: Vec<PathBuf>,
+
+    /// 指定语言的翻译 LRC，格式为 LANG=FILE，可重复指定 (用于文件名不符合命名规则的 LRC)。
+    #[arg(long = "ts", value_name = "LANG=FILE")]
+    translations: Vec<String>,
+
+    /// 罗马音 LRC，可重复指定 (用于文件名不符合命名规则的 LRC)。
+    #[arg(long = "roma", value_name = "FILE")]
+    roma_files: Vec<PathBuf>,
+
+    /// 某个来源文件的时间偏移，格式为 FILE=MS (负值表示提前)，可重复指定。
+    #[arg(long = "offset", value_name = "FILE=MS", allow_hyphen_values = true)]
+    offsets: Vec<String>,
+
+    /// 输出 ASS 文件路径 (默认为主歌词文件名加 "_converted.ass")。
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// LRC 行与主歌词行开始时间允许的最大偏差 (毫秒)。
+    #[arg(long, default_value_t = 1000)]
+    tolerance_ms: usize,
+
+    /// 转换选项 (例如 --rounding、--exact-ms、--meta)，应用于组装后的歌词。
+    #[command(flatten)]
+    conversion: ConversionArgs,
 }
 
 // --- 程序主入口 ---
@@ -277,20 +980,59 @@ fn main() {
     // 使用 clap 解析命令行参数。
     let args = CliArgs::parse();
 
-    // 进入交互模式的逻辑
+    // 在处理任何文件之前加载配置文件
+    if let Err(e) = load_lyrics_config(args.config.as_deref(), args.include_comments, &args.layers, args.karaoke_syntax) {
+        log_error!("读取配置文件 {:?} 失败: {}", args.config.as_deref().unwrap_or(Path::new(DEFAULT_CONFIG_FILE_NAME)), e);
+        if args.command.is_some() {
+            std::process::exit(1);
+        }
+        wait_for_exit();
+        return;
+    }
+
+    // 子命令直接执行，并通过退出码报告结果 (不等待用户按键)
+    if let Some(command) = &args.command {
+        let failed = match command {
+            Command::Validate(validate_args) => run_validate_command(validate_args),
+            Command::Diff(diff_args) => run_diff_command(diff_args),
+            Command::Textconv(textconv_args) => run_textconv_command(textconv_args),
+            Command::Verify(verify_args) => run_verify_command(verify_args),
+            Command::Import(import_args) => run_import_command(import_args),
+            Command::Assemble(assemble_args) => run_assemble_command(assemble_args),
+            Command::Sections(sections_args) => run_sections_command(sections_args),
+        };
+        std::process::exit(if failed { 1 } else { 0 });
+    }
+
+    // 根据命令行参数构建转换选项
+    let options = match build_conversion_options(&args.conversion) {
+        Ok(options) => ConversionOptions {
+            extract_format: args.extract_format.unwrap_or_default(),
+            merged_lrc: args.merged_lrc,
+            merged_lrc_order: args.lrc_order.clone(),
+            ..options
+        },
+        Err(e) => {
+            log_error!("命令行参数无效: {}", e);
+            wait_for_exit();
+            return;
+        }
+    };
+
+    // 进入交互模式的逻辑 (交互模式同样使用命令行给出的转换选项，例如 --anchor、--rounding)
     // 1. 如果明确使用了 --interactive 标志
     // 2. 如果程序没有接收到任何参数 (通常是双击运行)
     if args.interactive {
-        interactive_mode();
+        interactive_mode(&options);
         return;
     }
     if std::env::args().len() <= 1 { // 检查参数数量是否小于等于1
-        interactive_mode();
+        interactive_mode(&options);
         return;
     }
 
-    // 提取 extract_lrc 标志的值，以便传递给后续函数
-    let should_extract_lrc = args.extract_lrc;
+    // 提取 extract_lrc 标志的值，以便传递给后续函数 (指定了 --extract-format 时也需要提取)
+    let should_extract_lrc = args.extract_lrc || args.extract_format.is_some() || args.merged_lrc;
 
     // 清理从 clap 获取的输入文件路径
     let cleaned_input_file = args.input_file.map(clean_path_buf_quotes);
@@ -313,12 +1055,12 @@ fn main() {
     match (args.direction, cleaned_output_file) {
         // 组合 1: 自动模式 (direction 和 output_file 都没有提供)
         (None, None) => {
-            run_automatic_mode_clap(&input_path);
+            run_automatic_mode_clap(&input_path, &options);
         }
 
         // 组合 2: 手动模式 (direction 和 output_file 都提供了)
         (Some(dir), Some(output)) => { // output 来自 cleaned_output_file
-             run_manual_mode_clap(&dir, &input_path, &output, should_extract_lrc);
+             run_manual_mode_clap(&dir, &input_path, &output, should_extract_lrc, &options);
         }
 
         // 组合 3: 无效或不完整的参数组合 (手动模式参数不匹配)
@@ -341,68 +1083,86 @@ fn main() {
 
 // --- 模式处理函数 (由 main 调用) ---
 
+/// 根据命令行参数构建转换选项。
+fn build_conversion_options(args: &ConversionArgs) -> Result<ConversionOptions, ConversionError> {
+    let mut options = ConversionOptions {
+        rounding: args.rounding,
+        fix_timing: args.fix_timing,
+        exact_ms: args.exact_ms,
+        gap_mode: args.gap_mode,
+        strip_metadata: args.strip_meta,
+        background_mode: args.bg_mode,
+        inline_background: args.inline_bg,
+        song_part_tags: args.song_part_tags,
+        infer_duet: args.infer_duet,
+        furigana: args.furigana,
+        ..ConversionOptions::default()
+    };
+
+    // 解析 --meta key=value
+    for item in &args.metadata {
+        let Some((key, value)) = item.split_once('=').filter(|(key, _)| !key.trim().is_empty()) else {
+            return Err(ConversionError::InvalidFormat(format!("无效的元数据 '{}'，应为 key=value", item)));
+        };
+        options.metadata_overrides.push((key.trim().to_string(), value.to_string()));
+    }
+
+    // 收集时间扭曲锚点 (锚点文件 + 命令行)
+    let mut anchors = Vec::new();
+    if let Some(anchor_file) = &args.anchor_file {
+        anchors.extend(TimeWarp::from_file(&clean_path_buf_quotes(anchor_file.clone()))?);
+    }
+    for anchor_text in &args.anchors {
+        anchors.push(TimeWarp::parse_anchor(anchor_text)?);
+    }
+    if !anchors.is_empty() {
+        options.time_warp = Some(TimeWarp::new(anchors)?);
+    }
+
+    Ok(options)
+}
+
 /// 执行手动转换模式。
 ///
 /// # Arguments
 /// * `direction` - 用户指定的转换方向字符串。
 /// * `input_path` - 输入文件的路径。
 /// * `output_path` - 输出文件的路径。
-fn run_manual_mode_clap(direction: &str, input_path: &Path, output_path: &Path, extract_lrc: bool) {
+/// * `extract_lrc` - 输入为 ASS 时是否额外提取翻译和罗马音 LRC。
+/// * `options` - 转换选项。
+fn run_manual_mode_clap(direction: &str, input_path: &Path, output_path: &Path, extract_lrc: bool, options: &ConversionOptions) {
     if !input_path.exists() {
         log_error!("{}", FILE_NOT_FOUND_ERROR);
         wait_for_exit();
         return;
     }
 
-    // 定义转换函数的类型签名别名，方便使用。
     let lower_dir = direction.to_lowercase(); // 将方向字符串转为小写，进行不区分大小写的匹配。
-    // 定义转换函数的包装器，以统一返回类型 (Result<bool, ConversionError>)
-    // 这里的 bool 代表 warning_occurred
-    let qrc2ass_wrapper = |i: &Path, o: &Path| convert_qrc_to_ass(i, o); // convert_qrc_to_ass 已经是 Result<bool, ...>
-    let lys2ass_wrapper = |i: &Path, o: &Path| convert_lys_to_ass(i, o); // convert_lys_to_ass 已经是 Result<bool, ...>
-    // convert_ass_to_qrc 和 convert_ass_to_lys 已经是 Result<bool, ...>
 
     // 根据方向字符串选择对应的转换函数。
     let conversion_function_to_execute: Option<ConversionFnSig> = match lower_dir.as_str() {
         "ass2qrc" | "2q" => Some(convert_ass_to_qrc),
-        "qrc2ass" | "2a" => Some(qrc2ass_wrapper),
+        "qrc2ass" | "2a" => Some(convert_qrc_to_ass),
         "ass2lys" | "2l" => Some(convert_ass_to_lys),
-        "lys2ass" | "l2a" => Some(lys2ass_wrapper),
+        "lys2ass" | "l2a" => Some(convert_lys_to_ass),
         _ => None,
     };
 
-    // 初始化一个变量来跟踪操作是否需要暂停
-    let mut operation_requires_pause = false;
+    // 跟踪操作是否需要暂停
+    let mut operation_requires_pause;
 
     match conversion_function_to_execute {
         Some(selected_action) => {
             // 首先执行主转换
             // execute_conversion 返回 true 如果主转换出错或有需要暂停的警告
-            operation_requires_pause = execute_conversion(selected_action, input_path, output_path);
+            operation_requires_pause = execute_conversion(selected_action, input_path, output_path, options);
 
             // 检查是否需要提取翻译 (仅当输入是 ASS 时)
             let input_is_ass = input_path.extension()
                 .is_some_and(|ext| ext.to_string_lossy().eq_ignore_ascii_case("ass"));
 
-            if input_is_ass && extract_lrc { // extract_lrc 是命令行参数
-                match extract_translations_to_lrc(input_path) {
-                    Ok(warned) => {
-                        if warned { operation_requires_pause = true; } // 如果提取操作本身有警告，也需要暂停
-                    }
-                    Err(e) => {
-                        log_error!("提取 LRC 翻译时出错: {}", e);
-                        operation_requires_pause = true; // 提取出错需要暂停
-                    }
-                }
-                match extract_roma_to_lrc(input_path) {
-                    Ok(warned) => {
-                        if warned { operation_requires_pause = true; } // 如果提取操作本身有警告，也需要暂停
-                    }
-                    Err(e) => {
-                        log_error!("提取 LRC 罗马音时出错: {}", e);
-                        operation_requires_pause = true; // 提取出错需要等待
-                    }
-                }
+            if input_is_ass && extract_lrc && run_auxiliary_extraction(input_path, options) { // extract_lrc 是命令行参数
+                operation_requires_pause = true; // 如果提取操作本身出错或有警告，也需要暂停
             }
         }
         None => {
@@ -422,7 +1182,8 @@ fn run_manual_mode_clap(direction: &str, input_path: &Path, output_path: &Path,
 ///
 /// # Arguments
 /// * `input_path` - 输入文件的路径。
-fn run_automatic_mode_clap(input_path: &Path) {
+/// * `options` - 转换选项。
+fn run_automatic_mode_clap(input_path: &Path, options: &ConversionOptions) {
     // 检查输入文件是否存在。
     if !input_path.exists() {
         log_error!("{}", FILE_NOT_FOUND_ERROR);
@@ -439,19 +1200,11 @@ fn run_automatic_mode_clap(input_path: &Path) {
 
     let mut needs_wait = false; // 标记本次操作后是否需要等待退出
 
-    let qrc2ass_wrapper = |i: &Path, o: &Path| -> Result<bool, ConversionError> {
-    convert_qrc_to_ass(i, o).map(|_| false)};
-
-    let lys2ass_wrapper = |i: &Path, o: &Path| -> Result<bool, ConversionError> {
-    convert_lys_to_ass(i, o).map(|_| false)};
-
-
-
     // 根据文件扩展名决定执行哪个转换。
     match extension.as_str() {
         "lys" => {
             let output_path = auto_output_path(input_path, ASS_EXTENSION);
-            if execute_conversion(lys2ass_wrapper, input_path, &output_path) {
+            if execute_conversion(convert_lys_to_ass, input_path, &output_path, options) {
                  needs_wait = true;
             }
 
@@ -461,11 +1214,11 @@ fn run_automatic_mode_clap(input_path: &Path) {
             let main_conversion_result = match check_ass_has_special_names(input_path) {
                 Ok(true) => {
                     let output_path = auto_output_path(input_path, LYRICIFY_EXTENSION);
-                    execute_conversion(convert_ass_to_lys, input_path, &output_path)
+                    execute_conversion(convert_ass_to_lys, input_path, &output_path, options)
                 }
                 Ok(false) => {
                     let output_path = auto_output_path(input_path, QRC_EXTENSION);
-                    execute_conversion(convert_ass_to_qrc, input_path, &output_path)
+                    execute_conversion(convert_ass_to_qrc, input_path, &output_path, options)
                 }
                 Err(e) => {
                     log_error!("检查 ASS Name 字段时出错: {}", e);
@@ -474,32 +1227,13 @@ fn run_automatic_mode_clap(input_path: &Path) {
             };
             if main_conversion_result { needs_wait = true; } // 如果主转换出错/警告，标记等待
 
-            // --- 自动模式下，无条件尝试提取翻译 ---
-            match extract_translations_to_lrc(input_path) {
-                Ok(warned) => {
-                    if warned { needs_wait = true; }
-                }
-                Err(e) => {
-                    log_error!("提取 LRC 翻译时出错: {}", e);
-                    needs_wait = true;
-                }
-            }
-
-            // --- 自动模式下，无条件尝试提取罗马音 ---
-            match extract_roma_to_lrc(input_path) {
-                Ok(warned) => {
-                    if warned { needs_wait = true; }
-                }
-                Err(e) => {
-                    log_error!("提取 LRC 罗马音时出错: {}", e);
-                    needs_wait = true;
-                }
-            }
+            // --- 自动模式下，无条件尝试提取翻译和罗马音 ---
+            if run_auxiliary_extraction(input_path, options) { needs_wait = true; }
 
         }
         "qrc" => {
             let output_path = auto_output_path(input_path, ASS_EXTENSION);
-            if execute_conversion(qrc2ass_wrapper, input_path, &output_path) {
+            if execute_conversion(convert_qrc_to_ass, input_path, &output_path, options) {
                  needs_wait = true;
             }
 
@@ -517,7 +1251,10 @@ fn run_automatic_mode_clap(input_path: &Path) {
 }
 
 /// 运行交互式命令行界面，引导用户进行转换。
-fn interactive_mode() {
+///
+/// # Arguments
+/// * `options` - 由命令行参数构建的转换选项 (与 `--interactive` 一起给出的选项对每次转换都生效)。
+fn interactive_mode(options: &ConversionOptions) {
     log_info!("直接将文件拖到程序图标上可自动转换");
     // 无限循环，提供持续的转换服务，直到用户手动关闭窗口。
     loop {
@@ -626,16 +1363,16 @@ fn interactive_mode() {
         let _needs_wait = match (source_choice.as_str(), target_choice.as_str()) {
             (ASS_FORMAT_CHOICE, QRC_FORMAT_CHOICE) =>
                 // 调用 execute_conversion，传入转换函数 `convert_ass_to_qrc`
-                execute_conversion(convert_ass_to_qrc, &input_path, &output_path),
+                execute_conversion(convert_ass_to_qrc, &input_path, &output_path, options),
             (ASS_FORMAT_CHOICE, LYS_FORMAT_CHOICE) =>
                 // 调用 execute_conversion，传入转换函数 `convert_ass_to_lys`
-                execute_conversion(convert_ass_to_lys, &input_path, &output_path),
+                execute_conversion(convert_ass_to_lys, &input_path, &output_path, options),
             (QRC_FORMAT_CHOICE, ASS_FORMAT_CHOICE) =>
                 // 调用 execute_conversion，传入转换函数 `convert_qrc_to_ass`
-                execute_conversion(convert_qrc_to_ass, &input_path, &output_path),
+                execute_conversion(convert_qrc_to_ass, &input_path, &output_path, options),
             (LYS_FORMAT_CHOICE, ASS_FORMAT_CHOICE) =>
                 // 调用 execute_conversion，传入转换函数 `convert_lys_to_ass`
-                execute_conversion(convert_lys_to_ass, &input_path, &output_path),
+                execute_conversion(convert_lys_to_ass, &input_path, &output_path, options),
             _ => { // 理论上不会执行到这里，因为前面已经校验过选项组合
                 log_error!("内部错误：无效的转换组合");
                 false // 标记不需要等待
@@ -652,1424 +1389,4725 @@ fn interactive_mode() {
 
 // --- 核心转换函数 ---
 
-/// 将 ASS 文件转换为 QRC 文件 (单遍扫描)。
-fn convert_ass_to_qrc(ass_path: &Path, qrc_path: &Path) -> Result<bool, ConversionError> {
-    // 打开输入文件并获取元数据 (用于进度条总大小)
-    let file = File::open(ass_path)?;
-    let metadata = file.metadata()?;
-    let total_bytes = metadata.len() as usize;
-    let mut processed_bytes: usize = 0; // 跟踪已处理字节数
-    let mut warning_occurred = false; // 标记是否有时间不一致警告
+/// 将 ASS 文件转换为 QRC 文件。
+fn convert_ass_to_qrc(ass_path: &Path, qrc_path: &Path, options: &ConversionOptions) -> Result<bool, ConversionError> {
+    let (mut lyrics, mut warning_occurred) = read_ass_file(ass_path)?;
+    // 指定了 --fix-timing 时不一致的行会被修复 (并逐行列出)，不再作为警告
+    if options.fix_timing.is_none() {
+        warning_occurred |= !check_lyrics_time_consistency(&lyrics);
+    }
+    if options.infer_duet {
+        infer_duet_singers(&mut lyrics);
+    }
+    warning_occurred |= apply_conversion_options(&mut lyrics, options);
+    warning_occurred |= merge_background_lines(&mut lyrics, options.background_mode);
+    write_qrc_file(&lyrics, qrc_path, options)?;
+    log_success!("{}", ASS_TO_QRC_COMPLETE); // 打印成功信息
+    Ok(warning_occurred) // 返回包含警告状态的 Ok
+}
 
-    // 创建带缓冲的读取器和写入器以提高效率
-    let reader = BufReader::new(file);
-    let mut writer = BufWriter::new(File::create(qrc_path)?);
 
-    let mut metadata_lines: Vec<String> = Vec::new(); // 存储解析出的元数据行 [ti:...]
-    let mut output_qrc_lines: Vec<String> = Vec::new(); // 存储转换后的 QRC 内容行
+/// 将 QRC 文件转换为 ASS 文件。
+fn convert_qrc_to_ass(qrc_path: &Path, ass_path: &Path, options: &ConversionOptions) -> Result<bool, ConversionError> {
+    let (mut lyrics, mut warning_occurred) = read_qrc_file(qrc_path)?;
+    split_inline_background(&mut lyrics, options.inline_background);
+    warning_occurred |= apply_conversion_options(&mut lyrics, options);
+    write_ass_file(&lyrics, ass_path, options)?;
+    log_success!("{}", QRC_TO_ASS_COMPLETE);
+    Ok(warning_occurred)
+}
 
-    let mut after_format = false; // 标记是否已找到 Events 段的 Format 行
-    let mut line_number = 0; // 文件行号计数器
 
-    // 逐行读取输入文件
-    for line_result in reader.lines() {
-        line_number += 1;
-        let line = line_result?; // 处理 IO 错误
-        // 估算已处理字节数 (用于进度条)
-        let line_bytes = line.len() + if cfg!(windows) { 2 } else { 1 }; // 加上换行符字节
-        processed_bytes += line_bytes;
+/// 将 ASS 文件转换为 Lyricify Syllable (.lys) 文件。
+/// LYS 的 '背' (背景) 属性依赖于前一行的 Name 字段，因此需要先完整读取整个文件再写入。
+fn convert_ass_to_lys(ass_path: &Path, lys_path: &Path, options: &ConversionOptions) -> Result<bool, ConversionError> {
+    let (mut lyrics, mut warning_occurred) = read_ass_file(ass_path)?;
+    // 指定了 --fix-timing 时不一致的行会被修复 (并逐行列出)，不再作为警告
+    if options.fix_timing.is_none() {
+        warning_occurred |= !check_lyrics_time_consistency(&lyrics);
+    }
+    if options.infer_duet {
+        infer_duet_singers(&mut lyrics);
+    }
+    warning_occurred |= apply_conversion_options(&mut lyrics, options);
+    warning_occurred |= write_lys_file(&lyrics, lys_path, options)?;
+    log_success!("{}", ASS_TO_LYS_COMPLETE);
+    Ok(warning_occurred)
+}
 
-        // --- 核心处理逻辑 ---
 
-        // 必须先找到 Format 行才能开始处理 Dialogue 和 Comment
-        if !after_format {
-            if line.trim_start().starts_with("Format: Layer, Start, End, Style, Name,") {
-                after_format = true; // 找到 Format 行
-            }
-            display_progress_bar(processed_bytes.min(total_bytes), total_bytes); // 更新进度条
-            continue; // 跳过 Format 行之前的所有行 (包括 Format 行本身)
-        }
+/// 将 Lyricify Syllable (.lys) 文件转换为 ASS 文件。
+fn convert_lys_to_ass(lys_path: &Path, ass_path: &Path, options: &ConversionOptions) -> Result<bool, ConversionError> {
+    let (mut lyrics, mut warning_occurred) = read_lys_file(lys_path)?;
+    warning_occurred |= apply_conversion_options(&mut lyrics, options);
+    write_ass_file(&lyrics, ass_path, options)?;
+    log_success!("{}", LYS_TO_ASS_COMPLETE);
+    Ok(warning_occurred)
+}
 
-        // --- 在 Format 行之后 ---
 
-        // 检查是否是元数据 Comment 行
-        if let Some(caps) = META_COMMENT_REGEX.captures(&line) {
-            if let Some(text) = caps.get(1) { // 获取捕获的元数据文本
-                if let Some(formatted_meta) = parse_ass_metadata_text(text.as_str()) {
-                    metadata_lines.push(formatted_meta); // 收集元数据
-                }
-            }
-            display_progress_bar(processed_bytes.min(total_bytes), total_bytes); // 更新进度条
-            continue; // 跳到下一行
+/// 在读取之后、写入之前，对歌词模型应用命令行指定的附加选项。
+/// 返回 `true` 表示处理过程中产生了警告。
+fn apply_conversion_options(lyrics: &mut Lyrics, options: &ConversionOptions) -> bool {
+    let mut warning_occurred = false;
+    if options.furigana == FuriganaMode::Strip {
+        strip_furigana(lyrics);
+    }
+    // 先在源时间轴上修复时长不一致，再进行时间扭曲
+    if let Some(strategy) = options.fix_timing {
+        match fix_line_timing(lyrics, strategy) {
+            0 => log_info!("--fix-timing: 没有需要修复的行。"),
+            fixed_count => log_info!("--fix-timing: 共修复 {} 行时长不一致。", fixed_count),
         }
+    }
+    if let Some(warp) = &options.time_warp {
+        warning_occurred |= apply_time_warp(lyrics, warp);
+    }
+    apply_metadata_overrides(lyrics, options);
+    warning_occurred
+}
 
-        // 检查是否是 Dialogue 行
-        if line.starts_with("Dialogue:") {
-            // 调用辅助函数处理 Dialogue -> QRC 转换
-             match process_dialogue_for_qrc(&line, line_number, &mut warning_occurred) {
-                 Ok(Some(qrc_line)) => output_qrc_lines.push(qrc_line), // 收集 QRC 行
-                 Ok(None) => {}, // 解析器认为无效或跳过，不处理
-                 Err(e) => {
-                     // 记录警告并继续处理下一行
-                     log_warn!("处理第 {} 行 Dialogue 时出错: {}", line_number, e);
-                     // 注意：这里不返回 Err，允许程序继续处理文件的其余部分
-                 }
-             }
-        }
-        // 忽略 Format 行之后的其他非 Dialogue、非元数据 Comment 行
 
-        display_progress_bar(processed_bytes.min(total_bytes), total_bytes); // 更新进度条
+/// 应用 --strip-meta 和 --meta 指定的元数据修改。
+/// 同一个键在 --meta 中出现多次时，第一次替换原有的值，之后追加。
+fn apply_metadata_overrides(lyrics: &mut Lyrics, options: &ConversionOptions) {
+    if options.strip_metadata {
+        lyrics.clear_metadata();
+    }
+    let mut overridden_keys: Vec<&str> = Vec::new();
+    for (key, value) in &options.metadata_overrides {
+        let canonical_key = metadata_key_for_tag(key);
+        if overridden_keys.contains(&canonical_key) {
+            for value in split_metadata_values(canonical_key, value) {
+                lyrics.add_metadata(canonical_key, value);
+            }
+        } else {
+            lyrics.set_metadata(canonical_key, value);
+            overridden_keys.push(canonical_key);
+        }
     }
+}
 
-    // --- 读取完成，开始写入输出文件 ---
 
-    // 1. 写入元数据 (如果存在)
-    for meta_line in &metadata_lines {
-        writeln!(writer, "{}", meta_line)?;
+/// 按 `mode` 处理背景人声行 (见 `classify_singer`)，用于写入没有背景人声概念的格式:
+/// `Merge` 时把背景人声行的音节以 "(" 和 ")" 包裹后追加到前一个主歌词行，并扩展该行的时间范围；`Drop` 时删除背景人声行。
+/// 翻译和罗马音行不受影响。
+///
+/// # Returns
+/// * `bool` - 是否有之前没有主歌词行、无法合并的背景人声行 (这些行按原样保留)。
+fn merge_background_lines(lyrics: &mut Lyrics, mode: BackgroundMode) -> bool {
+    if mode == BackgroundMode::Separate {
+        return false;
     }
+    let mut warning_occurred = false;
+    let mut merged: Vec<LyricLine> = Vec::with_capacity(lyrics.lines.len());
+    let mut last_main: Option<usize> = None; // 上一个主歌词 (非背景人声) 行在 merged 中的下标
 
-    // 2. 写入 QRC 内容
-    for qrc_line in &output_qrc_lines {
-        writeln!(writer, "{}", qrc_line)?;
+    for line in std::mem::take(&mut lyrics.lines) {
+        if is_auxiliary_style(&line.style) {
+            merged.push(line);
+            continue;
+        }
+        if !classify_line_singer(&line).is_some_and(SingerInfo::is_background) {
+            last_main = Some(merged.len());
+            merged.push(line);
+            continue;
+        }
+        match (mode, last_main) {
+            (BackgroundMode::Drop, _) => {}
+            (_, Some(index)) => {
+                let main = &mut merged[index];
+                let mut syllables: Vec<Syllable> = line.syllables.into_iter().filter(|s| !s.is_gap && !s.text.is_empty()).collect();
+                if let Some(first) = syllables.first_mut() {
+                    // 前一个音节以拉丁字母或数字结尾时在括号前加空格 (中日文歌词不加)
+                    let needs_space = main.syllables.iter().rev().find(|s| !s.is_gap)
+                        .is_some_and(|s| s.text.ends_with(|c: char| c.is_ascii_alphanumeric()));
+                    first.text.insert_str(0, if needs_space { " (" } else { "(" });
+                }
+                if let Some(last) = syllables.last_mut() {
+                    last.text.push(')');
+                }
+                main.syllables.extend(syllables);
+                main.start_ms = main.start_ms.min(line.start_ms);
+                main.end_ms = main.end_ms.max(line.end_ms);
+            }
+            (_, None) => {
+                log_warn!("第 {} 行背景人声 '{}' 之前没有主歌词行，无法合并，将作为独立的行写出。", line.line_number, line.text());
+                warning_occurred = true;
+                merged.push(line);
+            }
+        }
     }
 
-    // --- 写入完成，收尾 ---
-    display_progress_bar(total_bytes, total_bytes); // 确保进度条显示 100%
-    println!(); // 进度条后换行
-
-    writer.flush()?; // 确保所有缓冲内容写入文件
-    log_success!("{}", ASS_TO_QRC_COMPLETE); // 打印成功信息
-
-    Ok(warning_occurred) // 返回包含警告状态的 Ok
+    lyrics.lines = merged;
+    warning_occurred
 }
 
 
-/// 将 QRC 文件转换为 ASS 文件 (单遍扫描)。
-fn convert_qrc_to_ass(qrc_path: &Path, ass_path: &Path) -> Result<bool, ConversionError> {
-    let file = File::open(qrc_path)?;
-    let metadata = file.metadata()?;
-    let total_bytes = metadata.len() as usize;
-    let mut processed_bytes: usize = 0; // 跟踪已处理字节
-
-    let reader = BufReader::new(file);
-    let mut writer = BufWriter::new(File::create(ass_path)?);
+/// `merge_background_lines` 的逆操作，用于读取 QRC 等把背景人声写在行内的格式:
+/// 以 "(" 开头、到以 ")" 结尾为止的连续音节 (也接受全角括号) 被视为背景人声，
+/// `Split` 时去掉括号后拆分为紧跟在该行之后的背景人声行 (Name 按演唱者规则取背景人声的名称，默认为 "背")，`Drop` 时删除。
+fn split_inline_background(lyrics: &mut Lyrics, mode: InlineBackgroundMode) {
+    if mode == InlineBackgroundMode::Keep {
+        return;
+    }
+    let background_name = singer_name_for(SingerInfo { side: None, background: Some(true) });
+    let mut result: Vec<LyricLine> = Vec::with_capacity(lyrics.lines.len());
 
-    // 写入 ASS 文件头和样式 (使用辅助函数)
-    write_ass_header(&mut writer)?;
+    for mut line in std::mem::take(&mut lyrics.lines) {
+        if line.untimed || is_auxiliary_style(&line.style) {
+            result.push(line);
+            continue;
+        }
 
-    // 逐行读取 QRC 文件
-    for line_result in reader.lines() {
-        let line = line_result?;
-        let line_bytes = line.len() + if cfg!(windows) { 2 } else { 1 };
-        processed_bytes += line_bytes;
-
-        // 尝试匹配行时间戳 [开始ms,持续ms]
-        // 跳过元数据行或非 QRC 时间戳开头的行
-        if let Some(ts_caps) = QRC_TIMESTAMP_REGEX.captures(&line) {
-             // 解析行开始和持续时间
-            let header_start_ms: usize = ts_caps[1].parse()?;
-            let header_duration_ms: usize = ts_caps[2].parse()?;
-            let header_end_ms = header_start_ms.saturating_add(header_duration_ms); // 计算行结束时间
-
-            // 转换为 ASS 时间格式
-            let start_time_ass = milliseconds_to_time(header_start_ms);
-            let end_time_ass = milliseconds_to_time(header_end_ms);
-
-            // --- 重建 ASS 文本和 K 标签 ---
-            let mut ass_text_builder = String::new(); // 用于构建带 K 标签的文本
-            let mut last_word_end_ms = header_start_ms; // 跟踪上一个单词的结束时间, 初始化为行开始时间
-
-            // 获取时间戳之后的内容部分
-            let content_part = &line[ts_caps.get(0).unwrap().end()..];
-
-            // 提取所有单词时间戳及其在字符串中的位置
-            let mut time_tags: Vec<(usize, usize, usize, usize)> = Vec::new(); // (start_pos, end_pos, start_ms, duration_ms)
-            for cap in WORD_TIME_TAG_REGEX.captures_iter(content_part) {
-                let start_pos = cap.get(0).unwrap().start(); // 时间戳在字符串中的开始位置
-                let end_pos = cap.get(0).unwrap().end();     // 时间戳在字符串中的结束位置
-                // 使用命名捕获组解析单词的开始和持续时间
-                let word_start_ms: usize = cap["start"].parse()?;
-                let word_duration_ms: usize = cap["duration"].parse()?;
-
-                // 过滤掉无效时间戳 (例如时长为0)
-                 if word_duration_ms == 0 { continue; }
-
-                time_tags.push((start_pos, end_pos, word_start_ms, word_duration_ms));
-            }
-
-            // 按时间戳在字符串中的位置排序，以正确提取文本片段
-             time_tags.sort_by_key(|k| k.0);
-
-            // 遍历时间戳，重建文本和 K 标签
-            let mut current_char_index = 0; // 跟踪 content_part 的处理位置
-            for (tag_start_pos, tag_end_pos, word_start_ms, word_duration_ms) in time_tags {
-                 // 提取当前时间戳之前的文本片段
-                 let text_segment = &content_part[current_char_index..tag_start_pos];
-
-                 // 计算与上个词尾的时间差（用于插入停顿的 K 标签）
-                 if word_start_ms > last_word_end_ms {
-                     let gap_ms = word_start_ms - last_word_end_ms;
-                     // 向上取整计算 K 值 (厘秒)
-                     let gap_k_value = (gap_ms + K_TAG_MULTIPLIER / 2) / K_TAG_MULTIPLIER;
-                     if gap_k_value > 0 {
-                         ass_text_builder.push_str(&format!("{{\\k{}}}", gap_k_value)); // 插入停顿 K 标签
-                     }
-                 }
-
-                 // 计算当前单词的 K 值 (向上取整)
-                 let word_k_value = (word_duration_ms + K_TAG_MULTIPLIER / 2) / K_TAG_MULTIPLIER;
-
-                 // 添加 K 标签和文本段 (只有 K > 0 时才添加标签)
-                 if word_k_value > 0 {
-                     // 如果文本段为空，只添加 K 标签（例如 {\k50}）
-                     // 如果文本段不为空，添加 K 标签和文本（例如 {\k50}歌词）
-                     ass_text_builder.push_str(&format!("{{\\k{}}}{}", word_k_value, text_segment));
-                 } else if !text_segment.is_empty() {
-                     // 如果 K=0 但有文本，只添加文本 (避免 {\k0} )
-                     ass_text_builder.push_str(text_segment);
-                 }
-
-                 // 更新下一个文本段的起始索引和上个词的结束时间
-                 current_char_index = tag_end_pos;
-                 last_word_end_ms = word_start_ms.saturating_add(word_duration_ms);
-            }
-
-             // 处理最后一个时间戳到行尾的内容
-             let remaining_text = &content_part[current_char_index..];
-             // 检查是否有剩余文本，或者是否有时间间隙
-             if !remaining_text.is_empty() { // 有剩余文本
-                 if header_end_ms > last_word_end_ms { // 且行未结束
-                    let gap_ms = header_end_ms - last_word_end_ms;
-                    let gap_k_value = (gap_ms + K_TAG_MULTIPLIER / 2) / K_TAG_MULTIPLIER;
-                    if gap_k_value > 0 {
-                        // 给剩余文本加上 K 标签
-                        ass_text_builder.push_str(&format!("{{\\k{}}}{}", gap_k_value, remaining_text));
-                    } else {
-                        // K=0, 直接加文本
-                        ass_text_builder.push_str(remaining_text);
-                    }
-                 } else {
-                     // 行已结束，直接追加剩余文本
-                     ass_text_builder.push_str(remaining_text);
-                 }
-             } else if header_end_ms > last_word_end_ms { // 没有剩余文本，但行未结束 (纯粹的时间间隙)
-                let gap_ms = header_end_ms - last_word_end_ms;
-                let gap_k_value = (gap_ms + K_TAG_MULTIPLIER / 2) / K_TAG_MULTIPLIER;
-                if gap_k_value > 0 {
-                    // 只插入 K 标签表示停顿
-                    ass_text_builder.push_str(&format!("{{\\k{}}}", gap_k_value));
-                }
-             }
+        // 把音节分为主歌词音节和括号内的背景人声音节段
+        let syllables = std::mem::take(&mut line.syllables);
+        let mut main_syllables: Vec<Syllable> = Vec::new();
+        let mut runs: Vec<Vec<Syllable>> = Vec::new();
+        let mut index = 0;
+        while index < syllables.len() {
+            let opens = syllables[index].text.trim_start().starts_with(['(', '（']);
+            let close = (index..syllables.len()).find(|&i| syllables[i].text.trim_end().ends_with([')', '）']));
+            if let (true, Some(close)) = (opens, close) {
+                let mut run = syllables[index..=close].to_vec();
+                let first = &mut run[0];
+                first.text = first.text.trim_start().chars().skip(1).collect();
+                let last = run.last_mut().unwrap();
+                let trimmed = last.text.trim_end();
+                last.text = trimmed[..trimmed.len() - trimmed.chars().next_back().map_or(0, char::len_utf8)].to_string();
+                run.retain(|syllable| syllable.is_gap || !syllable.text.is_empty());
+                runs.push(run);
+                index = close + 1;
+            } else {
+                main_syllables.push(syllables[index].clone());
+                index += 1;
+            }
+        }
 
-            // 移除可能产生的 {\k0} 标签 (理论上应该不会产生，但作为保险)
-            let final_ass_text = ass_text_builder.replace("{\\k0}", "");
+        if runs.is_empty() {
+            line.syllables = syllables;
+            result.push(line);
+            continue;
+        }
 
-            // 如果最终文本不为空，则写入 ASS Dialogue 行
-            if !final_ass_text.is_empty() {
-                writeln!(
-                    writer,
-                    // 使用默认样式 "Default", Name 字段留空
-                    "Dialogue: 0,{},{},Default,,0,0,0,,{}",
-                    start_time_ass,
-                    end_time_ass,
-                    final_ass_text
-                )?;
+        // 主歌词行的时间范围按剩余音节重新计算；整行都在括号内时不保留主歌词行
+        if let (Some(start_ms), Some(end_ms)) = (
+            main_syllables.iter().filter(|s| !s.is_gap).map(|s| s.start_ms).min(),
+            main_syllables.iter().filter(|s| !s.is_gap).map(Syllable::end_ms).max(),
+        ) {
+            result.push(LyricLine { start_ms, end_ms, syllables: main_syllables, ..line.clone() });
+        }
+        if mode == InlineBackgroundMode::Split {
+            for run in runs {
+                let (Some(start_ms), Some(end_ms)) = (run.iter().map(|s| s.start_ms).min(), run.iter().map(Syllable::end_ms).max()) else {
+                    continue;
+                };
+                result.push(LyricLine { start_ms, end_ms, name: background_name.clone(), syllables: run, ..line.clone() });
             }
-        } // end if let Some(ts_caps)
+        }
+    }
 
-        // 更新进度条
-        display_progress_bar(processed_bytes.min(total_bytes), total_bytes);
-    } // end for line_result
+    lyrics.lines = result;
+}
 
-    // --- 收尾 ---
-    display_progress_bar(total_bytes, total_bytes); // 确保进度条 100%
-    println!(); // 换行
 
-    writer.flush()?;
-    log_success!("{}", QRC_TO_ASS_COMPLETE); // 打印成功信息
+/// 从 ASS Name 中取出段落标签 (itunes:song-part=...)，返回 (剩余的 Name, 段落)。
+/// 没有段落标签时 Name 原样返回。
+fn split_song_part_from_name(name: &str) -> (String, Option<String>) {
+    let mut song_part = None;
+    let mut rest: Vec<&str> = Vec::new();
+    for token in name.split_whitespace() {
+        match token.strip_prefix(SONG_PART_NAME_PREFIX) {
+            Some(part) if song_part.is_none() && !part.is_empty() => song_part = Some(part.to_string()),
+            _ => rest.push(token),
+        }
+    }
+    match song_part {
+        Some(part) => (rest.join(" "), Some(part)),
+        None => (name.to_string(), None),
+    }
+}
 
-    Ok(false)
+/// 识别 QRC/LYS/LRC 中的段落标签行 `[song-part:段落名]`。
+/// 返回 `Some(None)` 表示标签值为空 (之后的行没有段落)，不是段落标签时返回 `None`。
+fn parse_song_part_tag(line: &str) -> Option<Option<String>> {
+    let caps = LRC_METADATA_TAG_REGEX.captures(line.trim())?;
+    if !caps[1].eq_ignore_ascii_case(SONG_PART_TAG) {
+        return None;
+    }
+    let part = caps[2].trim();
+    Some((!part.is_empty()).then(|| part.to_string()))
+}
+
+/// 行的段落与上一个写出的段落不同时，写出段落标签行 `[song-part:段落名]` (段落结束时写出空值)。
+fn write_song_part_tag<'a>(writer: &mut impl Write, current_part: &mut Option<&'a str>, line: &'a LyricLine) -> io::Result<()> {
+    let part = line.song_part.as_deref();
+    if part != *current_part {
+        writeln!(writer, "[{}:{}]", SONG_PART_TAG, part.unwrap_or(""))?;
+        *current_part = part;
+    }
+    Ok(())
+}
+
+
+// --- 歌词模型读取器 ---
+
+/// 根据文件扩展名 (.ass/.qrc/.lys) 选择读取器，将歌词文件读取为歌词模型。
+///
+/// # Returns
+/// * `Ok((Lyrics, bool, LyricFormat))` - 解析出的歌词、是否发生了警告以及识别出的输入格式。
+fn read_lyrics_file(path: &Path) -> Result<(Lyrics, bool, LyricFormat), ConversionError> {
+    let format = LyricFormat::from_path(path).ok_or_else(|| {
+        ConversionError::InvalidFormat(format!("无法根据文件后缀识别歌词格式: {:?}", path))
+    })?;
+    let (lyrics, warning_occurred) = match format {
+        LyricFormat::Ass => read_ass_file(path)?,
+        LyricFormat::Qrc => read_qrc_file(path)?,
+        LyricFormat::Lys => read_lys_file(path)?,
+    };
+    Ok((lyrics, warning_occurred, format))
 }
 
 
-/// 将 ASS 文件转换为 Lyricify Syllable (.lys) 文件 (两遍扫描)。
-/// 使用两遍扫描是为了正确处理 LYS 的 '背' (背景) 属性，该属性依赖于前一行的 Name 字段。
-fn convert_ass_to_lys(ass_path: &Path, lys_path: &Path) -> Result<bool, ConversionError> {
-    let mut metadata_lines: Vec<String> = Vec::new(); // 存储元数据行
-    let mut parsed_dialogues: Vec<ParsedDialogue> = Vec::new(); // 存储第一遍解析的 Dialogue 数据
-    let mut warning_occurred = false; // 时间不一致警告标志
-    let mut line_counter: usize = 0; // 文件行号计数器
-    let mut warning_occurred_overall = false; // 用于累积所有警告
+/// 读取 ASS 文件并解析为歌词模型，同时收集 `meta` Comment 行中的元数据。
+///
+/// # Returns
+/// * `Ok((Lyrics, bool))` - 解析出的歌词和是否发生了警告。
+fn read_ass_file(ass_path: &Path) -> Result<(Lyrics, bool), ConversionError> {
+    // 打开输入文件并获取元数据 (用于进度条总大小)
+    let file = File::open(ass_path)?;
+    let total_bytes = file.metadata()?.len() as usize;
+    parse_ass_lyrics(BufReader::new(file), total_bytes)
+}
 
-    // --- 第一遍扫描: 读取文件, 收集元数据, 解析 Dialogue 行并检查时间一致性 ---
-    { // 使用块作用域限制 reader_pass1 的生命周期
-        let file_pass1 = File::open(ass_path)?;
-        let reader_pass1 = BufReader::new(file_pass1);
-        let mut after_format = false; // 标记是否已找到 Format 行
 
-        for line_result in reader_pass1.lines() {
-            line_counter += 1;
-            let line = line_result?; // 处理 IO 错误
+/// 从输入流解析 ASS 歌词 (文件读取和内存中的往返校验共用)。
+///
+/// # Arguments
+/// * `total_bytes` - 输入的总字节数，仅用于显示进度条。
+fn parse_ass_lyrics(reader: impl BufRead, total_bytes: usize) -> Result<(Lyrics, bool), ConversionError> {
+    let mut processed_bytes: usize = 0; // 跟踪已处理字节数
+    let mut warning_occurred = false; // 标记是否有时间不一致等警告
 
-            // 跳过直到找到 Format 行
-            if !after_format {
-                if line.trim_start().starts_with("Format: Layer, Start, End, Style, Name,") {
-                    after_format = true;
-                }
-                continue;
+    let mut lyrics = Lyrics::default();
+    let mut after_format = false; // 标记是否已找到 Events 段的 Format 行
+    let mut line_number = 0; // 文件行号计数器
+    let mut styles: HashMap<String, StylePosition> = HashMap::new(); // [V4+ Styles] 段中的样式 (用于 --infer-duet)
+    let mut style_fields: Vec<String> = Vec::new(); // [V4+ Styles] 段 Format 行中的字段名 (小写)
+    let mut in_styles = false;
+    let mut is_karaoke_file = false; // 是否有 Effect 为 karaoke 的行或注音样式 (见 `KaraokeSyntax::Auto`)
+
+    // 逐行读取输入文件
+    for line_result in reader.lines() {
+        line_number += 1;
+        let line = line_result?; // 处理 IO 错误
+        // 估算已处理字节数 (用于进度条)
+        processed_bytes += line.len() + if cfg!(windows) { 2 } else { 1 }; // 加上换行符字节
+        display_progress_bar(processed_bytes.min(total_bytes), total_bytes);
+
+        // 必须先找到 Format 行才能开始处理 Dialogue 和 Comment
+        if !after_format {
+            let trimmed = line.trim();
+            if trimmed.starts_with("Format: Layer, Start, End, Style, Name,") {
+                after_format = true; // 找到 Format 行
+            } else if trimmed.starts_with('[') {
+                in_styles = trimmed.eq_ignore_ascii_case("[V4+ Styles]") || trimmed.eq_ignore_ascii_case("[V4 Styles]");
+            } else if in_styles && let Some(format) = trimmed.strip_prefix("Format:") {
+                style_fields = format.split(',').map(|field| field.trim().to_lowercase()).collect();
+            } else if in_styles && let Some((name, style)) = parse_ass_style_line(&style_fields, trimmed) {
+                is_karaoke_file |= name.to_lowercase().ends_with(FURIGANA_STYLE_SUFFIX);
+                styles.insert(name, style);
             }
+            continue; // 跳过 Format 行之前的所有行 (包括 Format 行本身)
+        }
 
-            // --- 在 Format 行之后处理 ---
-
-            // 检查元数据 Comment 行
-            if let Some(caps) = META_COMMENT_REGEX.captures(&line) {
-                 if let Some(text) = caps.get(1) {
-                     if let Some(formatted_meta) = parse_ass_metadata_text(text.as_str()) {
-                         metadata_lines.push(formatted_meta); // 收集元数据
-                     }
-                 }
-                 continue; // 处理完元数据，跳到下一行
-            }
-
-            // 尝试解析 Dialogue 行
-            if line.starts_with("Dialogue:") {
-                // 调用辅助函数解析
-                match parse_ass_dialogue_line(&line, line_counter)? {
-                    Some(dialogue_data) => {
-                        // --- 如果不是 roma/trans/ts 才进行时间一致性检查 ---
-                        if !(dialogue_data.style.eq_ignore_ascii_case("roma")
-                             || dialogue_data.style.eq_ignore_ascii_case("trans")
-                             || dialogue_data.style.eq_ignore_ascii_case("ts"))
-                        {
-                            // 只有在样式不是 roma/trans/ts 时才调用检查函数
-                            if !check_time_consistency(dialogue_data.duration_ms, dialogue_data.sum_k_ms, dialogue_data.line_number) {
-                                warning_occurred_overall = true; // 设置警告标志
-                            }
-                        }
-                        parsed_dialogues.push(dialogue_data); // 存储解析结果
-                    }
-                    None => {
-                        // 虽然以 "Dialogue:" 开头，但正则不匹配，可能格式错误
-                        log_warn!("第 {} 行看起来像 Dialogue 但无法完整解析其结构。", line_counter);
-                        warning_occurred_overall = true;
-                    }
-                }
+        is_karaoke_file |= ass_event_effect(&line).is_some_and(|effect| effect.eq_ignore_ascii_case(KARAOKE_EFFECT));
+
+        // 检查是否是元数据行 (Style 为 "meta" 的 Comment 或 Dialogue)
+        match parse_ass_metadata_event(&line) {
+            Some(Ok(entry)) => {
+                lyrics.add_metadata(&entry.key, &entry.value); // 收集元数据 (重复的键值会被合并)
+                continue;
             }
-            // 忽略其他非元数据、非 Dialogue 的行
+            Some(Err(reason)) => {
+                log_warn!("第 {} 行看起来像元数据，但已被忽略: {}", line_number, reason);
+                warning_occurred = true;
+                continue;
+            }
+            None => {}
         }
-    } // reader_pass1 和 file_pass1 在此释放
 
-    // --- 第二遍扫描: 写入元数据, 计算 LYS 属性并写入 LYS 文件 ---
-    let mut writer = BufWriter::new(File::create(lys_path)?);
-    let total_dialogues = parsed_dialogues.len();
-    // LYS '背' 属性计算需要跟踪上一次的计算结果 (因为 '背' 后面跟 '背' 需要继承)
-    let mut last_calculated_property = LYS_PROPERTY_UNSET;
+        // 跳过卡拉 OK 模板生成的行、模板和代码行，以及不作为歌词的 Comment 行和其他 Layer 的行
+        let Some(line) = ass_lyric_event(&line) else {
+            continue;
+        };
 
-    // 1. 写入元数据 (如果存在)
-    for meta_line in &metadata_lines {
-        writeln!(writer, "{}", meta_line)?;
+        match parse_ass_dialogue_line(&line, line_number, &styles) {
+            // 配置为 ignore 的样式直接丢弃
+            Ok(Some(dialogue)) if style_role(&dialogue.style) == StyleRole::Ignore => {}
+            Ok(Some(dialogue)) => lyrics.lines.push(dialogue),
+            Ok(None) => {
+                // 虽然以 "Dialogue:" 开头，但正则不匹配，可能格式错误
+                log_warn!("第 {} 行看起来像 Dialogue 但无法完整解析其结构。", line_number);
+                warning_occurred = true;
+            }
+            Err(e) => {
+                // 记录警告并继续处理文件的其余部分
+                log_warn!("处理第 {} 行 Dialogue 时出错: {}", line_number, e);
+                warning_occurred = true;
+            }
+        }
     }
 
-    // 2. 遍历第一遍解析好的 Dialogue 数据
-    for (i, current_dialogue) in parsed_dialogues.iter().enumerate() {
+    display_progress_bar(total_bytes, total_bytes); // 确保进度条显示 100%
+    if total_bytes >= PROGRESS_BAR_THRESHOLD { println!(); } // 进度条后换行
 
-        // 如果当前行的 Style 是 "ts" 或 "trans"，则跳过，不生成 LYS 输出
-        if current_dialogue.style == "ts" || current_dialogue.style == "trans" {
-            // 更新进度条时考虑跳过的行，可以使用总行数 i+1 作为当前进度
-            display_progress_bar(i + 1, total_dialogues);
-            continue; // 进行下一次循环
+    // 处理 Aegisub 卡拉 OK 的多段高亮 (#) 和注音 (漢字|かんじ) 语法，普通 ASS 中的 "|" 和 "#" 按文本保留
+    if lyrics_config().karaoke_syntax.enabled(is_karaoke_file) {
+        for line in lyrics.lines.iter_mut().filter(|line| !line.untimed) {
+            apply_karaoke_furigana(line);
         }
-        // 如果不是翻译行，则继续处理
+    }
 
-        // 获取上一个解析后的 Dialogue 数据（如果当前不是第一行）
-        let previous_dialogue = if i > 0 { parsed_dialogues.get(i - 1) } else { None };
+    Ok((lyrics, warning_occurred))
+}
 
-        // 调用辅助函数计算当前行的 LYS 属性
-        let (property, calc_warned) = calculate_lys_property(
-        current_dialogue,
-        previous_dialogue,
-        last_calculated_property // 传入上一次的计算结果用于继承
-        );
-        if calc_warned { // 如果 calculate_lys_property (主要来自 map_ass_name_to_category) 报告了警告
-            warning_occurred_overall = true;
+
+/// 读取 QRC 文件并解析为歌词模型。
+/// 行时间取自行首的 `[开始ms,持续ms]`，音节时间取自每个文本段后的 `(开始ms,持续ms)`。
+fn read_qrc_file(qrc_path: &Path) -> Result<(Lyrics, bool), ConversionError> {
+    let file = File::open(qrc_path)?;
+    let total_bytes = file.metadata()?.len() as usize;
+    parse_qrc_lyrics(BufReader::new(file), total_bytes)
+}
+
+
+/// 从输入流解析 QRC 歌词 (文件读取和内存中的往返校验共用)。
+fn parse_qrc_lyrics(reader: impl BufRead, total_bytes: usize) -> Result<(Lyrics, bool), ConversionError> {
+    let mut processed_bytes: usize = 0; // 跟踪已处理字节
+
+    let mut lyrics = Lyrics::default();
+
+    let mut song_part: Option<String> = None; // 当前段落 ([song-part:...] 标签)
+
+    // 逐行读取 QRC 文件
+    for (index, line_result) in reader.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line_result?;
+        processed_bytes += line.len() + if cfg!(windows) { 2 } else { 1 };
+        display_progress_bar(processed_bytes.min(total_bytes), total_bytes);
+
+        // 段落标签，对之后的行有效
+        if let Some(part) = parse_song_part_tag(&line) {
+            song_part = part;
+            continue;
         }
-        last_calculated_property = property;
 
-        // 构建 LYS 输出行: [属性]文本1(开始ms,持续ms)文本2(开始ms,持续ms)...
-        let mut lys_line_content = format!("[{}]", property); // 行首是属性标签
-        let mut current_segment_start_ms = current_dialogue.start_ms; // LYS 时间戳是分段的绝对开始时间
-        for (seg_text, seg_ms) in &current_dialogue.segments {
-             let segment_duration_ms = *seg_ms; // 解引用得到时长值
-             // 过滤掉无效分段 (无文本且无时长)
-             if !seg_text.is_empty() || segment_duration_ms > 0 {
-                 // 拼接文本和时间戳
-                 lys_line_content.push_str(&format!("{}({},{})", seg_text, current_segment_start_ms, segment_duration_ms));
-                 // 更新下一个分段的理论开始时间
-                 current_segment_start_ms += segment_duration_ms;
-             }
+        // 收集文件头中的元数据标签 (例如 [ti:歌曲名])
+        if let Some(entries) = parse_lrc_metadata_tag(&line) {
+            entries.iter().for_each(|entry| lyrics.add_metadata(&entry.key, &entry.value));
+            continue;
         }
-        // 将构建好的完整 LYS 行写入文件
-        writeln!(writer, "{}", lys_line_content)?;
 
-        // 更新进度条 (基于已处理的 Dialogue 数量)
-        display_progress_bar(i + 1, total_dialogues);
-    }
+        // 尝试匹配行时间戳 [开始ms,持续ms]，跳过非 QRC 时间戳开头的行
+        let Some(ts_caps) = QRC_TIMESTAMP_REGEX.captures(&line) else { continue; };
+        let header_start_ms: usize = ts_caps[1].parse()?;
+        let header_duration_ms: usize = ts_caps[2].parse()?;
+        let header_end_ms = header_start_ms.saturating_add(header_duration_ms); // 计算行结束时间
+
+        // 获取时间戳之后的内容部分，并按时间戳拆分出音节
+        let content_part = &line[ts_caps.get(0).unwrap().end()..];
+        let syllables = parse_word_timed_content(content_part, header_end_ms)?;
 
-    // --- 收尾工作 ---
-    display_progress_bar(total_dialogues, total_dialogues); // 确保进度条达到 100%
-    println!(); // 进度条后换行
+        lyrics.lines.push(LyricLine {
+            line_number,
+            start_ms: header_start_ms,
+            end_ms: header_end_ms,
+            style: "Default".to_string(),
+            name: None,
+            syllables,
+            effect: None,
+            song_part: song_part.clone(),
+            untimed: false,
+            position: None,
+        });
+    }
 
-    writer.flush()?; // 确保所有缓冲数据写入磁盘
-    log_success!("{}", ASS_TO_LYS_COMPLETE); // 输出成功日志
+    display_progress_bar(total_bytes, total_bytes);
+    if total_bytes >= PROGRESS_BAR_THRESHOLD { println!(); }
 
-    Ok(warning_occurred_overall) // 返回总的警告状态
+    Ok((lyrics, false))
 }
 
 
-/// 将 Lyricify Syllable (.lys) 文件转换为 ASS 文件 (单遍扫描)。
-fn convert_lys_to_ass(lys_path: &Path, ass_path: &Path) -> Result<bool, ConversionError> {
+/// 读取 Lyricify Syllable (.lys) 文件并解析为歌词模型。
+/// 行时间由行内所有音节的最早开始时间和最晚结束时间决定，LYS 属性映射为 ASS 的 Name 字段。
+fn read_lys_file(lys_path: &Path) -> Result<(Lyrics, bool), ConversionError> {
     let file = File::open(lys_path)?;
-    let metadata = file.metadata()?;
-    let total_bytes = metadata.len() as usize;
+    let total_bytes = file.metadata()?.len() as usize;
+    parse_lys_lyrics(BufReader::new(file), total_bytes)
+}
+
+
+/// 从输入流解析 Lyricify Syllable 歌词 (文件读取和内存中的往返校验共用)。
+fn parse_lys_lyrics(reader: impl BufRead, total_bytes: usize) -> Result<(Lyrics, bool), ConversionError> {
     let mut processed_bytes: usize = 0; // 跟踪已处理字节
     let mut line_number: usize = 0; // 文件行号
-    let mut warning_occurred_overall = false; // 用于累积所有警告
-
-    let reader = BufReader::new(file);
-    let mut writer = BufWriter::new(File::create(ass_path)?);
+    let mut warning_occurred = false; // 用于累积所有警告
 
-    // 写入 ASS 文件头和样式 (使用辅助函数)
-    write_ass_header(&mut writer)?;
+    let mut lyrics = Lyrics::default();
+    let mut properties: Vec<usize> = Vec::new(); // 每个歌词行的原始属性值
+    let mut song_part: Option<String> = None; // 当前段落 ([song-part:...] 标签)
 
     // 逐行读取 LYS 文件
     for line_result in reader.lines() {
         line_number += 1;
         let line = line_result?;
-        let line_bytes = line.len() + if cfg!(windows) { 2 } else { 1 };
-        processed_bytes += line_bytes;
+        processed_bytes += line.len() + if cfg!(windows) { 2 } else { 1 };
+        display_progress_bar(processed_bytes.min(total_bytes), total_bytes);
+
+        // 段落标签，对之后的行有效
+        if let Some(part) = parse_song_part_tag(&line) {
+            song_part = part;
+            continue;
+        }
+
+        // 收集文件头中的元数据标签 (例如 [ti:歌曲名])
+        if let Some(entries) = parse_lrc_metadata_tag(&line) {
+            entries.iter().for_each(|entry| lyrics.add_metadata(&entry.key, &entry.value));
+            continue;
+        }
 
         // 尝试匹配 LYS 行的属性标签 `[属性值]` 和内容部分
-        if let Some(prop_caps) = LYS_PROPERTY_REGEX.captures(&line) {
-            // 解析属性值 (如果解析失败则使用默认值 LYS_PROPERTY_UNSET)
-            let property: usize = prop_caps[1].parse().unwrap_or(LYS_PROPERTY_UNSET);
-            let content = &prop_caps[2]; // 获取属性标签之后的内容
-
-            // --- 解析内容中的单词/分段时间戳 `(开始ms,持续ms)` ---
-            let mut timestamps: Vec<(usize, usize, usize, usize)> = Vec::new(); // (start_pos, end_pos, start_ms, duration_ms)
-            for ts_caps in WORD_TIME_TAG_REGEX.captures_iter(content) {
-                let start_pos = ts_caps.get(0).unwrap().start(); // 时间戳在字符串中的开始位置
-                let end_pos = ts_caps.get(0).unwrap().end();     // 时间戳在字符串中的结束位置
-                // 使用命名捕获组解析时间戳数值
-                let start_ms: usize = ts_caps["start"].parse()?;
-                let duration_ms: usize = ts_caps["duration"].parse()?;
-                timestamps.push((start_pos, end_pos, start_ms, duration_ms));
-            }
-
-            // 如果行内没有解析到有效的时间戳，则跳过此行
-            if timestamps.is_empty() {
-                display_progress_bar(processed_bytes.min(total_bytes), total_bytes);
-                continue;
+        let Some(prop_caps) = LYS_PROPERTY_REGEX.captures(&line) else {
+            if !line.trim().is_empty() && !line.starts_with('[') {
+                // 不是 [数字] 开头，但也不是空行或元数据行，说明可能存在无法识别的数据
+                log_warn!("第 {} 行 LYS 数据格式无法识别，已跳过: '{}'", line_number, line);
+                warning_occurred = true;
             }
+            continue; // 忽略空行和其他方括号开头的行
+        };
 
-            // 按时间戳在字符串中的出现位置排序，以保证正确提取文本片段
-            timestamps.sort_by_key(|k| k.0);
-
-            // --- 重建 ASS 文本和对应的 {\k} 标签 ---
-            let mut ass_text_parts: Vec<String> = Vec::new(); // 存储构建的 ASS 文本片段
-            let mut last_char_pos = 0; // 跟踪已处理到的字符索引
-            let mut min_start_ms = usize::MAX; // 记录该行所有时间戳中最早的开始时间
-            let mut max_end_ms = 0; // 记录该行所有时间戳中最晚的结束时间
-
-            // 第一次遍历时间戳：确定行的整体开始时间和结束时间
-            for &(_, _, start_ms, duration_ms) in &timestamps {
-                 if start_ms < min_start_ms {
-                     min_start_ms = start_ms; // 更新最小开始时间
-                 }
-                 // 计算当前时间戳段的结束时间
-                 let current_end_ms = start_ms.saturating_add(duration_ms);
-                 if current_end_ms > max_end_ms {
-                     max_end_ms = current_end_ms; // 更新最大结束时间
-                 }
-            }
-
-            // 如果未能找到有效的最小开始时间 (例如所有时间戳都是 (0,0) 且被忽略)，跳过此行
-            if min_start_ms == usize::MAX {
-                 log_warn!("第 {} 行 LYS 数据缺少有效时间戳，已跳过。", line_number);
-                 warning_occurred_overall = true; // 累积警告
-                 display_progress_bar(processed_bytes.min(total_bytes), total_bytes);
-                 continue;
-            }
-
-            // 初始化上一个时间戳段的结束时间为行的最小开始时间，用于计算第一个 K 标签前的停顿
-            let mut last_segment_end_ms = min_start_ms;
-
-            // 第二次遍历时间戳：构建带 {\k} 标签的 ASS 文本
-            for &(start_pos, end_pos, current_start_ms, current_duration_ms) in &timestamps {
-                // 计算当前时间戳开始时间与上一个时间戳结束时间之间的间隙 (gap)
-                if current_start_ms > last_segment_end_ms {
-                    let gap_ms = current_start_ms - last_segment_end_ms;
-                    // 计算间隙对应的 K 值 (向上取整)
-                    let gap_k_value = (gap_ms + K_TAG_MULTIPLIER / 2) / K_TAG_MULTIPLIER;
-                    if gap_k_value > 0 {
-                        // 如果有间隙，插入一个只有 K 标签的停顿片段
-                        ass_text_parts.push(format!("{{\\k{}}}", gap_k_value));
-                    }
-                }
-
-                // 提取当前时间戳之前的文本片段
-                let text_segment = &content[last_char_pos..start_pos];
+        // 解析属性值 (如果解析失败则使用默认值 LYS_PROPERTY_UNSET)
+        let property: usize = prop_caps[1].parse().unwrap_or(LYS_PROPERTY_UNSET);
+        let content = &prop_caps[2]; // 获取属性标签之后的内容
+
+        // LYS 行没有行级时间戳，行的结束时间由最晚结束的音节决定
+        let max_end_ms = WORD_TIME_TAG_REGEX
+            .captures_iter(content)
+            .filter_map(|caps| {
+                let start_ms: usize = caps["start"].parse().ok()?;
+                let duration_ms: usize = caps["duration"].parse().ok()?;
+                Some(start_ms.saturating_add(duration_ms))
+            })
+            .max();
+        // 如果行内没有解析到有效的时间戳，则跳过此行
+        let Some(max_end_ms) = max_end_ms else { continue; };
+
+        let syllables = parse_word_timed_content(content, max_end_ms)?;
+        let Some(min_start_ms) = syllables.iter().map(|s| s.start_ms).min() else {
+            log_warn!("第 {} 行 LYS 数据缺少有效时间戳，已跳过。", line_number);
+            warning_occurred = true;
+            continue;
+        };
 
-                // 计算当前文本片段对应的 K 值 (向上取整)
-                let k_value = (current_duration_ms + K_TAG_MULTIPLIER / 2) / K_TAG_MULTIPLIER;
+        // 按演唱者规则将 LYS 属性值映射回 ASS 的 Name 字段
+        // (默认规则下左侧为 "左"，右侧为 "右"，未定方向的背景人声为 "背"，其他为空 Name)
+        let ass_name = singer_name_for(SingerInfo::from_lys_property(property));
 
-                // 组合 K 标签和文本片段
-                if k_value > 0 {
-                    // 如果 K > 0，则添加 {\kX}文本
-                    ass_text_parts.push(format!("{{\\k{}}}{}", k_value, text_segment));
-                } else if !text_segment.is_empty() {
-                     // 如果 K = 0 但有文本，只添加文本 (避免产生 {\k0})
-                    ass_text_parts.push(text_segment.to_string());
-                }
-                // 如果 K = 0 且文本为空，则忽略此段
-
-                // 更新下一个片段的处理起始位置和上一个片段的结束时间
-                last_segment_end_ms = current_start_ms.saturating_add(current_duration_ms);
-                last_char_pos = end_pos;
-            }
-
-             // 处理最后一个时间戳之后可能存在的文本
-             let remaining_text = &content[last_char_pos..];
-             if !remaining_text.is_empty() {
-                 // 检查这部分文本是否还有时间（即行结束时间晚于最后一个片段结束时间）
-                 if max_end_ms > last_segment_end_ms {
-                     let gap_ms = max_end_ms - last_segment_end_ms;
-                     let gap_k_value = (gap_ms + K_TAG_MULTIPLIER / 2) / K_TAG_MULTIPLIER;
-                     if gap_k_value > 0 {
-                         // 如果有时间，给剩余文本加上 K 标签
-                         ass_text_parts.push(format!("{{\\k{}}}{}", gap_k_value, remaining_text));
-                     } else {
-                         // 时间不足 K=1，直接添加文本
-                         ass_text_parts.push(remaining_text.to_string());
-                     }
-                 } else {
-                    // 没有剩余时间了，直接添加文本
-                    ass_text_parts.push(remaining_text.to_string());
-                 }
-             } else if max_end_ms > last_segment_end_ms {
-                // 没有剩余文本，但还有时间间隙
-                 let gap_ms = max_end_ms - last_segment_end_ms;
-                 let gap_k_value = (gap_ms + K_TAG_MULTIPLIER / 2) / K_TAG_MULTIPLIER;
-                 if gap_k_value > 0 {
-                     // 只添加一个 K 标签表示结束前的停顿
-                     ass_text_parts.push(format!("{{\\k{}}}", gap_k_value));
-                 }
-             }
-
-
-            // 将所有文本片段连接起来，形成最终的 ASS 文本内容
-            let final_ass_text = ass_text_parts.join("");
-
-            // 将 LYS 属性值映射回 ASS 的 Name 字段
-            let ass_name = match property {
-                // 左对齐相关的属性都映射为 "左"
-                LYS_PROPERTY_LEFT | LYS_PROPERTY_NO_BACK_LEFT | LYS_PROPERTY_BACK_LEFT => "左",
-                // 右对齐相关的属性都映射为 "右"
-                LYS_PROPERTY_RIGHT | LYS_PROPERTY_NO_BACK_RIGHT | LYS_PROPERTY_BACK_RIGHT => "右",
-                // 有背景但未定左右的属性映射为 "背"
-                LYS_PROPERTY_BACK_UNSET => "背",
-                // 其他属性 (如 LYS_PROPERTY_UNSET) 映射为空 Name
-                _ => "",
-            };
+        lyrics.lines.push(LyricLine {
+            line_number,
+            start_ms: min_start_ms,
+            end_ms: max_end_ms,
+            style: "Default".to_string(),
+            name: ass_name,
+            syllables,
+            effect: None,
+            song_part: song_part.clone(),
+            untimed: false,
+            position: None,
+        });
+        properties.push(property);
+    }
 
-            // 将 LYS 行的整体开始/结束时间转换为 ASS 时间格式
-            let start_time_ass = milliseconds_to_time(min_start_ms);
-            let end_time_ass = milliseconds_to_time(max_end_ms);
-
-            // 检查最终文本是否为空，避免写入空的 Dialogue 行
-            if !final_ass_text.is_empty() {
-                // 写入 ASS Dialogue 行到输出文件
-                writeln!(
-                    writer,
-                    "Dialogue: 0,{},{},Default,{},0,0,0,,{}", // 使用 Default 样式
-                    start_time_ass, end_time_ass, ass_name, final_ass_text
-                )?;
-            }
-        } else if !line.trim().is_empty() && !line.starts_with('[') {
-            // 如果行不匹配 LYS 格式 (不是 [数字] 开头)，但也不是空行或元数据行
-            // 则记录一个警告，说明可能存在无法识别的数据
-             log_warn!("第 {} 行 LYS 数据格式无法识别，已跳过: '{}'", line_number, line);
-             warning_occurred_overall = true; // 累积警告
-        } // 忽略空行和元数据行
-
-        // 更新进度条
-        display_progress_bar(processed_bytes.min(total_bytes), total_bytes);
-    } // 文件行处理循环结束
+    // 按 Name 重新计算的属性与原属性不同时 (例如 1 会被算为 4，背景人声行的方向会按前一行重新推断)，
+    // 在 Name 中附加 x-lys:<属性>，保证 LYS -> ASS -> LYS 的属性不变
+    let mut last_property = LYS_PROPERTY_UNSET;
+    for (index, &property) in properties.iter().enumerate() {
+        let previous_line = index.checked_sub(1).map(|previous| &lyrics.lines[previous]);
+        let (recalculated, _) = calculate_lys_property(&lyrics.lines[index], previous_line, last_property);
+        if recalculated != property && property <= LYS_PROPERTY_BACK_RIGHT {
+            let line = &mut lyrics.lines[index];
+            let tag = format!("{}{}", LYS_PROPERTY_NAME_PREFIX, property);
+            line.name = Some(match line.name.take() {
+                Some(name) => format!("{} {}", name, tag),
+                None => tag,
+            });
+        }
+        last_property = property;
+    }
 
-    // --- 收尾 ---
-    display_progress_bar(total_bytes, total_bytes); // 确保进度条显示 100%
-    println!(); // 换行
+    display_progress_bar(total_bytes, total_bytes);
+    if total_bytes >= PROGRESS_BAR_THRESHOLD { println!(); }
 
-    writer.flush()?; // 确保所有缓冲写入文件
-    log_success!("{}", LYS_TO_ASS_COMPLETE); // 打印成功信息
-    Ok(warning_occurred_overall) // 返回总的警告状态
+    Ok((lyrics, warning_occurred))
 }
 
 
-// --- 辅助函数 ---
+/// 解析 QRC/LYS 行内容中的 `文本(开始ms,持续ms)` 序列为音节列表。
+/// 最后一个时间戳之后若还有文本，则作为一个从上个音节结束持续到 `line_end_ms` 的音节。
+///
+/// # Arguments
+/// * `content` - 行时间戳/属性标签之后的内容部分。
+/// * `line_end_ms` - 行的结束时间 (毫秒)，用于计算尾部文本的时长。
+fn parse_word_timed_content(content: &str, line_end_ms: usize) -> Result<Vec<Syllable>, ConversionError> {
+    let mut syllables = Vec::new();
+    let mut last_char_pos = 0; // 跟踪已处理到的字符索引
+    let mut last_end_ms: Option<usize> = None; // 上一个音节的结束时间
+
+    for caps in WORD_TIME_TAG_REGEX.captures_iter(content) {
+        let tag = caps.get(0).unwrap();
+        let start_ms: usize = caps["start"].parse()?;
+        let duration_ms: usize = caps["duration"].parse()?;
+        // 时间戳之前的文本即为该音节的文本，没有文本的时间戳视为停顿
+        let text = content[last_char_pos..tag.start()].to_string();
+        syllables.push(Syllable {
+            is_gap: text.is_empty(),
+            text,
+            start_ms,
+            duration_ms,
+            ass_tags: String::new(),
+            ruby: None,
+        });
+        last_char_pos = tag.end();
+        last_end_ms = Some(start_ms.saturating_add(duration_ms));
+    }
 
-/// 将 ASS 文件头和样式信息写入 Writer。
-/// 用于 `convert_qrc_to_ass` 和 `convert_lys_to_ass`。
-fn write_ass_header(writer: &mut BufWriter<File>) -> io::Result<()> {
-    // 写入 [Script Info] 段，包含脚本元信息和播放器参数建议
-    writeln!(writer, "[Script Info]")?;
-    writeln!(writer, "PlayResX: 1920")?; // 建议播放器渲染分辨率宽度
-    writeln!(writer, "PlayResY: 1440")?; // 建议播放器渲染分辨率高度
-    writeln!(writer)?;
+    // 处理最后一个时间戳之后可能存在的文本
+    let remaining_text = &content[last_char_pos..];
+    if let Some(last_end_ms) = last_end_ms.filter(|_| !remaining_text.is_empty()) {
+        syllables.push(Syllable {
+            text: remaining_text.to_string(),
+            start_ms: last_end_ms,
+            duration_ms: line_end_ms.saturating_sub(last_end_ms),
+            is_gap: false,
+            ass_tags: String::new(),
+            ruby: None,
+        });
+    }
 
-    // 写入 [V4+ Styles] 段，定义样式
-    writeln!(writer, "[V4+ Styles]")?;
-    // 定义样式的格式 (字段顺序)
-    writeln!(writer, "Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding")?;
-    // 定义一个名为 "Default" 的样式，可以根据需要修改字体、颜色、边框等参数
-    writeln!(writer, "Style: Default,微软雅黑,100,&H00FFFFFF,&H004E503F,&H00000000,&H00000000,0,0,0,0,100,100,0,0,1,1.5,0.5,2,10,10,60,1")?;
-    writeln!(writer)?; // 空行分隔段落
+    Ok(syllables)
+}
 
-    // 写入 [Events] 段的头部，定义事件（即 Dialogue 行）的格式
-    writeln!(writer, "[Events]")?;
-    writeln!(writer, "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text")?;
 
-    Ok(()) // 返回成功
-}
+// --- 歌词模型写入器 ---
 
+/// 将歌词模型写入 QRC 文件。
+/// QRC 格式: [行开始ms,行持续ms]文本1(绝对开始ms,分段持续ms)文本2(绝对开始ms,分段持续ms)...
+fn write_qrc_file(lyrics: &Lyrics, qrc_path: &Path, options: &ConversionOptions) -> Result<(), ConversionError> {
+    let mut writer = BufWriter::new(File::create(qrc_path)?);
+    write_qrc_lyrics(lyrics, &mut writer, options)?;
+    writer.flush()?; // 确保所有缓冲内容写入文件
+    Ok(())
+}
 
-/// 封装转换函数的执行过程，包括日志打印和错误处理。
-/// 返回 `true` 如果发生了错误或（未来可能实现的）需要用户注意的警告，否则返回 `false`。
-fn execute_conversion(
-    action: fn(&Path, &Path) -> Result<bool, ConversionError>, // 接受一个转换函数作为参数
-    input_path: &Path,
-    output_path: &Path,
-) -> bool { // 返回 bool 表示是否出错/需要等待
 
-    // 执行传入的转换函数 `action`
-    match action(input_path, output_path) {
-        Ok(_) => {
-            // 转换成功，成功日志已在 action 内部打印
-            false // 返回 false 表示没有错误发生
+/// 将歌词模型以 QRC 格式写入输出流。
+fn write_qrc_lyrics(lyrics: &Lyrics, writer: &mut impl Write, options: &ConversionOptions) -> Result<(), ConversionError> {
+    // 1. 写入元数据 (如果存在)；由注音生成的 [kana:...] 标签替换原有的 kana 元数据
+    let kana_tag = if options.furigana == FuriganaMode::Kana { format_kana_tag(lyrics) } else { None };
+    for tag in format_metadata_header(&lyrics.metadata, false) {
+        if kana_tag.is_some() && tag.starts_with(&format!("[{}:", QRC_KANA_TAG)) {
+            continue;
         }
-        Err(e) => {
-            // 转换过程中发生错误
-            let formatted_error_msg = format!("{} {}", CONVERSION_ERROR_MSG, e); // 格式化错误信息
-            log_error!("{}", formatted_error_msg); // 打印错误日志
-            true // 返回 true 表示发生了错误，可能需要等待
+        writeln!(writer, "{}", tag)?;
+    }
+    if let Some(tag) = &kana_tag {
+        writeln!(writer, "{}", tag)?;
+    }
+
+    // 2. 写入 QRC 内容 (翻译和罗马音行不写入；纯文本行没有逐字时间，只写出行时间)
+    let mut current_part: Option<&str> = None;
+    for line in lyrics.lines.iter().filter(|line| !is_auxiliary_style(&line.style)) {
+        if options.song_part_tags {
+            write_song_part_tag(writer, &mut current_part, line)?;
         }
+        let syllables: &[Syllable] = if line.untimed { &[] } else { &line.syllables };
+        writeln!(
+            writer,
+            "[{},{}]{}",
+            line.start_ms,
+            line.duration_ms(),
+            format_word_timed_syllables(syllables, options.gap_mode)
+        )?;
     }
+
+    Ok(())
 }
 
 
-/// 在程序退出前暂停，等待用户按 Enter 键。
-/// 主要用于在命令行模式下，发生错误或警告后给用户时间查看信息。
-fn wait_for_exit() {
-     log_info!("按下 Enter 键退出..."); // 提示用户操作
-     let mut dummy = String::new(); // 用于存储读取的行（内容不重要）
-     let _ = io::stdin().read_line(&mut dummy);
+/// 将歌词模型写入 Lyricify Syllable (.lys) 文件。
+/// 翻译和罗马音行 (见 `is_auxiliary_style`) 不会写入。
+///
+/// # Returns
+/// * `Ok(bool)` - 计算 LYS 属性时是否发生了警告 (例如遇到未定义的 Name)。
+fn write_lys_file(lyrics: &Lyrics, lys_path: &Path, options: &ConversionOptions) -> Result<bool, ConversionError> {
+    let mut writer = BufWriter::new(File::create(lys_path)?);
+    let warning_occurred = write_lys_lyrics(lyrics, &mut writer, options)?;
+    writer.flush()?;
+    Ok(warning_occurred)
 }
 
 
-/// 根据输入路径和目标扩展名，自动生成输出文件的路径。
-/// 输出路径与输入路径在同一目录下，文件名添加 "_converted" 后缀。
-fn auto_output_path(input_path: &Path, output_ext_with_dot: &str) -> PathBuf {
-    // 获取输入文件的文件名（不含扩展名），如果失败则使用 "output" 作为默认值
-    let file_stem = input_path
-        .file_stem()
-        .and_then(|s| s.to_str()) // OsStr -> Option<&str>
-        .unwrap_or("output");
+/// 将歌词模型以 Lyricify Syllable 格式写入输出流。
+///
+/// # Returns
+/// * `Ok(bool)` - 计算 LYS 属性时是否发生了警告。
+fn write_lys_lyrics(lyrics: &Lyrics, writer: &mut impl Write, options: &ConversionOptions) -> Result<bool, ConversionError> {
+    let mut warning_occurred = false;
+    let total_lines = lyrics.lines.len();
+    // LYS '背' 属性计算需要跟踪上一次的计算结果 (因为 '背' 后面跟 '背' 需要继承)
+    let mut last_calculated_property = LYS_PROPERTY_UNSET;
+    let mut previous_line: Option<&LyricLine> = None; // 上一个写出的主歌词行
+    let mut current_part: Option<&str> = None; // 上一个写出的段落标签
 
-    // 构建新的文件名：原始文件名 + "_converted" + 目标扩展名
-    let new_file_name = format!("{}_converted{}", file_stem, output_ext_with_dot);
+    // 1. 写入元数据 (如果存在)
+    for tag in format_metadata_header(&lyrics.metadata, false) {
+        writeln!(writer, "{}", tag)?;
+    }
 
-    // 返回与输入文件同目录，但使用新文件名的 PathBuf
-    input_path.with_file_name(new_file_name)
+    // 2. 遍历所有歌词行
+    for (i, current_line) in lyrics.lines.iter().enumerate() {
+        display_progress_bar(i + 1, total_lines);
+
+        // 翻译和罗马音行不生成 LYS 输出
+        if is_auxiliary_style(&current_line.style) {
+            continue;
+        }
+
+        // 根据上一个主歌词行计算当前行的 LYS 属性
+        let (property, calc_warned) = calculate_lys_property(current_line, previous_line, last_calculated_property);
+        warning_occurred |= calc_warned;
+        last_calculated_property = property;
+        previous_line = Some(current_line);
+
+        if options.song_part_tags {
+            write_song_part_tag(writer, &mut current_part, current_line)?;
+        }
+
+        // 构建 LYS 输出行: [属性]文本1(开始ms,持续ms)文本2(开始ms,持续ms)... (纯文本行没有逐字时间)
+        let syllables: &[Syllable] = if current_line.untimed { &[] } else { &current_line.syllables };
+        writeln!(
+            writer,
+            "[{}]{}",
+            property,
+            format_word_timed_syllables(syllables, options.gap_mode)
+        )?;
+    }
+
+    display_progress_bar(total_lines, total_lines); // 确保进度条达到 100%
+    if total_lines >= PROGRESS_BAR_THRESHOLD { println!(); }
+
+    Ok(warning_occurred)
 }
 
 
-/// 读取用户在命令行中的单行输入。
-///
-/// # Arguments
-/// * `prompt` - 显示给用户的提示信息。
-///
-/// # Returns
-/// * `Ok(String)` - 用户输入的文本（已去除首尾空白）。
-/// * `Err(ConversionError)` - 读取过程中发生 IO 错误。
-fn read_user_input(prompt: &str) -> Result<String, ConversionError> {
-    print!("{}", prompt); // 显示提示信息
-    io::stdout().flush()?; // 确保提示信息立即显示在控制台
+/// 由主歌词行的注音生成 QRC 的 `[kana:...]` 标签。
+/// 每个注音写为 `<正文字数><读音>`，按在歌词中出现的顺序连接；没有注音时返回 `None`。
+fn format_kana_tag(lyrics: &Lyrics) -> Option<String> {
+    let mut entries = String::new();
+    for line in lyrics.lines.iter().filter(|line| !is_auxiliary_style(&line.style) && !line.untimed) {
+        let mut group: Option<(usize, &str)> = None; // 当前注音覆盖的 (正文字数, 读音)
+        for syllable in line.syllables.iter().filter(|syllable| !syllable.is_gap) {
+            let char_count = syllable.text.chars().count();
+            match (syllable.ruby.as_deref(), group.as_mut()) {
+                (Some(""), Some((count, _))) => *count += char_count, // "|<": 并入前一个注音
+                (ruby, _) => {
+                    if let Some((count, reading)) = group.take() {
+                        entries.push_str(&format!("{}{}", count, reading));
+                    }
+                    group = ruby.filter(|ruby| !ruby.is_empty()).map(|ruby| (char_count, ruby));
+                }
+            }
+        }
+        if let Some((count, reading)) = group {
+            entries.push_str(&format!("{}{}", count, reading));
+        }
+    }
+    (!entries.is_empty()).then(|| format!("[{}:{}]", QRC_KANA_TAG, entries))
+}
 
-    let mut input = String::new(); // 创建一个空字符串用于存储输入
-    io::stdin().read_line(&mut input)?; // 从标准输入读取一行
-    Ok(input.trim().to_string()) // 去除输入字符串首尾的空白字符并返回
+
+/// 将音节列表格式化为 QRC/LYS 共用的 `文本(绝对开始ms,持续ms)` 序列。
+/// 停顿按 `gap_mode` 处理；由于每个音节都带有绝对开始时间，丢弃停顿不会影响后续音节的时间。
+fn format_word_timed_syllables(syllables: &[Syllable], gap_mode: GapMode) -> String {
+    let mut output: Vec<Syllable> = Vec::with_capacity(syllables.len());
+    for syllable in syllables {
+        if syllable.is_gap {
+            match gap_mode {
+                GapMode::Emit => {}
+                GapMode::Drop => continue,
+                GapMode::Merge => {
+                    // 延长前一个歌词音节至停顿结束；行首的停顿没有可合并的音节，直接丢弃
+                    if let Some(previous) = output.last_mut() {
+                        previous.duration_ms = syllable.end_ms().saturating_sub(previous.start_ms);
+                    }
+                    continue;
+                }
+            }
+        }
+        output.push(syllable.clone());
+    }
+
+    output
+        .iter()
+        // 过滤掉无效的分段 (文本为空且时长为 0)
+        .filter(|syllable| !syllable.text.is_empty() || syllable.duration_ms > 0)
+        .map(|syllable| format!("{}({},{})", to_single_line_text(&syllable.text), syllable.start_ms, syllable.duration_ms))
+        .collect()
 }
 
 
-/// 读取用户输入的文件路径，并处理路径中可能包含的引号。
+/// 将歌词模型写入 ASS 文件。
+/// 音节之间的时间间隙会写成仅含 K 标签的停顿 (例如 `{\k20}`)。
 ///
 /// # Arguments
-/// * `prompt_template` - 提示信息模板，其中 "{}" 会被 `extension` 替换。
-/// * `extension` - 期望的文件扩展名（用于显示在提示信息中）。
-///
-/// # Returns
-/// * `Ok(PathBuf)` - 用户输入的有效文件路径。
-/// * `Err(ConversionError)` - 读取或处理路径过程中发生错误。
-fn read_file_path(prompt_template: &str, extension: &str) -> Result<PathBuf, ConversionError> {
-    loop {
-        // read_user_input 内部已经调用了 trim()，移除了原始输入两端的空白字符
-        let path_str_input = read_user_input(&prompt_template.replace("{}", extension))?;
-        
-        let mut current_path_slice: &str = &path_str_input;
+/// * `options` - 转换选项，决定取整策略 (`rounding`) 以及是否在 Effect 字段中嵌入精确毫秒时间 (`exact_ms`)。
+fn write_ass_file(lyrics: &Lyrics, ass_path: &Path, options: &ConversionOptions) -> Result<(), ConversionError> {
+    let mut writer = BufWriter::new(File::create(ass_path)?);
+    write_ass_lyrics(lyrics, &mut writer, options)?;
+    writer.flush()?;
+    Ok(())
+}
 
-        // 迭代移除成对的引号，直到不再有变化或者字符串长度不足以包含引号
-        loop {
-            let len_before_strip = current_path_slice.len();
-            if len_before_strip >= 2 { // 必须至少有两个字符才能构成一对引号
-                if current_path_slice.starts_with('"') && current_path_slice.ends_with('"') {
-                    current_path_slice = &current_path_slice[1..len_before_strip - 1];
-                } else if current_path_slice.starts_with('\'') && current_path_slice.ends_with('\'') {
-                    current_path_slice = &current_path_slice[1..len_before_strip - 1];
-                } else {
-                    // 如果两端不是匹配的同类型引号，则停止剥离
-                    break;
-                }
-            } else {
-                // 长度不足2，不可能有成对引号
-                break;
-            }
 
-            // 如果本次迭代没有改变长度，说明没有更多可剥离的成对引号
-            if current_path_slice.len() == len_before_strip {
-                break;
-            }
+/// 将歌词模型以 ASS 格式 (含文件头) 写入输出流。
+fn write_ass_lyrics(lyrics: &Lyrics, writer: &mut impl Write, options: &ConversionOptions) -> Result<(), ConversionError> {
+    let rounding = options.rounding;
+
+    // 写入 ASS 文件头和样式 (有注音时附带注音样式，以便再次读取时识别注音语法)
+    let has_ruby = lyrics.lines.iter().any(|line| line.syllables.iter().any(|syllable| syllable.ruby.is_some()));
+    write_ass_header(writer, has_ruby)?;
+
+    // 元数据写为 Style 为 "meta" 的 Comment 行
+    for entry in &lyrics.metadata {
+        writeln!(writer, "Comment: 0,0:00:00.00,0:00:00.00,meta,,0,0,0,,{}:{}", entry.key, entry.value)?;
+    }
+
+    for line in &lyrics.lines {
+        let (start_cs, end_cs) = (rounding.to_centiseconds(line.start_ms), rounding.to_centiseconds(line.end_ms));
+
+        // 纯文本行 (例如翻译行) 不写 K 标签
+        if line.untimed {
+            writeln!(
+                writer,
+                "Dialogue: 0,{},{},{},{},0,0,0,,{}",
+                milliseconds_to_time(start_cs * CENTISECONDS_TO_MILLISECONDS),
+                milliseconds_to_time(end_cs * CENTISECONDS_TO_MILLISECONDS),
+                line.style,
+                line.ass_name(),
+                encode_ass_escapes(&line.text())
+            )?;
+            continue;
         }
 
-        if current_path_slice.is_empty() {
-            let formatted_msg = format!("{} {}", EMPTY_FILE_PATH_ERROR.replace("{}", ""), extension); // 确保 {} 被替换
-            log_error!("{}", formatted_msg);
+        let segments = build_ass_karaoke_segments(line, rounding, options.exact_ms);
+
+        // 拼接最终文本，K=0 的分段只输出文本 (避免产生 {\k0})，精确模式下则保留以便与 Effect 中的记录一一对应
+        let ass_text: String = segments
+            .iter()
+            .map(|segment| {
+                let text = encode_ass_escapes(&segment.text);
+                if segment.k_cs > 0 || options.exact_ms {
+                    format!("{{\\k{}{}}}{}", segment.k_cs, segment.tags, text)
+                } else if !segment.tags.is_empty() {
+                    format!("{{{}}}{}", segment.tags, text)
+                } else {
+                    text
+                }
+            })
+            .collect();
+        // 检查最终文本是否为空，避免写入空的 Dialogue 行
+        if ass_text.is_empty() {
             continue;
         }
-        return Ok(PathBuf::from(current_path_slice.to_owned())); // 使用处理后的字符串创建 PathBuf
+
+        let effect = if options.exact_ms {
+            format_exact_timing_effect(start_cs, line.start_ms, end_cs, line.end_ms, &segments)
+        } else {
+            String::new()
+        };
+        writeln!(
+            writer,
+            "Dialogue: 0,{},{},{},{},0,0,0,{},{}",
+            milliseconds_to_time(start_cs * CENTISECONDS_TO_MILLISECONDS),
+            milliseconds_to_time(end_cs * CENTISECONDS_TO_MILLISECONDS),
+            line.style,
+            line.ass_name(),
+            effect,
+            ass_text
+        )?;
     }
-}
 
-/// 辅助函数：清理 PathBuf 中可能包含的、包裹整个路径的引号。
-fn clean_path_buf_quotes(path_buf: PathBuf) -> PathBuf {
-    let path_str_cow = path_buf.to_string_lossy(); // Cow<'a, str>
+    Ok(())
+}
 
-    let mut current_path_slice: &str = path_str_cow.as_ref();
-    let original_len = current_path_slice.len();
 
-    loop {
-        let len_before_strip = current_path_slice.len();
-        if len_before_strip >= 2 {
-            if current_path_slice.starts_with('"') && current_path_slice.ends_with('"') {
-                current_path_slice = &current_path_slice[1..len_before_strip - 1];
-            } else if current_path_slice.starts_with('\'') && current_path_slice.ends_with('\'') {
-                current_path_slice = &current_path_slice[1..len_before_strip - 1];
-            } else {
-                break;
-            }
-        } else {
-            break;
-        }
-        if current_path_slice.len() == len_before_strip {
-            break;
+/// 将歌词模型写入 LRC 文件 (只有行级时间，每行写为 [mm:ss.xx]文本)，文件头写出 LRC 原生支持的元数据标签。
+/// 没有文本的行不会写出。
+fn write_lrc_file(lyrics: &Lyrics, lrc_path: &Path) -> Result<(), ConversionError> {
+    let mut writer = BufWriter::new(File::create(lrc_path)?);
+    for tag in format_metadata_header(&lyrics.metadata, true) {
+        writeln!(writer, "{}", tag)?;
+    }
+    for line in &lyrics.lines {
+        let text = to_single_line_text(&line.text());
+        if !text.is_empty() {
+            writeln!(writer, "{}{}", milliseconds_to_lrc_time(line.start_ms), text)?;
         }
     }
+    writer.flush()?;
+    Ok(())
+}
 
-    // 仅当字符串内容确实发生了改变时（即引号被移除了），才创建新的 PathBuf
-    // 否则，返回原始的 PathBuf 以避免不必要的分配和潜在的 non-UTF8 路径信息的丢失。
-    if current_path_slice.len() < original_len {
-        PathBuf::from(current_path_slice.to_owned())
-    } else {
-        path_buf // 没有变化，返回原始 PathBuf
+
+/// 将歌词模型写入 SRT 字幕文件 (只有行级时间，每行一个字幕块)。
+fn write_srt_file(lyrics: &Lyrics, srt_path: &Path) -> Result<(), ConversionError> {
+    let mut writer = BufWriter::new(File::create(srt_path)?);
+    for (index, line) in lyrics.lines.iter().enumerate() {
+        writeln!(writer, "{}", index + 1)?;
+        writeln!(writer, "{} --> {}", milliseconds_to_srt_time(line.start_ms), milliseconds_to_srt_time(line.end_ms))?;
+        writeln!(writer, "{}", line.text())?;
+        writeln!(writer)?;
     }
+    writer.flush()?;
+    Ok(())
 }
 
 
-/// 在命令行中显示一个简单的文本进度条。
-/// 仅当文件总大小超过阈值时显示。
+/// 写入 ASS 时的一个 {\k} 分段 (音节或停顿)。
+struct KaraokeSegment {
+    k_cs: usize,     // 写入的 K 值 (厘秒)
+    exact_ms: usize, // 取整前的精确时长 (毫秒)
+    text: String,    // 分段文本 (停顿为空)
+    tags: String,    // 与 K 标签写在同一覆盖块中的其他标签
+}
+
+/// 根据行内音节生成 ASS {\k} 分段。
+/// 音节之间、行首与行尾的时间间隙会生成文本为空的停顿分段。
+///
+/// 使用 `RoundingStrategy::Cumulative` 时，对每个音节边界的绝对位置取整 (而不是对每段时长单独取整)，
+/// 并将其限制在取整后的行起止时间之内，因此所有 K 值之和恰好等于 End - Start。
 ///
 /// # Arguments
-/// * `current` - 当前已处理的大小（例如字节数）。
-/// * `total` - 文件总大小。
-fn display_progress_bar(current: usize, total: usize) {
-    // 如果总大小为 0 或小于预设阈值，则不显示进度条
-    if total == 0 || total < PROGRESS_BAR_THRESHOLD { return; }
+/// * `keep_empty` - 是否保留 K 值为 0 的停顿 (精确毫秒模式下需要保留，以免丢失不足 1 厘秒的间隙)。
+fn build_ass_karaoke_segments(line: &LyricLine, rounding: RoundingStrategy, keep_empty: bool) -> Vec<KaraokeSegment> {
+    let mut segments = Vec::new();
+    let line_end_cs = rounding.to_centiseconds(line.end_ms);
+    let mut last_end_ms = line.start_ms; // 跟踪上一个音节的结束时间, 初始化为行开始时间
+    let mut last_end_cs = rounding.to_centiseconds(line.start_ms); // 上一个音节结束位置 (厘秒，仅累计取整使用)
+
+    // 停顿分段只有在 K 值大于 0 (或精确模式下有实际时长) 时才写入
+    let push_gap = |segments: &mut Vec<KaraokeSegment>, k_cs: usize, exact_ms: usize| {
+        if k_cs > 0 || (keep_empty && exact_ms > 0) {
+            segments.push(KaraokeSegment { k_cs, exact_ms, text: String::new(), tags: String::new() });
+        }
+    };
 
-    // 计算当前进度百分比 (限制在 0.0 到 100.0 之间)
-    let percentage = (current as f64 / total as f64 * 100.0).min(100.0);
-    // 根据百分比计算进度条中已填充部分的长度
-    let filled_length = (PROGRESS_BAR_LENGTH as f64 * percentage / 100.0) as usize;
-    // 构建进度条的可视化字符串 (例如 "[=======      ]")
-    let bar = format!(
-        "{}{}", // 由填充部分和空白部分组成
-        "=".repeat(filled_length), // 填充部分用 "=" 表示
-        " ".repeat(PROGRESS_BAR_LENGTH.saturating_sub(filled_length)) // 空白部分用 " " 表示 (使用 saturating_sub 防止负数)
-    );
-    // 使用 \r (回车符) 将光标移到行首，实现原地更新进度条
-    // 打印格式: "转换进度: [========>     ]  XX% (current/total)"
-    print!("\r转换进度: [{}] {:>3.0}% ({}/{})", bar, percentage, current, total);
-    // 刷新标准输出流，确保进度条立即显示出来
-    let _ = io::stdout().flush(); // 忽略 flush 可能产生的错误
+    for syllable in &line.syllables {
+        let gap_ms = syllable.start_ms.saturating_sub(last_end_ms);
+        // 计算停顿和当前音节的 K 值
+        let (gap_k_value, k_value) = if rounding == RoundingStrategy::Cumulative {
+            let start_cs = rounding.to_centiseconds(syllable.start_ms).clamp(last_end_cs, line_end_cs.max(last_end_cs));
+            let end_cs = rounding.to_centiseconds(syllable.end_ms()).clamp(start_cs, line_end_cs.max(start_cs));
+            let values = (start_cs - last_end_cs, end_cs - start_cs);
+            last_end_cs = end_cs;
+            values
+        } else {
+            // 与上个音节结尾的时间差和音节时长分别单独取整
+            (rounding.to_centiseconds(gap_ms), rounding.to_centiseconds(syllable.duration_ms))
+        };
+        last_end_ms = syllable.end_ms();
+
+        push_gap(&mut segments, gap_k_value, gap_ms);
+        // K=0 且没有文本的音节没有任何信息，直接忽略
+        if k_value > 0 || !syllable.text.is_empty() || (keep_empty && syllable.duration_ms > 0) {
+            segments.push(KaraokeSegment {
+                k_cs: k_value,
+                exact_ms: syllable.duration_ms,
+                text: syllable.karaoke_text(),
+                tags: syllable.ass_tags.clone(),
+            });
+        }
+    }
+
+    // 行尾的时间间隙 (最后一个音节结束后行仍未结束)
+    let tail_ms = line.end_ms.saturating_sub(last_end_ms);
+    let tail_k_value = if rounding == RoundingStrategy::Cumulative {
+        line_end_cs.saturating_sub(last_end_cs)
+    } else {
+        rounding.to_centiseconds(tail_ms)
+    };
+    push_gap(&mut segments, tail_k_value, tail_ms);
+
+    segments
 }
 
 
-/// 将毫秒数转换为 ASS 时间格式字符串 (H:MM:SS.cs)。
-fn milliseconds_to_time(ms: usize) -> String {
-    // 计算小时、分钟、秒和厘秒
-    let hours = ms / MILLISECONDS_PER_HOUR;
-    let minutes = (ms % MILLISECONDS_PER_HOUR) / MILLISECONDS_PER_MINUTE;
-    let seconds = (ms % MILLISECONDS_PER_MINUTE) / MILLISECONDS_PER_SECOND;
-    // 厘秒 = 毫秒部分 / 10
-    let centiseconds = (ms % MILLISECONDS_PER_SECOND) / CENTISECONDS_TO_MILLISECONDS;
+// --- 精确毫秒时间 (Effect 字段) ---
 
-    // 格式化为 "H:MM:SS.cs" 字符串，注意使用 {:0X} 进行零填充
-    format!("{:01}:{:02}:{:02}.{:02}", hours, minutes, seconds, centiseconds)
+/// 生成嵌入到 ASS Effect 字段的精确时间记录。
+/// 格式: `x-ms:开始cs=开始ms/结束cs=结束ms/K1=时长1ms/K2=时长2ms/...`，
+/// 每一项同时记录写入的厘秒值和取整前的毫秒值，读取时只还原厘秒值未被修改的项。
+/// (Effect 字段不能包含逗号，因此使用 '/' 和 '=' 分隔。)
+fn format_exact_timing_effect(start_cs: usize, start_ms: usize, end_cs: usize, end_ms: usize, segments: &[KaraokeSegment]) -> String {
+    let mut effect = format!("{}{}={}/{}={}", EXACT_TIMING_EFFECT_PREFIX, start_cs, start_ms, end_cs, end_ms);
+    for segment in segments {
+        effect.push_str(&format!("/{}={}", segment.k_cs, segment.exact_ms));
+    }
+    effect
 }
 
-/// 将 ASS 时间格式字符串 (H:MM:SS.cs) 转换为毫秒数。
-fn time_to_milliseconds(time_str: &str) -> Result<usize, ConversionError> {
-    // 使用 ':' 和 '.' 作为分隔符分割时间字符串
-    let parts: Vec<&str> = time_str.split(&[':', '.'][..]).collect();
-    // ASS 时间格式必须有 4 个部分 (H, MM, SS, cs)
-    if parts.len() != 4 {
-        return Err(ConversionError::InvalidFormat(format!(
-            "无效 ASS 时间格式: '{}', 期望 H:MM:SS.cs", time_str
-        )));
+/// 解析 Effect 字段中的精确时间记录。
+///
+/// # Returns
+/// * `Some(Vec<(usize, usize)>)` - (厘秒值, 毫秒值) 列表，前两项为行开始和结束时间，其余为各 K 分段。
+/// * `None` - 如果 Effect 不是精确时间记录或格式无效。
+fn parse_exact_timing_effect(effect: &str) -> Option<Vec<(usize, usize)>> {
+    let payload = effect.trim().strip_prefix(EXACT_TIMING_EFFECT_PREFIX)?;
+    let entries = payload
+        .split('/')
+        .map(|entry| {
+            let (cs_str, ms_str) = entry.split_once('=')?;
+            Some((cs_str.parse().ok()?, ms_str.parse().ok()?))
+        })
+        .collect::<Option<Vec<(usize, usize)>>>()?;
+    // 至少需要包含行开始和结束时间
+    (entries.len() >= 2).then_some(entries)
+}
+
+/// 根据 Effect 中的精确时间记录还原行和音节的毫秒时间。
+/// 只有厘秒值与记录一致 (即在 Aegisub 中未被修改) 的时间才会被还原；
+/// 如果 K 分段的数量与记录不一致 (例如行被拆分或合并)，则只还原行起止时间。
+///
+/// # Arguments
+/// * `line` - 已按 K 标签解析好的行 (音节时间由厘秒值计算)。
+/// * `k_values` - 每个音节对应的 K 值 (厘秒)。
+/// * `entries` - `parse_exact_timing_effect` 的解析结果。
+fn restore_exact_timing(line: &mut LyricLine, k_values: &[usize], entries: &[(usize, usize)]) {
+    let (start_cs, start_ms) = entries[0];
+    let (end_cs, end_ms) = entries[1];
+    if line.start_ms == start_cs * CENTISECONDS_TO_MILLISECONDS {
+        line.start_ms = start_ms;
+    }
+    if line.end_ms == end_cs * CENTISECONDS_TO_MILLISECONDS {
+        line.end_ms = end_ms;
     }
 
-    // 解析每个部分为数字，并乘以相应的毫秒转换系数
-    // 使用 `?` 操作符处理 ParseIntError，它会自动通过 From trait 转换为 ConversionError
-    let h = parts[0].parse::<usize>()? * MILLISECONDS_PER_HOUR;
-    let m = parts[1].parse::<usize>()? * MILLISECONDS_PER_MINUTE;
-    let s = parts[2].parse::<usize>()? * MILLISECONDS_PER_SECOND;
-    let cs = parts[3].parse::<usize>()? * CENTISECONDS_TO_MILLISECONDS;
+    let segment_entries = &entries[2..];
+    let segments_match = segment_entries.len() == k_values.len();
+    if !segments_match {
+        log_warn!("第 {} 行的 K 分段数量与 Effect 中的精确时间记录不一致，仅按厘秒处理音节时间。", line.line_number);
+    }
 
-    // 返回各部分毫秒数之和
-    Ok(h + m + s + cs)
+    // 从 (可能已还原的) 行开始时间重新累加音节的绝对开始时间
+    let mut current_start_ms = line.start_ms;
+    for (index, syllable) in line.syllables.iter_mut().enumerate() {
+        if segments_match
+            && let Some(&(recorded_cs, recorded_ms)) = segment_entries.get(index)
+            && recorded_cs == k_values[index]
+        {
+            syllable.duration_ms = recorded_ms;
+        }
+        syllable.start_ms = current_start_ms;
+        current_start_ms += syllable.duration_ms;
+    }
 }
 
-/// 检查 ASS 文件中是否存在被视为“特殊”的 Name 字段的 Dialogue 行。
-/// “特殊”包括：空、"v1"、"左"、"右"、"v2"、"x-duet"、"x-anti"、"背"、"x-bg"。
-/// 用于自动模式判断 ASS 文件应转为 LYS (如果包含特殊名) 还是 QRC。
-fn check_ass_has_special_names(ass_path: &Path) -> Result<bool, ConversionError> {
-    let file = File::open(ass_path)?;
-    let reader = BufReader::new(file);
-    let mut after_format = false; // 标记是否已找到 [Events] 段的 Format 行
 
-    for line_result in reader.lines() {
-        let line = line_result?;
+// --- 时长修复 ---
 
-        // 首先定位到 Format 行
-        if !after_format {
-            if line.trim_start().starts_with("Format: Layer, Start, End, Style, Name,") {
-                after_format = true;
-            }
+/// 修复行时长与音节时长不一致的行，并逐行打印修改内容。
+/// 当最后一个音节的结束时间 (对于 ASS 即 Start + K 标签总和) 与行结束时间不同时视为不一致；
+/// `Tighten` 策略则会收紧所有首尾带有停顿或与音节边界不符的行。
+/// `Pad` 策略遇到最后一个音节开始于行结束之后的行时，改为按 `Stretch` 修改行结束时间。
+/// 翻译和罗马音行以及没有音节的行不会被修改。
+///
+/// # Returns
+/// * `usize` - 被修改的行数。
+fn fix_line_timing(lyrics: &mut Lyrics, strategy: TimingFixStrategy) -> usize {
+    let mut fixed_count = 0;
+    // 把行结束时间改为最后一个音节的结束时间 (Stretch 策略，Pad 无法修复的行也使用)
+    let stretch = |line: &mut LyricLine, last_end_ms: usize| {
+        let old_end_ms = line.end_ms;
+        line.end_ms = last_end_ms;
+        format!("行结束时间 {} -> {} ms ({:+} ms)", old_end_ms, line.end_ms, signed_difference(line.end_ms, old_end_ms))
+    };
+
+    for line in &mut lyrics.lines {
+        if is_auxiliary_style(&line.style) {
             continue;
         }
+        let Some(last_end_ms) = line.syllables.last().map(Syllable::end_ms) else {
+            continue;
+        };
+        if strategy != TimingFixStrategy::Tighten && last_end_ms == line.end_ms {
+            continue; // 时长一致，无需修复
+        }
 
-        // 在 Format 行之后，处理 Dialogue 行
-        if line.starts_with("Dialogue:") { // 快速前缀检查
-            // 解析行以获取 Name 字段
-            if let Some(caps) = ASS_DIALOGUE_REGEX.captures(&line) {
-                // 尝试获取 "name" 捕获组
-                if let Some(name_match) = caps.name("name") {
-                    let name = name_match.as_str(); // 获取 Name 字符串
-
-                    // 使用 matches! 宏检查 name 是否是任何一个需要特殊处理的值
-                    if matches!(name,
-                        // LeftV1 组 (包含空字符串)
-                        "" | "v1" | "左" |
-                        // RightV2 组
-                        "右" | "v2" | "x-duet" | "x-anti" |
-                        // Background 组
-                        "背" | "x-bg"
-                    ) {
-                        return Ok(true); // 只要找到一个符合条件的 Name，就认为文件“特殊”，返回 true
-                    }
+        let change = match strategy {
+            TimingFixStrategy::Stretch => stretch(line, last_end_ms),
+            TimingFixStrategy::Pad if line.syllables.last().is_some_and(|s| s.start_ms >= line.end_ms) => {
+                // 最后一个音节开始于行结束时间或之后，截短也无法使其结束于行结束时间
+                log_warn!("第 {} 行最后一个音节开始于行结束之后，改为修改行结束时间。", line.line_number);
+                stretch(line, last_end_ms)
+            }
+            TimingFixStrategy::Pad => {
+                let last_syllable = line.syllables.last_mut().unwrap();
+                let old_duration_ms = last_syllable.duration_ms;
+                last_syllable.duration_ms = line.end_ms - last_syllable.start_ms;
+                format!(
+                    "最后一个音节时长 {} -> {} ms ({:+} ms)",
+                    old_duration_ms, last_syllable.duration_ms, signed_difference(last_syllable.duration_ms, old_duration_ms)
+                )
+            }
+            TimingFixStrategy::Scale => {
+                let old_span_ms = last_end_ms.saturating_sub(line.start_ms);
+                let new_span_ms = line.duration_ms();
+                if old_span_ms == 0 {
+                    continue; // 所有音节都没有时长，无法缩放
                 }
-                // 如果正则匹配但没找到 name 组（理论上不应发生），继续检查下一行
+                // 以行开始时间为原点，按比例缩放每个音节的起止位置 (四舍五入)
+                let scale = |ms: usize| {
+                    let offset = ms.saturating_sub(line.start_ms) as u128;
+                    line.start_ms + ((offset * new_span_ms as u128 + old_span_ms as u128 / 2) / old_span_ms as u128) as usize
+                };
+                for syllable in &mut line.syllables {
+                    let (new_start_ms, new_end_ms) = (scale(syllable.start_ms), scale(syllable.end_ms()));
+                    syllable.start_ms = new_start_ms;
+                    syllable.duration_ms = new_end_ms.saturating_sub(new_start_ms);
+                }
+                format!("所有音节按 {:.3} 倍缩放 ({} -> {} ms)", new_span_ms as f64 / old_span_ms as f64, old_span_ms, new_span_ms)
             }
-            // 如果行不匹配正则，也继续检查下一行
-        }
-    } // 文件读取结束
+            TimingFixStrategy::Tighten => {
+                // 行首和行尾的停顿不算作歌词，收紧时一并移除
+                let Some(first_index) = line.syllables.iter().position(|s| !s.is_gap) else {
+                    continue;
+                };
+                let last_index = line.syllables.iter().rposition(|s| !s.is_gap).unwrap();
+                let (new_start_ms, new_end_ms) = (
+                    line.syllables[first_index].start_ms,
+                    line.syllables[last_index].end_ms(),
+                );
+                if (new_start_ms, new_end_ms) == (line.start_ms, line.end_ms) {
+                    continue; // 行边界已与音节一致
+                }
+                line.syllables.truncate(last_index + 1);
+                line.syllables.drain(..first_index);
 
-    // 遍历完所有行都没有找到任何特殊 Name，则返回 false
-    Ok(false)
+                let mut changes = Vec::new();
+                if new_start_ms != line.start_ms {
+                    changes.push(format!("行开始时间 {} -> {} ms ({:+} ms)", line.start_ms, new_start_ms, signed_difference(new_start_ms, line.start_ms)));
+                }
+                if new_end_ms != line.end_ms {
+                    changes.push(format!("行结束时间 {} -> {} ms ({:+} ms)", line.end_ms, new_end_ms, signed_difference(new_end_ms, line.end_ms)));
+                }
+                line.start_ms = new_start_ms;
+                line.end_ms = new_end_ms;
+                changes.join("，")
+            }
+        };
+        log_info!("已修复第 {} 行: {}", line.line_number, change);
+        fixed_count += 1;
+    }
+    fixed_count
 }
 
-/// 检查 ASS Dialogue 行定义的总时长与内部所有 {\k} 标签计算出的时长之和是否一致。
-///
-/// # Arguments
-/// * `expected_duration` - Dialogue 行定义的结束时间减去开始时间 (毫秒)。
-/// * `actual_duration` - 从行内所有 {\kX} 标签计算出的 X * 10 的总和 (毫秒)。
-/// * `line_number` - 当前处理的 Dialogue 在原始文件中的行号 (用于日志)。
-///
-/// # Returns
-/// * `true` - 如果时长一致。
-/// * `false` - 如果时长不一致，并会打印警告信息。
-fn check_time_consistency(expected_duration: usize, actual_duration: usize, line_number: usize) -> bool {
-    // 比较期望时长和实际计算时长
-    if expected_duration != actual_duration {
-        // 如果不一致，使用 log_warn! 宏打印带颜色的警告信息
-        log_warn!(
-            "第 {} 行 K tags 时间总和 {}{}{} ms 与行定义持续时间 {}{}{} ms 不匹配",
-            line_number,
-            RED, actual_duration, RESET, // K 标签总和用红色显示
-            GREEN, expected_duration, RESET // 行定义持续时间用绿色显示
-        );
-        return false; // 返回 false 表示时间不一致
-    }
-    true // 时间一致，返回 true
+/// 计算 `a - b` 的有符号差值 (毫秒)，用于报告修改量。
+fn signed_difference(a: usize, b: usize) -> i64 {
+    a as i64 - b as i64
 }
 
 
-/// 解析 ASS 元数据 Comment 行的文本部分 (例如 "musicName:歌曲名")
-/// 并将其转换为 LRC 风格的元数据标签 (例如 "[ti:歌曲名]")。
-///
-/// # Arguments
-/// * `text` - 从 META_COMMENT_REGEX 捕获到的 Comment 文本内容。
-///
-/// # Returns
-/// * `Some(String)` - 如果成功解析并映射，返回格式化后的标签字符串。
-/// * `None` - 如果文本格式不符、键不认识或值为空。
-fn parse_ass_metadata_text(text: &str) -> Option<String> {
-    // 查找文本中第一个冒号 ":" 的位置
-    if let Some(colon_pos) = text.find(':') {
-        // 提取冒号前后的部分作为键 (key) 和值 (value)，并去除首尾空格
-        let key = text[..colon_pos].trim();
-        let value = text[colon_pos + 1..].trim();
-        // 根据 ASS 中的键，映射到 LRC 标准或常用的标签
-        let tag = match key { // 直接匹配 &str 类型的 key
-            "musicName" => "ti", // Title / 歌曲名
-            "artists" => "ar", // Artist / 艺术家
-            "album" => "al", // Album / 专辑
-            "ttmlAuthorGithubLogin" => "by", // Editor / 编辑者 (这里使用了特定的键)
-            _ => return None, // 如果键无法识别，则忽略此元数据行
-        };
-        // 确保值不为空
-        if !value.is_empty() {
-            // 格式化为 "[标签:值]" 的字符串并返回
-            return Some(format!("[{}:{}]", tag, value));
+// --- 时间扭曲 ---
+
+impl TimeWarp {
+    /// 根据锚点列表创建时间扭曲。
+    /// 锚点会按旧时间排序；旧时间重复或新时间倒退时返回错误。
+    fn new(mut anchors: Vec<(usize, usize)>) -> Result<Self, ConversionError> {
+        if anchors.is_empty() {
+            return Err(ConversionError::InvalidFormat("时间扭曲至少需要一个锚点".to_string()));
+        }
+        anchors.sort_by_key(|&(old_ms, _)| old_ms);
+        for pair in anchors.windows(2) {
+            let ((old_a, new_a), (old_b, new_b)) = (pair[0], pair[1]);
+            if old_a == old_b {
+                return Err(ConversionError::InvalidFormat(format!("锚点的旧时间 {} ms 重复", old_a)));
+            }
+            if new_b < new_a {
+                return Err(ConversionError::InvalidFormat(format!(
+                    "锚点 {}->{} 与 {}->{} 会使时间倒退", old_a, new_a, old_b, new_b
+                )));
+            }
         }
+        Ok(TimeWarp { anchors })
+    }
+
+    /// 解析单个锚点字符串，例如 "29264->30000"、"29264=30000" 或 "0:00:29.26->0:00:30.00"。
+    fn parse_anchor(text: &str) -> Result<(usize, usize), ConversionError> {
+        let (old_str, new_str) = text
+            .split_once("->")
+            .or_else(|| text.split_once('='))
+            .ok_or_else(|| ConversionError::InvalidFormat(format!(
+                "无效的锚点 '{}'，期望格式为 旧时间->新时间", text
+            )))?;
+        Ok((parse_anchor_time(old_str.trim())?, parse_anchor_time(new_str.trim())?))
+    }
+
+    /// 从锚点文件读取时间扭曲。
+    /// 每行一个锚点，空行和以 '#' 开头的行会被忽略。
+    fn from_file(path: &Path) -> Result<Vec<(usize, usize)>, ConversionError> {
+        let reader = BufReader::new(File::open(path)?);
+        let mut anchors = Vec::new();
+        for (index, line_result) in reader.lines().enumerate() {
+            let line = line_result?;
+            let trimmed = line.trim();
+            if trimmed.is_empty() || trimmed.starts_with('#') {
+                continue;
+            }
+            let anchor = Self::parse_anchor(trimmed).map_err(|e| {
+                ConversionError::InvalidFormat(format!("锚点文件第 {} 行: {}", index + 1, e))
+            })?;
+            anchors.push(anchor);
+        }
+        Ok(anchors)
+    }
+
+    /// 创建整体平移 `offset_ms` 毫秒的时间扭曲 (只有一个锚点，负值表示提前，结果不会小于 0)。
+    fn from_offset(offset_ms: i64) -> Self {
+        let anchor = if offset_ms >= 0 { (0, offset_ms as usize) } else { (offset_ms.unsigned_abs() as usize, 0) };
+        TimeWarp { anchors: vec![anchor] }
+    }
+
+    /// 将旧时间映射为新时间 (毫秒)。
+    fn map(&self, ms: usize) -> usize {
+        let (first_old, first_new) = self.anchors[0];
+        let (last_old, last_new) = self.anchors[self.anchors.len() - 1];
+        // 锚点范围之外按最近锚点的恒定偏移平移
+        if ms <= first_old {
+            return (first_new + ms).saturating_sub(first_old);
+        }
+        if ms >= last_old {
+            return (last_new + ms).saturating_sub(last_old);
+        }
+        // 找到 ms 所在的锚点区间并做线性插值 (四舍五入)
+        let segment_index = self.anchors.partition_point(|&(old_ms, _)| old_ms <= ms) - 1;
+        let (old_a, new_a) = self.anchors[segment_index];
+        let (old_b, new_b) = self.anchors[segment_index + 1];
+        let old_span = (old_b - old_a) as u128;
+        let offset = ((ms - old_a) as u128 * (new_b - new_a) as u128 + old_span / 2) / old_span;
+        new_a + offset as usize
+    }
+
+    /// 返回与给定时间范围重叠的被删除区间 (旧时间)，即新时间长度为 0 的锚点区间。
+    fn removed_region_overlapping(&self, start_ms: usize, end_ms: usize) -> Option<(usize, usize)> {
+        self.anchors
+            .windows(2)
+            .map(|pair| (pair[0], pair[1]))
+            .find(|&((old_a, new_a), (old_b, new_b))| new_a == new_b && start_ms < old_b && end_ms > old_a)
+            .map(|((old_a, _), (old_b, _))| (old_a, old_b))
     }
-    // 如果没有找到冒号，或者值为空，则返回 None
-    None
 }
 
+/// 解析锚点中的时间：纯数字按毫秒处理，否则按 ASS 时间格式 (H:MM:SS.cs) 处理。
+fn parse_anchor_time(text: &str) -> Result<usize, ConversionError> {
+    if text.chars().all(|c| c.is_ascii_digit()) {
+        Ok(text.parse()?)
+    } else {
+        time_to_milliseconds(text)
+    }
+}
 
-/// 核心辅助函数：解析单行 ASS Dialogue 字符串，提取所有关键信息存入 `ParsedDialogue` 结构体。
-///
-/// # Arguments
-/// * `line` - 要解析的 ASS Dialogue 行字符串。
-/// * `line_number` - 该行在原始文件中的行号 (用于错误报告)。
+/// 对歌词模型中的所有行和音节时间应用时间扭曲。
+/// 落在被删除区间内的行会被保留 (时间被压缩)，但会打印警告。
 ///
 /// # Returns
-/// * `Ok(Some(ParsedDialogue))` - 如果成功解析。
-/// * `Ok(None)` - 如果该行不是有效的 Dialogue 行格式。
-/// * `Err(ConversionError)` - 如果解析过程中发生错误 (例如时间格式错误、数字解析错误)。
-fn parse_ass_dialogue_line(line: &str, line_number: usize) -> Result<Option<ParsedDialogue>, ConversionError> {
-    // 1. 尝试匹配整行结构
-    if let Some(caps) = ASS_DIALOGUE_REGEX.captures(line) {
-        // 2. 从命名捕获组提取时间字符串
-        // 使用 .get().map_or() 或 unwrap() - 这里假设匹配成功则必然存在这些组
-        let start_time_str = caps.name("start_time").unwrap().as_str();
-        let end_time_str   = caps.name("end_time").unwrap().as_str();
-
-        // 3. 转换时间字符串为毫秒数
-        let start_ms = time_to_milliseconds(start_time_str)
-            .map_err(|e| ConversionError::InvalidFormat(format!("第 {} 行对话开始时间解析失败: {}", line_number, e)))?;
-        let end_ms = time_to_milliseconds(end_time_str)
-             .map_err(|e| ConversionError::InvalidFormat(format!("第 {} 行对话结束时间解析失败: {}", line_number, e)))?;
-        let duration_ms = end_ms.saturating_sub(start_ms); // 计算行总持续时间
-
-        let style = caps.name("style").unwrap().as_str().trim().to_string(); // 获取 Style 并 trim
-
-        // 4. 提取 Name 字段内容
-        let name_str = caps.name("name").unwrap().as_str();
-        // 如果 Name 字段不为空，则存入 Some(String)，否则为 None
-        let name: Option<String> = if name_str.is_empty() {
-            None
-        } else {
-            Some(name_str.to_string())
-        };
+/// * `bool` - 是否有行落在被删除区间内。
+fn apply_time_warp(lyrics: &mut Lyrics, warp: &TimeWarp) -> bool {
+    let mut warning_occurred = false;
+    for line in &mut lyrics.lines {
+        if let Some((removed_start, removed_end)) = warp.removed_region_overlapping(line.start_ms, line.end_ms) {
+            log_warn!(
+                "第 {} 行 ({} - {} ms) 落在被删除的区间 {} - {} ms 内，请检查转换结果。",
+                line.line_number, line.start_ms, line.end_ms, removed_start, removed_end
+            );
+            warning_occurred = true;
+        }
+        line.start_ms = warp.map(line.start_ms);
+        line.end_ms = warp.map(line.end_ms);
+        for syllable in &mut line.syllables {
+            let new_start_ms = warp.map(syllable.start_ms);
+            let new_end_ms = warp.map(syllable.end_ms());
+            syllable.start_ms = new_start_ms;
+            syllable.duration_ms = new_end_ms.saturating_sub(new_start_ms);
+        }
+    }
+    warning_occurred
+}
 
-        // 5. 提取 Text 字段内容
-        let ass_text = caps.name("text").unwrap().as_str();
 
-        // 6. 解析 Text 字段中的 {\k} 标签和对应的文本段
-        let mut segments = Vec::new();
-        let mut sum_k_ms = 0;
-        for k_cap in K_TAG_REGEX.captures_iter(ass_text) {
-            let k_cs_str = k_cap.get(1).unwrap().as_str();
-            let k_cs: usize = k_cs_str.parse()
-                 .map_err(|e| ConversionError::InvalidFormat(format!("第 {} 行 K 数值解析失败 ('{}'): {}", line_number, k_cs_str, e)))?;
-            let seg_text = k_cap.get(2).unwrap().as_str().to_string();
-            let seg_ms = k_cs * K_TAG_MULTIPLIER;
+// --- 对唱方向推断 (--infer-duet) ---
 
-            sum_k_ms += seg_ms;
-            segments.push((seg_text, seg_ms));
-        }
+/// ASS Dialogue 行的定位信息 (行内标签和非零 Margin 覆盖 Style 中的值)，读取 ASS 时随行一起解析，用于推断对唱方向。
+#[derive(Debug, Clone, Default)]
+struct DialoguePosition {
+    alignment: Option<(u8, String)>, // 对齐值 (小键盘布局 1-9) 及其来源
+    margin_l: i32,
+    margin_r: i32,
+    margin_v: i32,
+    colour: Option<String>, // 主颜色 (BGR 十六进制，大写)
+    font_size: Option<u32>, // 字号 (取整)
+}
 
-        // 7. 构建并返回 ParsedDialogue 结构体
-        return Ok(Some(ParsedDialogue {
-            line_number,
-            start_ms,
-            name,
-            segments,
-            duration_ms,
-            sum_k_ms,
-            style,
-        }));
-    } // end if let Some(caps) = ASS_DIALOGUE_REGEX.captures(line)
+/// ASS Style 中与定位有关的字段，读取 ASS 时从 [V4+ Styles] 段解析。
+#[derive(Debug, Clone, Default)]
+struct StylePosition {
+    alignment: Option<u8>,
+    margin_l: i32,
+    margin_r: i32,
+    margin_v: i32,
+    colour: Option<String>,
+    font_size: Option<u32>,
+}
 
-    // 如果行不匹配 ASS_DIALOGUE_REGEX，则认为不是有效的 Dialogue 行
-    Ok(None)
+/// 规范化 ASS 颜色值 (去掉 &H 前缀和 & 后缀，转为大写，只保留 BGR 部分)。
+fn normalize_ass_colour(colour: &str) -> Option<String> {
+    let hex = colour.trim().trim_start_matches("&H").trim_start_matches("&h").trim_end_matches('&').to_uppercase();
+    if hex.is_empty() || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    // &HAABBGGRR 中的 AA 是透明度，不参与比较
+    Some(hex[hex.len().saturating_sub(6)..].to_string())
 }
 
+/// 解析 ASS 字号 (可以带小数)，取整后返回。
+fn parse_font_size(text: &str) -> Option<u32> {
+    text.trim().parse::<f64>().ok().filter(|size| size.is_finite() && *size >= 0.0).map(|size| size.round() as u32)
+}
 
-/// 辅助函数：处理单行 ASS Dialogue 到 QRC 格式行的转换逻辑。
+/// 解析 [V4+ Styles] 段中的 Style 行。
 ///
 /// # Arguments
-/// * `line` - 要转换的 ASS Dialogue 行字符串。
-/// * `line_number` - 该行在原始文件中的行号 (用于日志和错误报告)。
-/// * `warning_occurred` - 一个可变引用，用于标记是否在此行发生了时间不一致的警告。
+/// * `fields` - 该段 Format 行中的字段名 (小写)。
 ///
 /// # Returns
-/// * `Ok(Some(String))` - 如果成功转换，返回格式化后的 QRC 行字符串。
-/// * `Ok(None)` - 如果输入行不是有效的 Dialogue 或解析/转换失败但选择忽略。
-/// * `Err(ConversionError)` - 如果发生不可恢复的解析错误。
-fn process_dialogue_for_qrc(line: &str, line_number: usize, warning_occurred: &mut bool) -> Result<Option<String>, ConversionError> {
-    // 1. 调用统一的解析函数获取解析后的 Dialogue 数据
-    match parse_ass_dialogue_line(line, line_number)? {
-        Some(parsed_data) => { // 如果解析成功
-            // 2. 如果不是 roma/trans/ts 才进行时间一致性检查
-            if !(parsed_data.style.eq_ignore_ascii_case("roma")
-                 || parsed_data.style.eq_ignore_ascii_case("trans")
-                 || parsed_data.style.eq_ignore_ascii_case("ts"))
-            {
-                // 只有在样式不是 roma/trans/ts 时才调用检查函数
-                if !check_time_consistency(parsed_data.duration_ms, parsed_data.sum_k_ms, parsed_data.line_number) {
-                    *warning_occurred = true; // 设置警告标志
-                }
-            }
+/// * `Some((Style 名, StylePosition))` - 成功解析的样式；不是 Style 行或没有 Name 字段时返回 None。
+fn parse_ass_style_line(fields: &[String], line: &str) -> Option<(String, StylePosition)> {
+    let values: Vec<&str> = line.strip_prefix("Style:")?.splitn(fields.len().max(1), ',').map(str::trim).collect();
+    let field = |name: &str| fields.iter().position(|f| f == name).and_then(|i| values.get(i).copied());
+    let margin = |name: &str| field(name).and_then(|v| v.parse().ok()).unwrap_or(0);
+    let style = StylePosition {
+        alignment: field("alignment").and_then(|v| v.parse().ok()),
+        margin_l: margin("marginl"),
+        margin_r: margin("marginr"),
+        margin_v: margin("marginv"),
+        colour: field("primarycolour").and_then(normalize_ass_colour),
+        font_size: field("fontsize").and_then(parse_font_size),
+    };
+    Some((field("name")?.to_string(), style))
+}
 
-            // 3. 使用解析出的数据构建 QRC 格式的行字符串
-            // QRC 格式: [行开始ms,行持续ms]文本1(绝对开始ms,分段持续ms)文本2(绝对开始ms,分段持续ms)...
-            let mut qrc_line = format!("[{},{}]", parsed_data.start_ms, parsed_data.duration_ms);
-            let mut current_segment_start_ms = parsed_data.start_ms; // QRC 分段时间戳使用绝对开始时间
-
-            // 遍历解析出的文本段和时长
-            for (seg_text, seg_ms) in &parsed_data.segments {
-                let segment_duration_ms = *seg_ms; // 解引用得到时长值 (usize)
-                // 过滤掉无效的分段 (例如，文本为空且时长为 0)
-                if !seg_text.is_empty() || segment_duration_ms > 0 {
-                    // 拼接文本和对应的 (开始时间, 持续时间) 标签
-                    qrc_line.push_str(&format!("{}({},{})", seg_text, current_segment_start_ms, segment_duration_ms));
-                    // 更新下一个分段的理论开始时间
-                    current_segment_start_ms += segment_duration_ms;
-                }
-            }
-            // 返回构建好的 QRC 行字符串
-            Ok(Some(qrc_line))
+impl DialoguePosition {
+    /// 根据 Style、Dialogue 行的 Margin (0 表示使用 Style 中的值) 和 Text 中的覆盖标签确定行的定位信息。
+    /// 只使用顶层的标签，\t(...) 动画中的标签不计入。
+    fn from_dialogue(style_name: &str, style: Option<&StylePosition>, margins: [i32; 3], text: &str) -> Self {
+        let style = style.cloned().unwrap_or_default();
+        let margin = |line_margin: i32, style_margin: i32| if line_margin != 0 { line_margin } else { style_margin };
+        let tags = ass_override_tags(text);
+        let find_tag = |regex: &Regex| tags.iter().find_map(|tag| regex.captures(tag));
+        let alignment = match find_tag(&ALIGNMENT_TAG_REGEX) {
+            Some(caps) => Some((caps[1].parse().unwrap(), format!("\\an{}", &caps[1]))),
+            None => style.alignment.map(|alignment| (alignment, format!("样式 {} 对齐 {}", style_name, alignment))),
+        };
+        DialoguePosition {
+            alignment,
+            margin_l: margin(margins[0], style.margin_l),
+            margin_r: margin(margins[1], style.margin_r),
+            margin_v: margin(margins[2], style.margin_v),
+            colour: find_tag(&COLOUR_TAG_REGEX).and_then(|caps| normalize_ass_colour(&caps[1])).or(style.colour),
+            font_size: find_tag(&FONT_SIZE_TAG_REGEX).and_then(|caps| parse_font_size(&caps[1])).or(style.font_size),
         }
-        None => { // ASS_DIALOGUE_REGEX 未匹配但行以 "Dialogue:" 开头
-        log_warn!("第 {} 行看起来像 Dialogue 但未能通过 parse_ass_dialogue_line 解析。", line_number);
-        *warning_occurred = true;
-        Ok(None)
     }
-    } // end match parse_ass_dialogue_line
-}
 
+    /// 按对齐方式和 Margin 判断方向，返回方向及依据。
+    fn side(&self) -> Option<(DuetSide, String)> {
+        match &self.alignment {
+            Some((1 | 4 | 7, source)) => return Some((DuetSide::Left, source.clone())),
+            Some((3 | 6 | 9, source)) => return Some((DuetSide::Right, source.clone())),
+            _ => {}
+        }
+        if self.margin_l == self.margin_r {
+            return None;
+        }
+        let side = if self.margin_l > self.margin_r { DuetSide::Right } else { DuetSide::Left };
+        Some((side, format!("MarginL={} MarginR={}", self.margin_l, self.margin_r)))
+    }
 
-/// 核心辅助函数：计算当前 Dialogue 行对应的 LYS 属性值。
-///
-/// # Arguments
-/// * `current_dialogue` - 当前正在处理的、已解析的 Dialogue 数据。
-/// * `previous_dialogue` - 上一个已处理的 Dialogue 数据 (如果是第一行则为 None)。
-/// * `last_calculated_property` - 上一个 Dialogue 行最终计算得到的 LYS 属性值 (用于处理连续 '背' 的情况)。
-///
-/// # Returns
-/// * `usize` - 计算得到的当前行应使用的 LYS 属性常量值。
-fn calculate_lys_property(
-    current_dialogue: &ParsedDialogue,
-    previous_dialogue: Option<&ParsedDialogue>,
-    last_calculated_property: usize,
-) -> (usize, bool) {
-    // 获取当前行的 Name 字段分类，并捕获是否发生警告
-    let (current_category, map_warned) = map_ass_name_to_category(current_dialogue.name.as_deref());
+    /// 对齐值，没有指定时为默认的 2 (底部居中)。
+    fn alignment_value(&self) -> u8 {
+        self.alignment.as_ref().map_or(2, |(alignment, _)| *alignment).clamp(1, 9)
+    }
 
-    // 2. 根据当前行的分类决定 LYS 属性
-    let property = match current_category {
-        // LeftV1 分类 (包括空, v1, 左, None) -> 映射为无背景左对齐
-        AssNameCategory::LeftV1 => LYS_PROPERTY_NO_BACK_LEFT,
-        
-        // RightV2 分类 (包括右, v2, x-duet, x-anti) -> 映射为无背景右对齐
-        AssNameCategory::RightV2 => LYS_PROPERTY_NO_BACK_RIGHT,
+    /// 对齐方式所在的行: 0 为底部 (1-3)，1 为中间 (4-6)，2 为顶部 (7-9)。
+    fn row(&self) -> u8 {
+        (self.alignment_value() - 1) / 3
+    }
 
-        // Background 分类 (包括背, x-bg) -> 需要根据上一行决定具体属性
-        AssNameCategory::Background => {
-            // 获取上一行的 Name 字段分类 (如果不存在上一行，则视为 Other)
-             let previous_category = previous_dialogue
-                .map(|prev| map_ass_name_to_category(prev.name.as_deref()).0) // 只取类别，忽略内部警告（已由map_warned捕获）
-                .unwrap_or(AssNameCategory::Other); // 没有前一行时，默认前一行为 Other
+    /// 对齐方式所在的列: 0 为左，1 为居中，2 为右。
+    fn column(&self) -> usize {
+        usize::from((self.alignment_value() - 1) % 3)
+    }
+}
 
-            match previous_category {
-                AssNameCategory::LeftV1 => LYS_PROPERTY_BACK_LEFT, // 前一行是 LeftV1 -> 有背景左
-                AssNameCategory::RightV2 => LYS_PROPERTY_BACK_RIGHT, // 前一行是 RightV2 -> 有背景右
-                AssNameCategory::Background => last_calculated_property, // 前一行也是 Background -> 继承上次计算结果
-                AssNameCategory::Other => LYS_PROPERTY_BACK_UNSET, // 前一行是 Other -> 有背景未定左右
-            }
+/// 出现次数最多的值 (次数相同时取先出现的)，用于确定主歌词行的字号和位置。
+fn most_common<T: PartialEq>(values: impl Iterator<Item = T>) -> Option<T> {
+    let mut counts: Vec<(T, usize)> = Vec::new();
+    for value in values {
+        match counts.iter_mut().find(|(existing, _)| *existing == value) {
+            Some((_, count)) => *count += 1,
+            None => counts.push((value, 1)),
         }
-        
-        // Other 分类 -> 映射为未设置属性
-        AssNameCategory::Other => LYS_PROPERTY_UNSET,
-    };
-    (property, map_warned) // 返回计算的属性和map_ass_name_to_category的警告状态
+    }
+    let max_count = counts.iter().map(|(_, count)| *count).max()?;
+    counts.into_iter().find(|(_, count)| *count == max_count).map(|(value, _)| value)
 }
 
-/// 将从 ASS 解析出的 Name 字段 (Option<&str>) 映射到对应的内部逻辑分类 `AssNameCategory`。
-///
-/// 此函数旨在处理 ASS `Dialogue` 行中的 `Name` 字段，即使该字段包含多个由空格分隔的标签
-/// （例如 "左 itunes:song-part=..." 或 "v1 extra-tag"）。
-/// 它会分析 `Name` 字段的第一个词（"word"，按空格分割）是否为已知的类别关键字。
-///
-/// # Arguments
-/// * `name_opt` - 一个 `Option<&str>`，代表从 ASS 行解析出来的 `Name` 字段。
-///   - `None` 表示 ASS 行中没有 `Name` 字段。
-///   - `Some("")` 表示 `Name` 字段存在但为空。
-///   - `Some("左 anothertag")` 表示 `Name` 字段包含内容。
+/// 按定位推断 Name 为空的主歌词行的对唱方向和背景人声标记，并把结果写入 Name，同时列出所有推断结果。
 ///
-/// # Returns
-/// * `AssNameCategory` - 根据 `Name` 字段内容判断出的逻辑分类。
+/// * 对齐方式 (行内 \anN 优先于 Style) 为左列 (1/4/7) 或右列 (3/6/9) 时取对应方向；
+/// * 居中对齐时，MarginL 大于 MarginR 视为右，反之视为左；
+/// * 仍无法判断且这些行恰好使用两种颜色时，先出现的颜色为左，另一种为右；
+/// * 只有存在背景人声特征时才视为背景人声: Style 名含 "bg"/"back"，字号小于主歌词行，位置高于主歌词行
+///   (与同一列的主歌词行相比对齐在更高的行，或同为底部对齐时 MarginV 更大)，或使用只出现在与前一行重叠的行中的颜色。
+///   与前一个主歌词行时间重叠只在报告中标出，不单独作为背景人声的依据。
 ///
-/// # 逻辑优先级:
-/// 1. 如果 `name_opt` 是 `None` (无 Name 字段) 或 `Some("")` (Name 字段为空)，则归类为 `AssNameCategory::LeftV1`。
-/// 2. 否则，获取 `Name` 字段字符串，去除首尾空格。
-/// 3. 将处理后的字符串按空格分割，提取第一个词（`first_part`）。
-/// 4. 检查 `first_part` 是否匹配以下任一关键字组合：
-///    - "左" 或 "v1" -> `AssNameCategory::LeftV1`
-///    - "右" 或 "v2" 或 "x-duet" 或 "x-anti" -> `AssNameCategory::RightV2`
-///    - "背" 或 "x-bg" -> `AssNameCategory::Background`
-/// 5. 如果 `first_part` 不匹配任何已知关键字，则记录一条警告日志，并将该 `Name` 字段归类为 `AssNameCategory::Other`。
-///    这适用于如 "路人甲" 或其他非预定义 Actor 名称的情况。
-fn map_ass_name_to_category(name_opt: Option<&str>) -> (AssNameCategory, bool) {
-    match name_opt {
-        // 情况 1: Name 字段不存在或为空
-        None | Some("") => (AssNameCategory::LeftV1, false),
-        Some(name_str) => {
-            // 去除 Name 字段首尾的空格，以确保后续处理的准确性
-            let trimmed_name = name_str.trim();
-            
-            // 如果去除空格后为空字符串，也视为 LeftV1 (例如 Name 字段只包含空格)
-            if trimmed_name.is_empty() {
-                return (AssNameCategory::LeftV1, false);
-            }
-
-            // 按空白字符分割 Name 字段，以分析其组成部分，特别是第一个词。
-            // `.next()` 获取迭代器的第一个元素，即按空格分割后的第一个词。
-            if let Some(first_part) = trimmed_name.split_whitespace().next() {
-                // 情况 4: 检查第一个词是否为已定义的类别关键字
-                if matches!(first_part, "左" | "v1" | "合" | "v1000") {
-                    return (AssNameCategory::LeftV1, false);
-                }
-                if matches!(first_part, "右" | "v2" | "x-duet" | "x-anti") {
-                    return (AssNameCategory::RightV2, false);
-                }
-                if matches!(first_part, "背" | "x-bg") {
-                    return (AssNameCategory::Background, false);
-                }
+/// Name 按演唱者规则选取 (见 `singer_name_for`)，规则无法精确表达时附加 x-lys:<属性>。
+fn infer_duet_singers(lyrics: &mut Lyrics) {
+    let is_candidate = |line: &LyricLine| !is_auxiliary_style(&line.style) && line.name.is_none() && line.position.is_some();
+    let position_of = |line: &LyricLine| line.position.clone().unwrap_or_default();
+
+    // 主歌词行的字号取候选行中最常见的值；位置 (对齐所在的行和 MarginV) 按对齐的列分别取最常见的值，
+    // 以免把用 \an7/\an9 区分左右的对唱行当作位置升高
+    let candidates = || lyrics.lines.iter().filter(|line| is_candidate(line)).map(position_of);
+    let main_font_size = most_common(candidates().filter_map(|position| position.font_size));
+    let in_column = |column: usize| candidates().filter(move |position| position.column() == column);
+    let main_rows = [0, 1, 2].map(|column| most_common(in_column(column).map(|position| position.row())));
+    let main_margin_vs = [0, 1, 2].map(|column| most_common(in_column(column).map(|position| position.margin_v)));
+
+    // 第一遍: 按 Style、字号和位置判断背景人声，并记录与前一个非背景主歌词行的重叠
+    // 元素为 (行下标, 背景人声依据, 重叠的前一行行号)
+    let mut decisions: Vec<(usize, Option<String>, Option<usize>)> = Vec::new();
+    let mut previous_end: Option<(usize, usize)> = None; // 前一个非背景主歌词行的 (行号, 结束时间)
+    for (index, line) in lyrics.lines.iter().enumerate() {
+        if is_auxiliary_style(&line.style) {
+            continue;
+        }
+        let overlapping = previous_end.filter(|&(_, end_ms)| line.start_ms < end_ms).map(|(line_number, _)| line_number);
+        let background = if is_candidate(line) {
+            let position = position_of(line);
+            let style = line.style.to_lowercase();
+            let reason = if BACKGROUND_STYLE_KEYWORDS.iter().any(|keyword| style.contains(keyword)) {
+                Some(format!("背景样式 {}", line.style))
+            } else if let (Some(size), Some(main_size)) = (position.font_size, main_font_size)
+                && size < main_size
+            {
+                Some(format!("字号 {} 小于主歌词的 {}", size, main_size))
+            } else if main_rows[position.column()].is_some_and(|main_row| position.row() > main_row) {
+                Some(format!("对齐 {} 高于同列的主歌词", position.alignment_value()))
+            } else if let Some(main_margin_v) = main_margin_vs[position.column()]
+                && main_rows[position.column()] == Some(0)
+                && position.row() == 0
+                && position.margin_v > main_margin_v
+            {
+                Some(format!("MarginV={} 高于主歌词的 {}", position.margin_v, main_margin_v))
+            } else {
+                None
+            };
+            let is_background = reason.is_some();
+            decisions.push((index, reason, overlapping));
+            is_background
+        } else {
+            classify_line_singer(line).is_some_and(SingerInfo::is_background)
+        };
+        if !background && previous_end.is_none_or(|(_, end_ms)| line.end_ms > end_ms) {
+            previous_end = Some((line.line_number, line.end_ms));
+        }
+    }
+
+    // 第二遍: 只在与前一行重叠的行中出现的颜色视为背景人声的颜色
+    let colour_of = |index: usize| position_of(&lyrics.lines[index]).colour.clone();
+    let main_colours: Vec<String> = decisions
+        .iter()
+        .filter(|(_, reason, overlapping)| reason.is_none() && overlapping.is_none())
+        .filter_map(|&(index, _, _)| colour_of(index))
+        .collect();
+    for (index, reason, overlapping) in decisions.iter_mut() {
+        if reason.is_none()
+            && overlapping.is_some()
+            && let Some(colour) = colour_of(*index)
+            && !main_colours.contains(&colour)
+        {
+            *reason = Some(format!("颜色 &H{}& 只用于重叠的行", colour));
+        }
+    }
+
+    // 无法按定位判断方向的非背景行使用的颜色 (按出现顺序)，恰好两种时才作为依据
+    let mut colours: Vec<String> = Vec::new();
+    for (index, reason, _) in &decisions {
+        if reason.is_none()
+            && position_of(&lyrics.lines[*index]).side().is_none()
+            && let Some(colour) = colour_of(*index)
+            && !colours.contains(&colour)
+        {
+            colours.push(colour);
+        }
+    }
+    if colours.len() != 2 {
+        colours.clear();
+    }
+
+    let mut report: Vec<String> = Vec::new();
+    let mut flagged: Vec<String> = Vec::new(); // 与前一行重叠但没有背景人声特征的行
+    for (index, background, overlapping) in decisions {
+        let position = position_of(&lyrics.lines[index]);
+        let line = &mut lyrics.lines[index];
+        let mut reasons: Vec<String> = Vec::new();
+        let mut side = position.side().map(|(side, reason)| {
+            reasons.push(reason);
+            side
+        });
+        if side.is_none()
+            && background.is_none()
+            && let Some(colour) = &position.colour
+            && let Some(index) = colours.iter().position(|c| c == colour)
+        {
+            reasons.push(format!("颜色 &H{}&", colour));
+            side = Some(if index == 0 { DuetSide::Left } else { DuetSide::Right });
+        }
+        reasons.extend(background.clone());
+        if let Some(previous_line) = overlapping {
+            match background {
+                Some(_) => reasons.push(format!("与第 {} 行重叠", previous_line)),
+                None => flagged.push(format!("  第 {} 行 '{}': 与第 {} 行重叠", line.line_number, line.text(), previous_line)),
             }
-            // 情况 5: 如果 Name 字段的第一个词不匹配任何已知关键字
-            // (或者 Name 字段不包含任何非空白字符，这种情况已被 trimmed_name.is_empty() 捕获)
-            // 则记录警告并归类为 Other。
-            log_warn!(
-                "遇到未定义的 ASS Name 字段值 '{}'，将按默认方式处理。",
-                name_str // 记录原始的 name_str 以便调试
-            );
-            (AssNameCategory::Other, true) // 发生警告
         }
+        if reasons.is_empty() {
+            continue; // 没有任何依据，保持原样 (按空 Name 的规则处理)
+        }
+
+        let info = SingerInfo { side, background: Some(background.is_some()) };
+        let mut name = singer_name_for(info).unwrap_or_default();
+        if classify_singer(&line.style, Some(&name), line.effect.as_deref()) != Some(info) {
+            name = format!("{} {}{}", name, LYS_PROPERTY_NAME_PREFIX, info.lys_property()).trim().to_string();
+        }
+        report.push(format!("  第 {} 行 '{}': {} ({})", line.line_number, line.text(), name, reasons.join(", ")));
+        line.name = Some(name);
+    }
+
+    if report.is_empty() {
+        log_info!("--infer-duet: 没有可以按定位推断对唱方向的行。");
+    } else {
+        log_info!("--infer-duet: 按定位推断了 {} 行的对唱方向:\n{}", report.len(), report.join("\n"));
+    }
+    if !flagged.is_empty() {
+        log_warn!(
+            "--infer-duet: 以下 {} 行与前一行时间重叠，但没有背景人声特征 (样式、字号、位置或颜色)，未视为背景人声:\n{}",
+            flagged.len(),
+            flagged.join("\n")
+        );
     }
 }
 
-/// 移除字符串中所有 ASS 标签 (形如 {\...} 的部分)。
-fn strip_ass_tags(text: &str) -> String {
-    // 使用 ASS_TAG_REGEX 替换所有匹配项为空字符串
-    ASS_TAG_REGEX.replace_all(text, "").into_owned() // into_owned() 将 Cow<str> 转换为 String
+
+// --- 歌词校验 (validate 子命令) ---
+
+/// `validate` 子命令可用的检查规则。
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, ValueEnum)]
+enum ValidationRule {
+    /// ASS 行的 K 标签总和与 End - Start 不一致
+    KSumMismatch,
+    /// 主歌词行 (非翻译、罗马音、背景行) 之间的时间重叠
+    OverlappingLines,
+    /// 行内音节时间倒退或相互重叠
+    NonMonotonicSyllables,
+    /// 音节时间超出所在行的时间范围 (例如 QRC 逐字时间超出行首的 [开始,时长])
+    SyllableOutOfBounds,
+    /// 时长为 0 的歌词音节
+    ZeroSyllable,
+    /// 时长超过阈值 (--max-syllable-ms) 的歌词音节
+    LongSyllable,
+    /// 前面没有主歌词行的背景人声行 (背/x-bg)
+    OrphanBackground,
+    /// 无法识别的 Name 字段值
+    UnknownName,
+    /// 无法确定语言的翻译行 (Name 不是 x-lang:<语言代码>，且样式角色和配置都没有指定语言)
+    MissingLang,
 }
 
-/// 将毫秒数转换为 LRC 时间格式字符串 [mm:ss.xx] (注意 xx 是百分秒)。
-fn milliseconds_to_lrc_time(ms: usize) -> String {
-    let minutes = ms / MILLISECONDS_PER_MINUTE; // 计算分钟
-    let seconds = (ms % MILLISECONDS_PER_MINUTE) / MILLISECONDS_PER_SECOND; // 计算秒
-    // 计算百分秒 (毫秒除以 10)
-    let hundredths = (ms % MILLISECONDS_PER_SECOND) / 10;
-    // 格式化输出，MM:SS.xx，注意补零
-    format!("[{:02}:{:02}.{:02}]", minutes, seconds, hundredths)
+impl ValidationRule {
+    /// 规则在报告中显示的名称 (与命令行中使用的名称一致)。
+    fn name(self) -> String {
+        self.to_possible_value().map(|value| value.get_name().to_string()).unwrap_or_default()
+    }
+}
+
+/// 校验配置：启用的规则和各规则的阈值。
+#[derive(Debug, Clone)]
+struct ValidationConfig {
+    enabled_rules: Vec<ValidationRule>, // 启用的规则
+    max_syllable_ms: usize,             // LongSyllable 规则的音节时长上限 (毫秒)
+    overlap_tolerance_ms: usize,        // OverlappingLines 规则允许的重叠时长 (毫秒)
+}
+
+impl ValidationConfig {
+    fn is_enabled(&self, rule: ValidationRule) -> bool {
+        self.enabled_rules.contains(&rule)
+    }
+}
+
+/// 校验发现的单个问题。
+#[derive(Debug, Clone)]
+struct ValidationIssue {
+    rule: ValidationRule, // 触发的规则
+    line_number: usize,   // 问题所在的源文件行号
+    message: String,      // 问题描述
 }
 
-/// 从 ASS 文件中提取指定样式的翻译行，并按语言生成 LRC 文件。
+/// 按配置中启用的规则检查歌词模型，返回所有发现的问题 (按行号排序)。
 ///
 /// # Arguments
-/// * `ass_path` - 输入的 ASS 文件路径。
-///
-/// # Returns
-/// * `Ok(())` - 如果提取和写入成功（即使没有找到翻译行）。
-/// * `Err(ConversionError)` - 如果发生文件读取或写入错误。
-fn extract_translations_to_lrc(ass_path: &Path) -> Result<bool, ConversionError> { // 新签名
-    log_info!("开始从 {:?} 提取翻译...", ass_path.file_name().unwrap_or_default());
-    let mut warning_occurred_during_extraction = false;
+/// * `lyrics` - 要检查的歌词。
+/// * `format` - 歌词的源格式 (K 标签总和检查只适用于 ASS)。
+/// * `config` - 校验配置。
+fn validate_lyrics(lyrics: &Lyrics, format: LyricFormat, config: &ValidationConfig) -> Vec<ValidationIssue> {
+    let mut issues = Vec::new();
+    let mut report = |rule: ValidationRule, line_number: usize, message: String| {
+        if config.is_enabled(rule) {
+            issues.push(ValidationIssue { rule, line_number, message });
+        }
+    };
 
-    // 使用 HashMap 存储不同语言的 LRC 行数据
-    // Key: 语言代码 (String), Value: Vec<(开始时间ms, 纯文本)>
-    let mut translations: HashMap<String, Vec<(usize, String)>> = HashMap::new();
+    let mut main_line_seen = false; // 之前是否出现过主歌词行 (用于 OrphanBackground)
+    let mut last_main_line: Option<&LyricLine> = None; // 结束时间最晚的主歌词行 (用于 OverlappingLines)
+
+    for line in &lyrics.lines {
+        // --- 翻译和罗马音行只检查语言 ---
+        if is_auxiliary_style(&line.style) {
+            if line_role(&line.style, line.name.as_deref()) == StyleRole::Translation(None) {
+                report(
+                    ValidationRule::MissingLang,
+                    line.line_number,
+                    format!("翻译行的 Name '{}' 不是 x-lang:<语言代码> 形式，且没有配置默认语言", line.name.as_deref().unwrap_or("")),
+                );
+            }
+            continue;
+        }
 
-    // --- 读取和解析 ASS 文件 ---
-    let file = File::open(ass_path)?;
-    let reader = BufReader::new(file);
-    let mut after_format = false;
-    let mut line_number = 0;
+        // --- 行级检查 ---
+        if format == LyricFormat::Ass && line.duration_ms() != line.sum_syllables_ms() {
+            report(
+                ValidationRule::KSumMismatch,
+                line.line_number,
+                format!("K 标签总和 {} ms 与行持续时间 {} ms 不一致", line.sum_syllables_ms(), line.duration_ms()),
+            );
+        }
 
-    for line_result in reader.lines() {
-        line_number += 1;
-        let line = line_result?;
+        let singer = classify_line_singer(line);
+        if singer.is_none() {
+            report(
+                ValidationRule::UnknownName,
+                line.line_number,
+                format!("无法识别的 Name 字段值 '{}'", line.name.as_deref().unwrap_or("")),
+            );
+        }
 
-        // 定位到 Format 行
-        if !after_format {
-            if line.trim_start().starts_with("Format: Layer, Start, End, Style, Name,") {
-                after_format = true;
+        if singer.is_some_and(SingerInfo::is_background) {
+            if !main_line_seen {
+                report(ValidationRule::OrphanBackground, line.line_number, "背景人声行之前没有主歌词行".to_string());
+            }
+        } else {
+            main_line_seen = true;
+            if let Some(previous) = last_main_line
+                && line.start_ms + config.overlap_tolerance_ms < previous.end_ms
+            {
+                report(
+                    ValidationRule::OverlappingLines,
+                    line.line_number,
+                    format!(
+                        "与第 {} 行重叠 {} ms",
+                        previous.line_number,
+                        previous.end_ms.min(line.end_ms).saturating_sub(line.start_ms)
+                    ),
+                );
+            }
+            if last_main_line.is_none_or(|previous| line.end_ms > previous.end_ms) {
+                last_main_line = Some(line);
             }
-            continue;
         }
 
-        // 只处理 Format 行之后的 Dialogue 行
-        if line.starts_with("Dialogue:") {
-            // 使用合并后的 Regex 解析行
-            if let Some(caps) = ASS_DIALOGUE_REGEX.captures(&line) {
-                // 安全地获取 Style 和 Name 字段
-                let style = caps.name("style").map_or("", |m| m.as_str()).trim();
-                let name = caps.name("name").map_or("", |m| m.as_str()).trim();
-
-                // 检查样式是否是翻译样式 ("ts" 或 "trans")
-                if style == "ts" || style == "trans" {
-                    // 检查 Name 字段是否匹配语言标签格式 "x-lang:..."
-                    if let Some(lang_caps) = LANG_TAG_REGEX.captures(name) {
-                        // 提取语言代码
-                        if let Some(lang_code_match) = lang_caps.name("lang_code") {
-                            let lang_code = lang_code_match.as_str().to_lowercase(); // 统一转小写
-
-                            // 提取开始时间和纯文本
-                            let start_time_str = caps.name("start_time").unwrap().as_str();
-                            let text_with_tags = caps.name("text").unwrap().as_str();
-
-                            match time_to_milliseconds(start_time_str) {
-                                Ok(start_ms) => {
-                                    // 移除 ASS 标签获取纯文本
-                                    let plain_text = strip_ass_tags(text_with_tags);
-                                    // 如果纯文本不为空，则添加到对应语言的列表中
-                                    if !plain_text.is_empty() {
-                                        translations
-                                            .entry(lang_code) // 获取或插入该语言的条目
-                                            .or_default() // 如果不存在则创建空的 Vec
-                                            .push((start_ms, plain_text)); // 添加 (时间, 文本)
-                                    }
-                                }
-                                Err(e) => {
-                                    // 报告时间解析错误，但继续处理
-                                    log_warn!("第 {} 行翻译时间解析失败: {}", line_number, e);
-                                    warning_occurred_during_extraction = true; // 设置警告标志
-                                }
-                            }
-                        }
-                    } // end if lang tag matches
-                } // end if style is translation
-            } // end if dialogue matches
-        } // end if starts with Dialogue:
-    } // end for line_result
-
-    // --- 写入 LRC 文件 ---
-    if translations.is_empty() {
-        log_info!("在文件中未找到符合条件的翻译行。");
-        return Ok(warning_occurred_during_extraction); // 即使没找到翻译，也可能之前有时间解析警告
-    }
-
-    let mut lrc_files_generated = 0;
-    // 遍历 HashMap 中每个语言的数据
-    for (lang_code, mut lines) in translations {
-        // 检查该语言是否有内容行
-        if lines.is_empty() { continue; }
-
-        // 1. 按开始时间对行进行排序
-        lines.sort_unstable_by_key(|k| k.0);
-
-        // 2. 构建输出 LRC 文件名: 输入文件名(无扩展名).语言代码.lrc
-        let lrc_filename = format!(
-            "{}.{}.lrc",
-            ass_path.file_stem().unwrap_or_default().to_string_lossy(), // 获取文件名（不含扩展名）
-            lang_code
-        );
-        let lrc_output_path = ass_path.with_file_name(lrc_filename);
-
-        log_info!("正在生成翻译文件: {:?}", lrc_output_path.file_name().unwrap_or_default());
-
-        // 3. 创建并写入 LRC 文件
-        match File::create(&lrc_output_path) {
-            Ok(lrc_file) => {
-                let mut lrc_writer = BufWriter::new(lrc_file);
-                // 写入元数据（可选，可以考虑从 ASS 元数据传递）
-                // writeln!(lrc_writer, "[by:ASS Extractor]")?;
-                for (start_ms, text) in lines {
-                    // 将毫秒转换为 LRC 时间格式 [mm:ss.xx]
-                    let lrc_time = milliseconds_to_lrc_time(start_ms);
-                    // 写入 LRC 行
-                    if let Err(e) = writeln!(lrc_writer, "{}{}", lrc_time, text) {
-                        log_error!("写入 LRC 文件 {:?} 时出错: {}", lrc_output_path, e);
-                        // 选择继续尝试写入其他语言，或者直接返回错误
-                        // return Err(e.into()); // 如果希望任何写入失败都中止
-                        break; // 中止当前文件的写入，尝试下一个语言
-                    }
-                }
-                // 确保写入缓冲区
-                if let Err(e) = lrc_writer.flush() {
-                     log_error!("刷新 LRC 文件 {:?} 缓冲区时出错: {}", lrc_output_path, e);
-                } else {
-                    lrc_files_generated += 1;
-                }
+        // --- 音节级检查 ---
+        let mut previous_end_ms: Option<usize> = None;
+        for (index, syllable) in line.syllables.iter().enumerate() {
+            let label = format!("第 {} 个音节 '{}'", index + 1, syllable.text);
+
+            if let Some(previous_end_ms) = previous_end_ms
+                && syllable.start_ms < previous_end_ms
+            {
+                report(
+                    ValidationRule::NonMonotonicSyllables,
+                    line.line_number,
+                    format!("{} 开始于 {} ms，早于前一个音节的结束时间 {} ms", label, syllable.start_ms, previous_end_ms),
+                );
             }
-            Err(e) => {
-                log_error!("无法创建 LRC 输出文件 {:?}: {}", lrc_output_path, e);
-                // 继续尝试生成其他语言的文件
+            previous_end_ms = Some(syllable.end_ms());
+
+            // ASS 的 K 标签超出行范围已由 KSumMismatch 报告
+            if format != LyricFormat::Ass && (syllable.start_ms < line.start_ms || syllable.end_ms() > line.end_ms) {
+                report(
+                    ValidationRule::SyllableOutOfBounds,
+                    line.line_number,
+                    format!(
+                        "{} ({} - {} ms) 超出行时间范围 {} - {} ms",
+                        label, syllable.start_ms, syllable.end_ms(), line.start_ms, line.end_ms
+                    ),
+                );
+            }
+
+            if syllable.is_gap {
+                continue;
+            }
+            if syllable.duration_ms == 0 {
+                report(ValidationRule::ZeroSyllable, line.line_number, format!("{} 的时长为 0", label));
+            } else if syllable.duration_ms > config.max_syllable_ms {
+                report(
+                    ValidationRule::LongSyllable,
+                    line.line_number,
+                    format!("{} 的时长 {} ms 超过上限 {} ms", label, syllable.duration_ms, config.max_syllable_ms),
+                );
             }
-        } // end match File::create
-    } // end for (lang_code, lines)
+        }
+    }
+
+    issues.sort_by_key(|issue| issue.line_number);
+    issues
+}
+
+/// 执行 `validate` 子命令：读取文件、按规则检查并打印所有问题。
+///
+/// # Returns
+/// * `bool` - 是否发现了问题 (或读取时发生了警告/错误)，用于决定进程退出码。
+fn run_validate_command(args: &ValidateArgs) -> bool {
+    let input_path = clean_path_buf_quotes(args.input_file.clone());
+    let (lyrics, read_warned, format) = match read_lyrics_file(&input_path) {
+        Ok(result) => result,
+        Err(e) => {
+            log_error!("读取 {:?} 时出错: {}", input_path, e);
+            return true;
+        }
+    };
 
-    if lrc_files_generated > 0 {
-        log_success!("成功生成 {} 个 LRC 翻译文件。", lrc_files_generated);
+    // 确定启用的规则: --only 指定时只使用其中的规则，否则使用全部规则，再去掉 --disable 的规则
+    let candidate_rules: Vec<ValidationRule> = if args.only.is_empty() {
+        ValidationRule::value_variants().to_vec()
     } else {
-        log_warn!("提取过程完成，但未能成功生成任何 LRC 文件（请检查错误信息）。");
-        warning_occurred_during_extraction = true; // 标记需要等待
+        args.only.clone()
+    };
+    let config = ValidationConfig {
+        enabled_rules: candidate_rules.into_iter().filter(|rule| !args.disable.contains(rule)).collect(),
+        max_syllable_ms: args.max_syllable_ms,
+        overlap_tolerance_ms: args.overlap_tolerance_ms,
+    };
+
+    let issues = validate_lyrics(&lyrics, format, &config);
+    for issue in &issues {
+        println!("{}[{}]{} 第 {} 行: {}", YELLOW, issue.rule.name(), RESET, issue.line_number, issue.message);
     }
 
-    Ok(warning_occurred_during_extraction)
+    if issues.is_empty() && !read_warned {
+        log_success!("未发现问题 ({} 行，{} 条规则)。", lyrics.lines.len(), config.enabled_rules.len());
+        false
+    } else {
+        log_warn!("共发现 {} 个问题。", issues.len());
+        true
+    }
 }
 
-// --- 在 extract_translations_to_lrc 函数之后添加 ---
 
-/// 从 ASS 文件中提取指定样式 ("roma") 的行，并生成罗马音 LRC 文件。
+// --- 时间轴对比 (diff / textconv 子命令) ---
+
+/// 序列对齐结果中的一项：两边的下标 (None 表示该项只存在于另一边)。
+type AlignedPair = (Option<usize>, Option<usize>);
+
+/// 用最长公共子序列 (LCS) 对齐两个序列，返回按顺序排列的对齐结果。
 ///
 /// # Arguments
-/// * `ass_path` - 输入的 ASS 文件路径。
+/// * `a`, `b` - 要对齐的两个序列。
+/// * `eq` - 判断两项是否可以匹配的函数。
+fn align_sequences<T>(a: &[T], b: &[T], eq: impl Fn(&T, &T) -> bool) -> Vec<AlignedPair> {
+    // lcs[i][j] = a[i..] 与 b[j..] 的 LCS 长度
+    let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            lcs[i][j] = if eq(&a[i], &b[j]) { lcs[i + 1][j + 1] + 1 } else { lcs[i + 1][j].max(lcs[i][j + 1]) };
+        }
+    }
+
+    let mut result = Vec::with_capacity(a.len().max(b.len()));
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if eq(&a[i], &b[j]) {
+            result.push((Some(i), Some(j)));
+            i += 1;
+            j += 1;
+        } else if lcs[i + 1][j] >= lcs[i][j + 1] {
+            result.push((Some(i), None));
+            i += 1;
+        } else {
+            result.push((None, Some(j)));
+            j += 1;
+        }
+    }
+    result.extend((i..a.len()).map(|i| (Some(i), None)));
+    result.extend((j..b.len()).map(|j| (None, Some(j))));
+    result
+}
+
+/// 两个文件中一对行的对比结果。
+#[derive(Debug)]
+enum LineDiff<'a> {
+    Same(&'a LyricLine, &'a LyricLine),    // 文本相同 (时间可能不同)
+    Changed(&'a LyricLine, &'a LyricLine), // 文本不同，但时间重叠，视为同一行的修改
+    Removed(&'a LyricLine),                // 只存在于第一个文件
+    Added(&'a LyricLine),                  // 只存在于第二个文件
+}
+
+/// 对齐两个歌词的歌词行 (`include_auxiliary` 为 false 时忽略翻译和罗马音行)。
+/// 先按文本做 LCS 对齐，再把同一段未匹配区间中时间重叠的删除行和新增行配对为"文本修改"。
+fn diff_lyric_lines<'a>(old: &'a Lyrics, new: &'a Lyrics, include_auxiliary: bool) -> Vec<LineDiff<'a>> {
+    let keep = |line: &&LyricLine| include_auxiliary || !is_auxiliary_style(&line.style);
+    let old_lines: Vec<&LyricLine> = old.lines.iter().filter(keep).collect();
+    let new_lines: Vec<&LyricLine> = new.lines.iter().filter(keep).collect();
+    let aligned = align_sequences(&old_lines, &new_lines, |a, b| a.text() == b.text());
+
+    let mut result = Vec::new();
+    let mut removed: Vec<&LyricLine> = Vec::new();
+    let mut added: Vec<&LyricLine> = Vec::new();
+
+    // 把一段连续的未匹配区间中时间重叠的行配对
+    let flush = |removed: &mut Vec<&'a LyricLine>, added: &mut Vec<&'a LyricLine>, result: &mut Vec<LineDiff<'a>>| {
+        let mut remaining_added: Vec<Option<&LyricLine>> = added.drain(..).map(Some).collect();
+        for old_line in removed.drain(..) {
+            let partner = remaining_added.iter_mut().find(|candidate| {
+                candidate.is_some_and(|new_line| new_line.start_ms < old_line.end_ms && old_line.start_ms < new_line.end_ms)
+            });
+            match partner.and_then(Option::take) {
+                Some(new_line) => result.push(LineDiff::Changed(old_line, new_line)),
+                None => result.push(LineDiff::Removed(old_line)),
+            }
+        }
+        result.extend(remaining_added.into_iter().flatten().map(LineDiff::Added));
+    };
+
+    for pair in aligned {
+        match pair {
+            (Some(i), Some(j)) => {
+                flush(&mut removed, &mut added, &mut result);
+                result.push(LineDiff::Same(old_lines[i], new_lines[j]));
+            }
+            (Some(i), None) => removed.push(old_lines[i]),
+            (None, Some(j)) => added.push(new_lines[j]),
+            (None, None) => {}
+        }
+    }
+    flush(&mut removed, &mut added, &mut result);
+    result
+}
+
+/// 时间差异的统计数据。
+#[derive(Debug, Default)]
+struct TimingDiffStats {
+    matched_syllables: usize,  // 参与统计的已匹配音节数
+    start_error_sum: usize,    // 开始时间绝对误差之和 (毫秒)
+    duration_error_sum: usize, // 时长绝对误差之和 (毫秒)
+    max_start_error: usize,    // 最大开始时间绝对误差 (毫秒)
+}
+
+impl TimingDiffStats {
+    fn record(&mut self, old: &Syllable, new: &Syllable) {
+        let start_error = old.start_ms.abs_diff(new.start_ms);
+        self.matched_syllables += 1;
+        self.start_error_sum += start_error;
+        self.duration_error_sum += old.duration_ms.abs_diff(new.duration_ms);
+        self.max_start_error = self.max_start_error.max(start_error);
+    }
+
+    fn mean(sum: usize, count: usize) -> f64 {
+        if count == 0 { 0.0 } else { sum as f64 / count as f64 }
+    }
+}
+
+/// 格式化带符号的毫秒差值 (例如 "+12 ms", "-3 ms")。
+fn format_signed_ms(old_ms: usize, new_ms: usize) -> String {
+    format!("{:+} ms", signed_difference(new_ms, old_ms))
+}
+
+/// 执行 `diff` 子命令：对齐两个歌词文件的行和音节，打印逐音节的时间差异和文本变化，最后输出统计摘要。
 ///
 /// # Returns
-/// * `Ok(())` - 如果提取和写入成功（即使没有找到 "roma" 行）。
-/// * `Err(ConversionError)` - 如果发生文件读取或写入错误。
-fn extract_roma_to_lrc(ass_path: &Path) -> Result<bool, ConversionError> {
-    log_info!("开始从 {:?} 提取罗马音 (Style: roma)...", ass_path.file_name().unwrap_or_default());
-    let mut warning_occurred_during_extraction = false;
-
-    // 使用 Vec 存储罗马音行的 (开始时间ms, 纯文本)
-    let mut roma_lines: Vec<(usize, String)> = Vec::new();
+/// * `bool` - 是否存在差异 (或读取失败)，用于决定进程退出码。
+fn run_diff_command(args: &DiffArgs) -> bool {
+    let mut loaded = Vec::with_capacity(2);
+    for path in [&args.old_file, &args.new_file] {
+        let path = clean_path_buf_quotes(path.clone());
+        match read_lyrics_file(&path) {
+            Ok((lyrics, _, _)) => loaded.push(lyrics),
+            Err(e) => {
+                log_error!("读取 {:?} 时出错: {}", path, e);
+                return true;
+            }
+        }
+    }
+    let (old, new) = (&loaded[0], &loaded[1]);
+    let tolerance = args.tolerance_ms;
 
-    // --- 读取和解析 ASS 文件 ---
-    let file = File::open(ass_path)?;
-    let reader = BufReader::new(file);
-    let mut after_format = false;
-    let mut line_number = 0;
+    let mut stats = TimingDiffStats::default();
+    let (mut changed_lines, mut removed_lines, mut added_lines, mut retimed_lines) = (0, 0, 0, 0);
 
-    for line_result in reader.lines() {
-        line_number += 1;
-        let line = line_result?;
+    for line_diff in diff_lyric_lines(old, new, false) {
+        let (old_line, new_line) = match line_diff {
+            LineDiff::Removed(line) => {
+                removed_lines += 1;
+                println!("{}- a:{} {} {}{}", RED, line.line_number, milliseconds_to_lrc_time(line.start_ms), line.text(), RESET);
+                continue;
+            }
+            LineDiff::Added(line) => {
+                added_lines += 1;
+                println!("{}+ b:{} {} {}{}", GREEN, line.line_number, milliseconds_to_lrc_time(line.start_ms), line.text(), RESET);
+                continue;
+            }
+            LineDiff::Changed(old_line, new_line) => {
+                changed_lines += 1;
+                println!(
+                    "{}~ a:{} b:{} {} \"{}\" -> \"{}\"{}",
+                    YELLOW, old_line.line_number, new_line.line_number,
+                    milliseconds_to_lrc_time(new_line.start_ms), old_line.text(), new_line.text(), RESET
+                );
+                (old_line, new_line)
+            }
+            LineDiff::Same(old_line, new_line) => (old_line, new_line),
+        };
 
-        // 定位到 Format 行
-        if !after_format {
-            if line.trim_start().starts_with("Format: Layer, Start, End, Style, Name,") {
-                after_format = true;
+        // 对齐行内的歌词音节 (停顿不参与对比)
+        let old_syllables: Vec<&Syllable> = old_line.syllables.iter().filter(|s| !s.is_gap).collect();
+        let new_syllables: Vec<&Syllable> = new_line.syllables.iter().filter(|s| !s.is_gap).collect();
+        let aligned = align_sequences(&old_syllables, &new_syllables, |a, b| a.text == b.text);
+
+        let mut details = Vec::new();
+        if old_line.start_ms.abs_diff(new_line.start_ms) > tolerance || old_line.end_ms.abs_diff(new_line.end_ms) > tolerance {
+            details.push(format!(
+                "    行时间: 开始 {}, 结束 {}",
+                format_signed_ms(old_line.start_ms, new_line.start_ms),
+                format_signed_ms(old_line.end_ms, new_line.end_ms)
+            ));
+        }
+        for pair in aligned {
+            match pair {
+                (Some(i), Some(j)) => {
+                    let (a, b) = (old_syllables[i], new_syllables[j]);
+                    stats.record(a, b);
+                    if a.start_ms.abs_diff(b.start_ms) > tolerance || a.duration_ms.abs_diff(b.duration_ms) > tolerance {
+                        details.push(format!(
+                            "    '{}': 开始 {}, 时长 {}",
+                            a.text,
+                            format_signed_ms(a.start_ms, b.start_ms),
+                            format_signed_ms(a.duration_ms, b.duration_ms)
+                        ));
+                    }
+                }
+                (Some(i), None) => details.push(format!("{}    - '{}' ({}, {}){}", RED, old_syllables[i].text, old_syllables[i].start_ms, old_syllables[i].duration_ms, RESET)),
+                (None, Some(j)) => details.push(format!("{}    + '{}' ({}, {}){}", GREEN, new_syllables[j].text, new_syllables[j].start_ms, new_syllables[j].duration_ms, RESET)),
+                (None, None) => {}
             }
+        }
+
+        if details.is_empty() {
             continue;
         }
+        if matches!(line_diff, LineDiff::Same(..)) {
+            retimed_lines += 1;
+            println!("  a:{} b:{} {} {}", old_line.line_number, new_line.line_number, milliseconds_to_lrc_time(new_line.start_ms), new_line.text());
+        }
+        for detail in details {
+            println!("{}", detail);
+        }
+    }
 
-        // 只处理 Format 行之后的 Dialogue 行
-        if line.starts_with("Dialogue:") {
-            if let Some(caps) = ASS_DIALOGUE_REGEX.captures(&line) {
-                // 获取 Style 字段
-                let style = caps.name("style").map_or("", |m| m.as_str()).trim();
-
-                // 检查样式是否为 "roma" (不区分大小写)
-                if style.eq_ignore_ascii_case("roma") {
-                    // 提取开始时间和纯文本
-                    let start_time_str = caps.name("start_time").unwrap().as_str();
-                    let text_with_tags = caps.name("text").unwrap().as_str();
-
-                    match time_to_milliseconds(start_time_str) {
-                        Ok(start_ms) => {
-                            // 移除 ASS 标签获取纯文本
-                            let plain_text = strip_ass_tags(text_with_tags);
-                            // 如果纯文本不为空，则添加到列表中
-                            if !plain_text.is_empty() {
-                                roma_lines.push((start_ms, plain_text));
-                            }
-                        }
-                        Err(e) => {
-                            log_warn!("第 {} 行罗马音时间解析失败: {}", line_number, e);
-                            warning_occurred_during_extraction = true;
-                        }
-                    }
-                } // end if style is roma
-            } // end if dialogue matches
-        } // end if starts with Dialogue:
-    } // end for line_result
+    println!(
+        "\n摘要: 时间变化 {} 行，文本修改 {} 行，删除 {} 行，新增 {} 行。",
+        retimed_lines, changed_lines, removed_lines, added_lines
+    );
+    println!(
+        "已匹配音节 {} 个: 开始时间平均绝对误差 {:.1} ms (最大 {} ms)，时长平均绝对误差 {:.1} ms。",
+        stats.matched_syllables,
+        TimingDiffStats::mean(stats.start_error_sum, stats.matched_syllables),
+        stats.max_start_error,
+        TimingDiffStats::mean(stats.duration_error_sum, stats.matched_syllables)
+    );
+
+    retimed_lines + changed_lines + removed_lines + added_lines > 0
+}
+
+/// 执行 `textconv` 子命令：把歌词文件输出为便于逐行对比的规范文本 (每行歌词一行，每个音节一行)。
+/// 可作为 git 的 diff 驱动使用，见 README。
+///
+/// # Returns
+/// * `bool` - 是否读取失败，用于决定进程退出码。
+fn run_textconv_command(args: &TextconvArgs) -> bool {
+    let input_path = clean_path_buf_quotes(args.input_file.clone());
+    let lyrics = match read_lyrics_file(&input_path) {
+        Ok((lyrics, _, _)) => lyrics,
+        Err(e) => {
+            log_error!("读取 {:?} 时出错: {}", input_path, e);
+            return true;
+        }
+    };
 
-    // --- 写入 LRC 文件 ---
-    if roma_lines.is_empty() {
-        log_info!("在文件中未找到 Style 为 'roma' 的行。");
-        return Ok(warning_occurred_during_extraction);
+    let mut output = String::new();
+    for entry in &lyrics.metadata {
+        output.push_str(&format!("{}: {}\n", entry.key, entry.value));
+    }
+    for line in &lyrics.lines {
+        let name = line.ass_name();
+        let role = if name.is_empty() { line.style.clone() } else { format!("{} {}", line.style, name) };
+        output.push_str(&format!("[{},{}] {}: {}\n", line.start_ms, line.duration_ms(), role, encode_ass_escapes(&line.text())));
+        for syllable in line.syllables.iter().filter(|s| !s.is_gap) {
+            output.push_str(&format!("    ({},{}) {}\n", syllable.start_ms, syllable.duration_ms, encode_ass_escapes(&syllable.karaoke_text())));
+        }
     }
+    print!("{}", output);
+    false
+}
 
-    // 1. 按开始时间对行进行排序
-    roma_lines.sort_unstable_by_key(|k| k.0);
+/// 执行 `sections` 子命令：按主歌词行列出连续的同一段落，输出时间范围和行号。
+/// 没有段落标记的行显示为 "(未标记)"。
+///
+/// # Returns
+/// * `bool` - 是否读取失败，用于决定进程退出码。
+fn run_sections_command(args: &SectionsArgs) -> bool {
+    let input_path = clean_path_buf_quotes(args.input_file.clone());
+    let lyrics = match read_lyrics_file(&input_path) {
+        Ok((lyrics, _, _)) => lyrics,
+        Err(e) => {
+            log_error!("读取 {:?} 时出错: {}", input_path, e);
+            return true;
+        }
+    };
 
-    // 2. 构建输出 LRC 文件名: 输入文件名(无扩展名).roma.lrc
-    let lrc_filename = format!(
-        "{}.roma.lrc", // 固定后缀
-        ass_path.file_stem().unwrap_or_default().to_string_lossy(),
-    );
-    let lrc_output_path = ass_path.with_file_name(lrc_filename);
-
-    log_info!("正在生成罗马音文件: {:?}", lrc_output_path.file_name().unwrap_or_default());
-
-    // 3. 创建并写入 LRC 文件
-    match File::create(&lrc_output_path) {
-        Ok(lrc_file) => {
-            let mut lrc_writer = BufWriter::new(lrc_file);
-            // 写入元数据（可选）
-            // writeln!(lrc_writer, "[by:ASS Roma Extractor]")?;
-            for (start_ms, text) in roma_lines {
-                let lrc_time = milliseconds_to_lrc_time(start_ms);
-                if let Err(e) = writeln!(lrc_writer, "{}{}", lrc_time, text) {
-                    log_error!("写入罗马音 LRC 文件 {:?} 时出错: {}", lrc_output_path, e);
-                    return Err(e.into()); // 写入失败则直接返回错误
-                }
+    // (段落, 起始时间, 结束时间, 首行行号, 末行行号)
+    let mut sections: Vec<(Option<&str>, usize, usize, usize, usize)> = Vec::new();
+    for line in lyrics.lines.iter().filter(|line| !is_auxiliary_style(&line.style)) {
+        let part = line.song_part.as_deref();
+        match sections.last_mut() {
+            Some(section) if section.0 == part => {
+                section.1 = section.1.min(line.start_ms);
+                section.2 = section.2.max(line.end_ms);
+                section.4 = line.line_number;
             }
-            // 确保写入缓冲区
-            if let Err(e) = lrc_writer.flush() {
-                 log_error!("刷新罗马音 LRC 文件 {:?} 缓冲区时出错: {}", lrc_output_path, e);
-                 return Err(e.into());
-            } else {
-                log_success!("成功生成罗马音 LRC 文件。");
+            _ => sections.push((part, line.start_ms, line.end_ms, line.line_number, line.line_number)),
+        }
+    }
+
+    if sections.iter().all(|section| section.0.is_none()) {
+        log_info!("{:?} 中没有段落标记。", input_path);
+    }
+    for (part, start_ms, end_ms, first_line, last_line) in &sections {
+        println!(
+            "{} - {}  {} (第 {}-{} 行)",
+            milliseconds_to_time(*start_ms),
+            milliseconds_to_time(*end_ms),
+            part.unwrap_or("(未标记)"),
+            first_line,
+            last_line
+        );
+    }
+    false
+}
+
+
+// --- 往返校验 (verify 子命令) ---
+
+/// 把歌词模型以指定格式写入内存缓冲区，再用同一格式的解析器读取回来。
+///
+/// # Returns
+/// * `Ok((Lyrics, bool))` - 读取回来的歌词，以及写入或读取时是否发生了警告。
+fn round_trip_in_memory(lyrics: &Lyrics, format: LyricFormat, options: &ConversionOptions) -> Result<(Lyrics, bool), ConversionError> {
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut warning_occurred = false;
+    match format {
+        LyricFormat::Ass => write_ass_lyrics(lyrics, &mut buffer, options)?,
+        LyricFormat::Qrc => write_qrc_lyrics(lyrics, &mut buffer, options)?,
+        LyricFormat::Lys => warning_occurred |= write_lys_lyrics(lyrics, &mut buffer, options)?,
+    }
+
+    let total_bytes = buffer.len();
+    let (parsed, read_warned) = match format {
+        LyricFormat::Ass => parse_ass_lyrics(buffer.as_slice(), total_bytes)?,
+        LyricFormat::Qrc => parse_qrc_lyrics(buffer.as_slice(), total_bytes)?,
+        LyricFormat::Lys => parse_lys_lyrics(buffer.as_slice(), total_bytes)?,
+    };
+    Ok((parsed, warning_occurred || read_warned))
+}
+
+/// 逐行、逐音节比较原始歌词与往返后的歌词，返回所有文本、时间、角色 (Style/Name) 和元数据的变化。
+fn collect_round_trip_losses(original: &Lyrics, result: &Lyrics) -> Vec<String> {
+    let mut losses = Vec::new();
+
+    // 元数据
+    for entry in original.metadata.iter().filter(|entry| !result.metadata.contains(entry)) {
+        losses.push(format!("元数据丢失: {}:{}", entry.key, entry.value));
+    }
+    for entry in result.metadata.iter().filter(|entry| !original.metadata.contains(entry)) {
+        losses.push(format!("元数据新增: {}:{}", entry.key, entry.value));
+    }
+
+    for line_diff in diff_lyric_lines(original, result, true) {
+        let (old_line, new_line) = match line_diff {
+            LineDiff::Removed(line) => {
+                losses.push(format!("第 {} 行 '{}' ({}) 丢失", line.line_number, line.text(), line.style));
+                continue;
             }
+            LineDiff::Added(line) => {
+                losses.push(format!("多出一行 '{}' ({})", line.text(), line.style));
+                continue;
+            }
+            LineDiff::Changed(old_line, new_line) => {
+                losses.push(format!("第 {} 行文本 '{}' 变为 '{}'", old_line.line_number, old_line.text(), new_line.text()));
+                (old_line, new_line)
+            }
+            LineDiff::Same(old_line, new_line) => (old_line, new_line),
+        };
+        let prefix = format!("第 {} 行 '{}'", old_line.line_number, old_line.text());
+
+        if old_line.style != new_line.style {
+            losses.push(format!("{}: Style '{}' 变为 '{}'", prefix, old_line.style, new_line.style));
         }
-        Err(e) => {
-            log_error!("无法创建罗马音 LRC 输出文件 {:?}: {}", lrc_output_path, e);
-            return Err(e.into()); // 创建文件失败也返回错误
+        let (old_name, new_name) = (old_line.name.as_deref().unwrap_or(""), new_line.name.as_deref().unwrap_or(""));
+        if old_name != new_name {
+            losses.push(format!("{}: Name '{}' 变为 '{}'", prefix, old_name, new_name));
+        }
+        if old_line.song_part != new_line.song_part {
+            losses.push(format!(
+                "{}: 段落 '{}' 变为 '{}'",
+                prefix,
+                old_line.song_part.as_deref().unwrap_or(""),
+                new_line.song_part.as_deref().unwrap_or("")
+            ));
+        }
+        if old_line.start_ms != new_line.start_ms || old_line.end_ms != new_line.end_ms {
+            losses.push(format!(
+                "{}: 行时间 {} - {} ms 变为 {} - {} ms",
+                prefix, old_line.start_ms, old_line.end_ms, new_line.start_ms, new_line.end_ms
+            ));
+        }
+
+        // 音节 (包括停顿) 按文本对齐后比较时间
+        let aligned = align_sequences(&old_line.syllables, &new_line.syllables, |a, b| a.text == b.text && a.is_gap == b.is_gap);
+        for pair in aligned {
+            match pair {
+                (Some(i), Some(j)) => {
+                    let (a, b) = (&old_line.syllables[i], &new_line.syllables[j]);
+                    if a.start_ms != b.start_ms || a.duration_ms != b.duration_ms {
+                        losses.push(format!(
+                            "{}: 音节 '{}' ({},{}) 变为 ({},{})",
+                            prefix, a.text, a.start_ms, a.duration_ms, b.start_ms, b.duration_ms
+                        ));
+                    }
+                    if a.ruby != b.ruby {
+                        losses.push(format!("{}: 注音 '{}' 变为 '{}'", prefix, a.karaoke_text(), b.karaoke_text()));
+                    }
+                }
+                (Some(i), None) => {
+                    let a = &old_line.syllables[i];
+                    let kind = if a.is_gap { "停顿" } else { "音节" };
+                    losses.push(format!("{}: {} '{}' ({},{}) 丢失", prefix, kind, a.text, a.start_ms, a.duration_ms));
+                }
+                (None, Some(j)) => {
+                    let b = &new_line.syllables[j];
+                    let kind = if b.is_gap { "停顿" } else { "音节" };
+                    losses.push(format!("{}: 多出{} '{}' ({},{})", prefix, kind, b.text, b.start_ms, b.duration_ms));
+                }
+                (None, None) => {}
+            }
         }
     }
 
-    Ok(warning_occurred_during_extraction)
-}
\ No newline at end of file
+    losses
+}
+
+/// 执行 `verify` 子命令：把输入文件在内存中转换为每种目标格式再转换回来，报告所有发生变化的内容。
+///
+/// # Returns
+/// * `bool` - 是否存在任何损失 (或读取失败)，用于决定进程退出码。
+fn run_verify_command(args: &VerifyArgs) -> bool {
+    let input_path = clean_path_buf_quotes(args.input_file.clone());
+    let (original, _, source_format) = match read_lyrics_file(&input_path) {
+        Ok(result) => result,
+        Err(e) => {
+            log_error!("读取 {:?} 时出错: {}", input_path, e);
+            return true;
+        }
+    };
+    let options = ConversionOptions {
+        rounding: args.rounding,
+        exact_ms: args.exact_ms,
+        gap_mode: args.gap_mode,
+        song_part_tags: args.song_part_tags,
+        ..ConversionOptions::default()
+    };
+
+    // 未指定 --target 时校验除源格式之外的所有格式
+    let targets: Vec<LyricFormat> = if args.targets.is_empty() {
+        LyricFormat::value_variants().iter().copied().filter(|format| *format != source_format).collect()
+    } else {
+        args.targets.clone()
+    };
+
+    let mut any_loss = false;
+    for target in targets {
+        let path_name = format!("{} -> {} -> {}", source_format.name(), target.name(), source_format.name());
+        // 源格式 -> 目标格式 -> 源格式 (目标格式与源格式相同时只做一次往返)
+        let round_trip = round_trip_in_memory(&original, target, &options).and_then(|(converted, warned)| {
+            if target == source_format {
+                return Ok((converted, warned));
+            }
+            round_trip_in_memory(&converted, source_format, &options).map(|(back, back_warned)| (back, warned || back_warned))
+        });
+        let (result, warned) = match round_trip {
+            Ok(result) => result,
+            Err(e) => {
+                log_error!("{}: 转换失败: {}", path_name, e);
+                any_loss = true;
+                continue;
+            }
+        };
+
+        let losses = collect_round_trip_losses(&original, &result);
+        if losses.is_empty() && !warned {
+            log_success!("{}: 无损。", path_name);
+            continue;
+        }
+        any_loss = true;
+        log_warn!("{}: 发现 {} 处变化{}", path_name, losses.len(), if warned { " (转换时还产生了警告)" } else { "" });
+        for loss in &losses {
+            println!("  {}", loss);
+        }
+    }
+
+    any_loss
+}
+
+
+// --- 导入翻译/罗马音 LRC (import 子命令) ---
+
+/// 翻译或罗马音轨道，对应样式角色为翻译 (按语言区分) 或罗马音的 ASS 行 (见 `line_role`)。
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum AuxiliaryTrack {
+    Translation(String),          // 翻译，包含语言代码 (小写)
+    Romanization(Option<String>), // 罗马音，可带语言代码 (小写)
+}
+
+impl AuxiliaryTrack {
+    /// 根据提取时生成的文件名 (`<stem>.<lang>.lrc`、`<stem>.roma.lrc` 或 `<stem>.roma-<lang>.lrc`) 判断轨道。
+    fn from_lrc_path(path: &Path) -> Option<Self> {
+        let stem = path.file_stem()?.to_str()?;
+        let (_, suffix) = stem.rsplit_once('.')?;
+        let suffix = suffix.to_lowercase();
+        if suffix == "roma" {
+            return Some(AuxiliaryTrack::Romanization(None));
+        }
+        if let Some(lang) = suffix.strip_prefix("roma-").filter(|lang| !lang.is_empty()) {
+            return Some(AuxiliaryTrack::Romanization(Some(lang.to_string())));
+        }
+        (!suffix.is_empty()).then_some(AuxiliaryTrack::Translation(suffix))
+    }
+
+    /// 根据行的角色判断其所属的轨道；主歌词行和没有语言的翻译行返回 None。
+    fn from_line(style: &str, name: Option<&str>) -> Option<Self> {
+        match line_role(style, name) {
+            StyleRole::Translation(Some(lang)) => Some(AuxiliaryTrack::Translation(lang)),
+            StyleRole::Romanization(lang) => Some(AuxiliaryTrack::Romanization(lang)),
+            _ => None,
+        }
+    }
+
+    /// 提取的文件名和合并 LRC 轨道顺序中使用的标识: 语言代码、"roma" 或 "roma-<语言代码>"。
+    fn key(&self) -> String {
+        match self {
+            AuxiliaryTrack::Translation(lang) => lang.clone(),
+            AuxiliaryTrack::Romanization(None) => "roma".to_string(),
+            AuxiliaryTrack::Romanization(Some(lang)) => format!("roma-{}", lang),
+        }
+    }
+
+    /// 写入 ASS 时使用的 Style: 优先使用配置中语言相同的该角色 Style，其次是没有指定语言的该角色 Style
+    /// (语言由 Name 中的 x-lang: 给出)，都没有时使用内置的 "ts"/"roma"。
+    fn style(&self) -> String {
+        let configured = &lyrics_config().style_names;
+        let find = |wanted: &StyleRole| configured.iter().find(|(_, role)| role == wanted).map(|(style, _)| style.clone());
+        let found = match self {
+            AuxiliaryTrack::Translation(lang) => {
+                find(&StyleRole::Translation(Some(lang.clone()))).or_else(|| find(&StyleRole::Translation(None)))
+            }
+            AuxiliaryTrack::Romanization(None) => find(&StyleRole::Romanization(None)),
+            AuxiliaryTrack::Romanization(Some(lang)) => {
+                find(&StyleRole::Romanization(Some(lang.clone()))).or_else(|| find(&StyleRole::Romanization(None)))
+            }
+        };
+        found.unwrap_or_else(|| match self {
+            AuxiliaryTrack::Translation(_) => "ts".to_string(),
+            AuxiliaryTrack::Romanization(_) => "roma".to_string(),
+        })
+    }
+
+    /// 写入 ASS 时使用的 Name。
+    fn name(&self) -> String {
+        match self {
+            AuxiliaryTrack::Translation(lang) | AuxiliaryTrack::Romanization(Some(lang)) => format!("x-lang:{}", lang),
+            AuxiliaryTrack::Romanization(None) => String::new(),
+        }
+    }
+
+    /// 判断 ASS 行 (Style 和 Name) 是否属于该轨道。
+    fn matches(&self, style: &str, name: &str) -> bool {
+        AuxiliaryTrack::from_line(style, Some(name)).as_ref() == Some(self)
+    }
+}
+
+impl fmt::Display for AuxiliaryTrack {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuxiliaryTrack::Translation(lang) => write!(f, "翻译 ({})", lang),
+            AuxiliaryTrack::Romanization(None) => write!(f, "罗马音"),
+            AuxiliaryTrack::Romanization(Some(lang)) => write!(f, "罗马音 ({})", lang),
+        }
+    }
+}
+
+/// 从 LRC 文件读取到的一行歌词。
+#[derive(Debug, Clone)]
+struct LrcEntry {
+    line_number: usize, // 在 LRC 文件中的行号
+    time_ms: usize,     // 时间 (毫秒，已应用 [offset])
+    text: String,       // 歌词文本
+}
+
+/// 读取 LRC 文件，返回按时间排序的歌词行和文件头中的元数据。
+/// 支持一行多个时间标签 (例如 `[00:12.34][01:02.00]文本`)，没有文本的行会被忽略；
+/// `[offset:毫秒]` 会应用到所有时间上 (正值表示歌词提前)。
+fn read_lrc_file(lrc_path: &Path) -> Result<(Vec<LrcEntry>, Vec<MetadataEntry>), ConversionError> {
+    let reader = BufReader::new(File::open(lrc_path)?);
+    let mut entries = Vec::new();
+    let mut metadata: Vec<MetadataEntry> = Vec::new();
+    let mut offset_ms: i64 = 0;
+
+    for (index, line_result) in reader.lines().enumerate() {
+        let line = line_result?;
+        let line = line.trim();
+
+        if let Some(tag_entries) = parse_lrc_metadata_tag(line) {
+            for entry in tag_entries {
+                if entry.key == "offset" {
+                    offset_ms = entry.value.parse().unwrap_or(0);
+                }
+                if !metadata.contains(&entry) {
+                    metadata.push(entry);
+                }
+            }
+            continue;
+        }
+
+        // 依次读取行首的所有时间标签
+        let mut times = Vec::new();
+        let mut rest = line;
+        while let Some(caps) = LRC_TIMESTAMP_REGEX.captures(rest) {
+            let minutes: usize = caps[1].parse()?;
+            let seconds: usize = caps[2].parse()?;
+            // 小数部分按位数解释: 1 位为 0.1 秒，2 位为厘秒，3 位为毫秒
+            let fraction_ms = match caps.get(3).map(|m| m.as_str()) {
+                Some(fraction) if fraction.len() == 1 => fraction.parse::<usize>()? * 100,
+                Some(fraction) if fraction.len() == 2 => fraction.parse::<usize>()? * CENTISECONDS_TO_MILLISECONDS,
+                Some(fraction) => fraction[..3].parse::<usize>()?,
+                None => 0,
+            };
+            times.push(minutes * MILLISECONDS_PER_MINUTE + seconds * MILLISECONDS_PER_SECOND + fraction_ms);
+            rest = &rest[caps.get(0).unwrap().end()..];
+        }
+
+        let text = rest.trim();
+        if text.is_empty() {
+            continue;
+        }
+        for time_ms in times {
+            entries.push(LrcEntry { line_number: index + 1, time_ms, text: text.to_string() });
+        }
+    }
+
+    // 应用 [offset] (LRC 的 offset 为正表示歌词整体提前)
+    if offset_ms != 0 {
+        for entry in &mut entries {
+            entry.time_ms = (entry.time_ms as i64 - offset_ms).max(0) as usize;
+        }
+    }
+    entries.sort_by_key(|entry| entry.time_ms);
+    Ok((entries, metadata))
+}
+
+/// 为每个 LRC 行找到它所属的主歌词行：开始时间最接近且相差不超过 `tolerance_ms` 的行。
+/// 同一主歌词行只匹配一次，之后的 LRC 行视为未匹配。
+///
+/// # Arguments
+/// * `main_starts` - 主歌词行的开始时间 (毫秒)，按下标与调用方的主歌词行列表对应。
+///
+/// # Returns
+/// * `Vec<Option<usize>>` - 与 `entries` 一一对应，匹配到的主歌词行下标。
+fn match_lrc_entries_to_lines(entries: &[LrcEntry], main_starts: &[usize], tolerance_ms: usize) -> Vec<Option<usize>> {
+    let mut used = vec![false; main_starts.len()];
+    entries
+        .iter()
+        .map(|entry| {
+            let best = main_starts
+                .iter()
+                .enumerate()
+                .filter(|(index, start)| !used[*index] && start.abs_diff(entry.time_ms) <= tolerance_ms)
+                .min_by_key(|(_, start)| start.abs_diff(entry.time_ms))
+                .map(|(index, _)| index);
+            if let Some(index) = best {
+                used[index] = true;
+            }
+            best
+        })
+        .collect()
+}
+
+/// 把一个翻译/罗马音 LRC 的各行匹配到歌词中的主歌词行 (非翻译/罗马音、非背景人声行，见 `match_lrc_entries_to_lines`)，
+/// 对没有匹配的 LRC 行给出警告，并输出匹配的行数。
+///
+/// # Returns
+/// * `(Vec<(&LrcEntry, usize)>, bool)` - 匹配到的 LRC 行及其主歌词行在 `lines` 中的下标，以及是否有未匹配的 LRC 行。
+fn match_lrc_track<'a>(
+    lines: &[LyricLine],
+    lrc_path: &Path,
+    track: &AuxiliaryTrack,
+    entries: &'a [LrcEntry],
+    tolerance_ms: usize,
+) -> (Vec<(&'a LrcEntry, usize)>, bool) {
+    let main_indices: Vec<usize> = (0..lines.len())
+        .filter(|&i| !is_auxiliary_style(&lines[i].style) && !classify_line_singer(&lines[i]).is_some_and(SingerInfo::is_background))
+        .collect();
+    let main_starts: Vec<usize> = main_indices.iter().map(|&i| lines[i].start_ms).collect();
+
+    let mut matched = Vec::new();
+    let mut warning_occurred = false;
+    for (entry, main_position) in entries.iter().zip(match_lrc_entries_to_lines(entries, &main_starts, tolerance_ms)) {
+        match main_position {
+            Some(main_position) => matched.push((entry, main_indices[main_position])),
+            None => {
+                log_warn!(
+                    "{:?} 第 {} 行 {} '{}' 没有找到对应的主歌词行",
+                    lrc_path.file_name().unwrap_or_default(), entry.line_number, milliseconds_to_lrc_time(entry.time_ms), entry.text
+                );
+                warning_occurred = true;
+            }
+        }
+    }
+    log_info!("{:?}: {} 已匹配 {} / {} 行。", lrc_path.file_name().unwrap_or_default(), track, matched.len(), entries.len());
+    (matched, warning_occurred)
+}
+
+/// 执行 `import` 子命令：把翻译/罗马音 LRC 合并到已有的 ASS 中。
+/// 原文件的其他内容 (样式、注释、主歌词行) 保持不变；同一轨道原有的行会被替换，新行插入到对应主歌词行之后。
+///
+/// # Returns
+/// * `bool` - 是否有未匹配的 LRC 行或发生了错误，用于决定进程退出码。
+fn run_import_command(args: &ImportArgs) -> bool {
+    let ass_path = clean_path_buf_quotes(args.ass_file.clone());
+    let output_path = args.output.clone().map(clean_path_buf_quotes).unwrap_or_else(|| ass_path.clone());
+    match import_lrc_into_ass(&ass_path, &args.lrc_files, &output_path, args.tolerance_ms) {
+        Ok(warning_occurred) => warning_occurred,
+        Err(e) => {
+            log_error!("{}", CONVERSION_ERROR_MSG.replace("{}", &e.to_string()));
+            true
+        }
+    }
+}
+
+fn import_lrc_into_ass(ass_path: &Path, lrc_files: &[PathBuf], output_path: &Path, tolerance_ms: usize) -> Result<bool, ConversionError> {
+    let mut warning_occurred = false;
+    let source_lines: Vec<String> = BufReader::new(File::open(ass_path)?).lines().collect::<Result<_, _>>()?;
+    let (lyrics, read_warned) = read_ass_file(ass_path)?;
+    warning_occurred |= read_warned;
+
+    // 每个源文件行号之后要插入的新行
+    let mut insertions: HashMap<usize, Vec<String>> = HashMap::new();
+    let mut replaced_tracks: Vec<AuxiliaryTrack> = Vec::new();
+
+    for lrc_path in lrc_files {
+        let lrc_path = clean_path_buf_quotes(lrc_path.clone());
+        let track = AuxiliaryTrack::from_lrc_path(&lrc_path).ok_or_else(|| {
+            ConversionError::InvalidFormat(format!("无法从文件名 {:?} 判断轨道，文件名应为 <名称>.<语言代码>.lrc 或 <名称>.roma.lrc", lrc_path))
+        })?;
+        let (entries, _) = read_lrc_file(&lrc_path)?;
+        let (matched, unmatched) = match_lrc_track(&lyrics.lines, &lrc_path, &track, &entries, tolerance_ms);
+        warning_occurred |= unmatched;
+
+        for (entry, main_index) in matched {
+            let main_line = &lyrics.lines[main_index];
+            insertions.entry(main_line.line_number).or_default().push(format!(
+                "Dialogue: 0,{},{},{},{},0,0,0,,{}",
+                milliseconds_to_time(main_line.start_ms),
+                milliseconds_to_time(main_line.end_ms),
+                track.style(),
+                track.name(),
+                encode_ass_escapes(&entry.text)
+            ));
+        }
+        replaced_tracks.push(track);
+    }
+
+    // 重新写出 ASS: 删除被替换轨道的旧行，并在主歌词行之后插入新行
+    let mut writer = BufWriter::new(File::create(output_path)?);
+    let mut removed_count = 0;
+    for (index, line) in source_lines.iter().enumerate() {
+        let line_number = index + 1;
+        let replaced = line.starts_with("Dialogue:")
+            && ASS_DIALOGUE_REGEX
+                .captures(line)
+                .is_some_and(|caps| replaced_tracks.iter().any(|track| track.matches(caps["style"].trim(), caps["name"].trim())));
+        if replaced {
+            removed_count += 1;
+        } else {
+            writeln!(writer, "{}", line)?;
+        }
+        for new_line in insertions.get(&line_number).into_iter().flatten() {
+            writeln!(writer, "{}", new_line)?;
+        }
+    }
+    writer.flush()?;
+
+    if removed_count > 0 {
+        log_info!("已替换 {} 行原有的翻译/罗马音。", removed_count);
+    }
+    log_success!("已写入 {:?}。", output_path);
+    Ok(warning_occurred)
+}
+
+
+// --- 组装 ASS 工程 (assemble 子命令) ---
+
+/// 解析 `--offset FILE=MS`，返回与 `path` 对应的偏移 (按完整路径或文件名匹配)，未指定时为 0。
+fn source_offset_ms(offsets: &[(PathBuf, i64)], path: &Path) -> i64 {
+    offsets
+        .iter()
+        .find(|(source, _)| source == path || (source.parent() == Some(Path::new("")) && source.file_name() == path.file_name()))
+        .map_or(0, |(_, offset)| *offset)
+}
+
+/// 执行 `assemble` 子命令：以逐字歌词 (QRC/LYS/ASS) 为主歌词，合并翻译和罗马音 LRC 与元数据，生成一个 ASS 工程。
+///
+/// # Returns
+/// * `bool` - 是否有未匹配的 LRC 行或发生了错误，用于决定进程退出码。
+fn run_assemble_command(args: &AssembleArgs) -> bool {
+    match assemble_ass_project(args) {
+        Ok(warning_occurred) => warning_occurred,
+        Err(e) => {
+            log_error!("{}", CONVERSION_ERROR_MSG.replace("{}", &e.to_string()));
+            true
+        }
+    }
+}
+
+fn assemble_ass_project(args: &AssembleArgs) -> Result<bool, ConversionError> {
+    let options = build_conversion_options(&args.conversion)?;
+
+    // 解析各来源的时间偏移
+    let mut offsets = Vec::new();
+    for item in &args.offsets {
+        let (file, ms) = item
+            .rsplit_once('=')
+            .ok_or_else(|| ConversionError::InvalidFormat(format!("无效的偏移 '{}'，应为 FILE=MS", item)))?;
+        let ms: i64 = ms.trim().parse().map_err(|_| ConversionError::InvalidFormat(format!("无效的偏移毫秒数 '{}'", ms)))?;
+        offsets.push((clean_path_buf_quotes(PathBuf::from(file)), ms));
+    }
+
+    // 收集所有翻译/罗马音来源: 按文件名判断的 LRC、--ts LANG=FILE 和 --roma FILE
+    let mut tracks: Vec<(AuxiliaryTrack, PathBuf)> = Vec::new();
+    for lrc_path in &args.lrc_files {
+        let lrc_path = clean_path_buf_quotes(lrc_path.clone());
+        let track = AuxiliaryTrack::from_lrc_path(&lrc_path).ok_or_else(|| {
+            ConversionError::InvalidFormat(format!("无法从文件名 {:?} 判断轨道，请使用 --ts LANG=FILE 或 --roma FILE", lrc_path))
+        })?;
+        tracks.push((track, lrc_path));
+    }
+    for item in &args.translations {
+        let (lang, file) = item
+            .split_once('=')
+            .filter(|(lang, _)| !lang.trim().is_empty())
+            .ok_or_else(|| ConversionError::InvalidFormat(format!("无效的翻译来源 '{}'，应为 LANG=FILE", item)))?;
+        tracks.push((AuxiliaryTrack::Translation(lang.trim().to_lowercase()), clean_path_buf_quotes(PathBuf::from(file))));
+    }
+    for roma_path in &args.roma_files {
+        tracks.push((AuxiliaryTrack::Romanization(None), clean_path_buf_quotes(roma_path.clone())));
+    }
+
+    // 读取主歌词
+    let main_path = clean_path_buf_quotes(args.main_file.clone());
+    let (mut lyrics, mut warning_occurred, _) = read_lyrics_file(&main_path)?;
+    warning_occurred |= apply_time_warp(&mut lyrics, &TimeWarp::from_offset(source_offset_ms(&offsets, &main_path)));
+    // 主歌词为 ASS 时，删除其中将被替换的同一轨道的行
+    lyrics.lines.retain(|line| !tracks.iter().any(|(track, _)| track.matches(&line.style, line.name.as_deref().unwrap_or(""))));
+
+    // 每个主歌词行之后要插入的翻译/罗马音行
+    let mut insertions: HashMap<usize, Vec<LyricLine>> = HashMap::new();
+    for (track, lrc_path) in &tracks {
+        let (mut entries, metadata) = read_lrc_file(lrc_path)?;
+        let offset_warp = TimeWarp::from_offset(source_offset_ms(&offsets, lrc_path));
+        for entry in &mut entries {
+            entry.time_ms = offset_warp.map(entry.time_ms);
+        }
+        // [offset] 已应用到时间上，不再作为元数据合并
+        lyrics.merge_metadata(metadata.into_iter().filter(|entry| entry.key != "offset"));
+
+        let (matched, unmatched) = match_lrc_track(&lyrics.lines, lrc_path, track, &entries, args.tolerance_ms);
+        warning_occurred |= unmatched;
+        for (entry, main_index) in matched {
+            let main_line = &lyrics.lines[main_index];
+            insertions.entry(main_index).or_default().push(LyricLine {
+                line_number: main_line.line_number,
+                start_ms: main_line.start_ms,
+                end_ms: main_line.end_ms,
+                style: track.style(),
+                name: Some(track.name()).filter(|name| !name.is_empty()),
+                syllables: vec![Syllable {
+                    text: entry.text.clone(),
+                    start_ms: main_line.start_ms,
+                    duration_ms: main_line.duration_ms(),
+                    is_gap: false,
+                    ass_tags: String::new(),
+                    ruby: None,
+                }],
+                effect: None,
+                song_part: main_line.song_part.clone(),
+                untimed: true,
+                position: None,
+            });
+        }
+    }
+
+    // 把翻译/罗马音行插入到对应的主歌词行之后
+    let mut assembled = Vec::with_capacity(lyrics.lines.len() + insertions.values().map(Vec::len).sum::<usize>());
+    for (index, line) in std::mem::take(&mut lyrics.lines).into_iter().enumerate() {
+        assembled.push(line);
+        assembled.extend(insertions.remove(&index).unwrap_or_default());
+    }
+    lyrics.lines = assembled;
+    warning_occurred |= apply_conversion_options(&mut lyrics, &options);
+
+    let output_path = args.output.clone().map(clean_path_buf_quotes).unwrap_or_else(|| auto_output_path(&main_path, ASS_EXTENSION));
+    write_ass_file(&lyrics, &output_path, &options)?;
+    log_success!("已生成 {:?}。", output_path);
+    Ok(warning_occurred)
+}
+
+
+// --- 辅助函数 ---
+
+/// 将 ASS 文件头和样式信息写入 Writer。
+/// 用于 `convert_qrc_to_ass` 和 `convert_lys_to_ass`。
+/// `furigana` 为 true 时额外写出 Aegisub 卡拉 OK 模板使用的注音样式 "Default-furigana"。
+fn write_ass_header(writer: &mut impl Write, furigana: bool) -> io::Result<()> {
+    // 写入 [Script Info] 段，包含脚本元信息和播放器参数建议
+    writeln!(writer, "[Script Info]")?;
+    writeln!(writer, "PlayResX: 1920")?; // 建议播放器渲染分辨率宽度
+    writeln!(writer, "PlayResY: 1440")?; // 建议播放器渲染分辨率高度
+    writeln!(writer)?;
+
+    // 写入 [V4+ Styles] 段，定义样式
+    writeln!(writer, "[V4+ Styles]")?;
+    // 定义样式的格式 (字段顺序)
+    writeln!(writer, "Format: Name, Fontname, Fontsize, PrimaryColour, SecondaryColour, OutlineColour, BackColour, Bold, Italic, Underline, StrikeOut, ScaleX, ScaleY, Spacing, Angle, BorderStyle, Outline, Shadow, Alignment, MarginL, MarginR, MarginV, Encoding")?;
+    // 定义一个名为 "Default" 的样式，可以根据需要修改字体、颜色、边框等参数
+    writeln!(writer, "Style: Default,微软雅黑,100,&H00FFFFFF,&H004E503F,&H00000000,&H00000000,0,0,0,0,100,100,0,0,1,1.5,0.5,2,10,10,60,1")?;
+    if furigana {
+        writeln!(writer, "Style: Default{},微软雅黑,50,&H00FFFFFF,&H004E503F,&H00000000,&H00000000,0,0,0,0,100,100,0,0,1,1.5,0.5,2,10,10,60,1", FURIGANA_STYLE_SUFFIX)?;
+    }
+    writeln!(writer)?; // 空行分隔段落
+
+    // 写入 [Events] 段的头部，定义事件（即 Dialogue 行）的格式
+    writeln!(writer, "[Events]")?;
+    writeln!(writer, "Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text")?;
+
+    Ok(()) // 返回成功
+}
+
+
+/// 封装转换函数的执行过程，包括日志打印和错误处理。
+/// 返回 `true` 如果发生了错误或（未来可能实现的）需要用户注意的警告，否则返回 `false`。
+fn execute_conversion(
+    action: ConversionFnSig, // 接受一个转换函数作为参数
+    input_path: &Path,
+    output_path: &Path,
+    options: &ConversionOptions,
+) -> bool { // 返回 bool 表示是否出错/需要等待
+
+    // 执行传入的转换函数 `action`
+    match action(input_path, output_path, options) {
+        Ok(_) => {
+            // 转换成功，成功日志已在 action 内部打印
+            false // 返回 false 表示没有错误发生
+        }
+        Err(e) => {
+            // 转换过程中发生错误
+            let formatted_error_msg = format!("{} {}", CONVERSION_ERROR_MSG, e); // 格式化错误信息
+            log_error!("{}", formatted_error_msg); // 打印错误日志
+            true // 返回 true 表示发生了错误，可能需要等待
+        }
+    }
+}
+
+
+/// 在程序退出前暂停，等待用户按 Enter 键。
+/// 主要用于在命令行模式下，发生错误或警告后给用户时间查看信息。
+fn wait_for_exit() {
+     log_info!("按下 Enter 键退出..."); // 提示用户操作
+     let mut dummy = String::new(); // 用于存储读取的行（内容不重要）
+     let _ = io::stdin().read_line(&mut dummy);
+}
+
+
+/// 根据输入路径和目标扩展名，自动生成输出文件的路径。
+/// 输出路径与输入路径在同一目录下，文件名添加 "_converted" 后缀。
+fn auto_output_path(input_path: &Path, output_ext_with_dot: &str) -> PathBuf {
+    // 获取输入文件的文件名（不含扩展名），如果失败则使用 "output" 作为默认值
+    let file_stem = input_path
+        .file_stem()
+        .and_then(|s| s.to_str()) // OsStr -> Option<&str>
+        .unwrap_or("output");
+
+    // 构建新的文件名：原始文件名 + "_converted" + 目标扩展名
+    let new_file_name = format!("{}_converted{}", file_stem, output_ext_with_dot);
+
+    // 返回与输入文件同目录，但使用新文件名的 PathBuf
+    input_path.with_file_name(new_file_name)
+}
+
+
+/// 读取用户在命令行中的单行输入。
+///
+/// # Arguments
+/// * `prompt` - 显示给用户的提示信息。
+///
+/// # Returns
+/// * `Ok(String)` - 用户输入的文本（已去除首尾空白）。
+/// * `Err(ConversionError)` - 读取过程中发生 IO 错误。
+fn read_user_input(prompt: &str) -> Result<String, ConversionError> {
+    print!("{}", prompt); // 显示提示信息
+    io::stdout().flush()?; // 确保提示信息立即显示在控制台
+
+    let mut input = String::new(); // 创建一个空字符串用于存储输入
+    io::stdin().read_line(&mut input)?; // 从标准输入读取一行
+    Ok(input.trim().to_string()) // 去除输入字符串首尾的空白字符并返回
+}
+
+
+/// 读取用户输入的文件路径，并处理路径中可能包含的引号。
+///
+/// # Arguments
+/// * `prompt_template` - 提示信息模板，其中 "{}" 会被 `extension` 替换。
+/// * `extension` - 期望的文件扩展名（用于显示在提示信息中）。
+///
+/// # Returns
+/// * `Ok(PathBuf)` - 用户输入的有效文件路径。
+/// * `Err(ConversionError)` - 读取或处理路径过程中发生错误。
+fn read_file_path(prompt_template: &str, extension: &str) -> Result<PathBuf, ConversionError> {
+    loop {
+        // read_user_input 内部已经调用了 trim()，移除了原始输入两端的空白字符
+        let path_str_input = read_user_input(&prompt_template.replace("{}", extension))?;
+        
+        let mut current_path_slice: &str = &path_str_input;
+
+        // 迭代移除成对的引号，直到不再有变化或者字符串长度不足以包含引号
+        loop {
+            let len_before_strip = current_path_slice.len();
+            if len_before_strip >= 2 { // 必须至少有两个字符才能构成一对引号
+                if (current_path_slice.starts_with('"') && current_path_slice.ends_with('"'))
+                    || (current_path_slice.starts_with('\'') && current_path_slice.ends_with('\''))
+                {
+                    current_path_slice = &current_path_slice[1..len_before_strip - 1];
+                } else {
+                    // 如果两端不是匹配的同类型引号，则停止剥离
+                    break;
+                }
+            } else {
+                // 长度不足2，不可能有成对引号
+                break;
+            }
+
+            // 如果本次迭代没有改变长度，说明没有更多可剥离的成对引号
+            if current_path_slice.len() == len_before_strip {
+                break;
+            }
+        }
+
+        if current_path_slice.is_empty() {
+            let formatted_msg = format!("{} {}", EMPTY_FILE_PATH_ERROR.replace("{}", ""), extension); // 确保 {} 被替换
+            log_error!("{}", formatted_msg);
+            continue;
+        }
+        return Ok(PathBuf::from(current_path_slice.to_owned())); // 使用处理后的字符串创建 PathBuf
+    }
+}
+
+/// 辅助函数：清理 PathBuf 中可能包含的、包裹整个路径的引号。
+fn clean_path_buf_quotes(path_buf: PathBuf) -> PathBuf {
+    let path_str_cow = path_buf.to_string_lossy(); // Cow<'a, str>
+
+    let mut current_path_slice: &str = path_str_cow.as_ref();
+    let original_len = current_path_slice.len();
+
+    loop {
+        let len_before_strip = current_path_slice.len();
+        if len_before_strip >= 2 {
+            if (current_path_slice.starts_with('"') && current_path_slice.ends_with('"'))
+                || (current_path_slice.starts_with('\'') && current_path_slice.ends_with('\''))
+            {
+                current_path_slice = &current_path_slice[1..len_before_strip - 1];
+            } else {
+                break;
+            }
+        } else {
+            break;
+        }
+        if current_path_slice.len() == len_before_strip {
+            break;
+        }
+    }
+
+    // 仅当字符串内容确实发生了改变时（即引号被移除了），才创建新的 PathBuf
+    // 否则，返回原始的 PathBuf 以避免不必要的分配和潜在的 non-UTF8 路径信息的丢失。
+    if current_path_slice.len() < original_len {
+        PathBuf::from(current_path_slice.to_owned())
+    } else {
+        path_buf // 没有变化，返回原始 PathBuf
+    }
+}
+
+
+/// 在命令行中显示一个简单的文本进度条。
+/// 仅当文件总大小超过阈值时显示。
+///
+/// # Arguments
+/// * `current` - 当前已处理的大小（例如字节数）。
+/// * `total` - 文件总大小。
+fn display_progress_bar(current: usize, total: usize) {
+    // 如果总大小为 0 或小于预设阈值，则不显示进度条
+    if total == 0 || total < PROGRESS_BAR_THRESHOLD { return; }
+
+    // 计算当前进度百分比 (限制在 0.0 到 100.0 之间)
+    let percentage = (current as f64 / total as f64 * 100.0).min(100.0);
+    // 根据百分比计算进度条中已填充部分的长度
+    let filled_length = (PROGRESS_BAR_LENGTH as f64 * percentage / 100.0) as usize;
+    // 构建进度条的可视化字符串 (例如 "[=======      ]")
+    let bar = format!(
+        "{}{}", // 由填充部分和空白部分组成
+        "=".repeat(filled_length), // 填充部分用 "=" 表示
+        " ".repeat(PROGRESS_BAR_LENGTH.saturating_sub(filled_length)) // 空白部分用 " " 表示 (使用 saturating_sub 防止负数)
+    );
+    // 使用 \r (回车符) 将光标移到行首，实现原地更新进度条
+    // 打印格式: "转换进度: [========>     ]  XX% (current/total)"
+    print!("\r转换进度: [{}] {:>3.0}% ({}/{})", bar, percentage, current, total);
+    // 刷新标准输出流，确保进度条立即显示出来
+    let _ = io::stdout().flush(); // 忽略 flush 可能产生的错误
+}
+
+
+/// 将毫秒数转换为 ASS 时间格式字符串 (H:MM:SS.cs)。
+fn milliseconds_to_time(ms: usize) -> String {
+    // 计算小时、分钟、秒和厘秒
+    let hours = ms / MILLISECONDS_PER_HOUR;
+    let minutes = (ms % MILLISECONDS_PER_HOUR) / MILLISECONDS_PER_MINUTE;
+    let seconds = (ms % MILLISECONDS_PER_MINUTE) / MILLISECONDS_PER_SECOND;
+    // 厘秒 = 毫秒部分 / 10
+    let centiseconds = (ms % MILLISECONDS_PER_SECOND) / CENTISECONDS_TO_MILLISECONDS;
+
+    // 格式化为 "H:MM:SS.cs" 字符串，注意使用 {:0X} 进行零填充
+    format!("{:01}:{:02}:{:02}.{:02}", hours, minutes, seconds, centiseconds)
+}
+
+/// 将 ASS 时间格式字符串 (H:MM:SS.cs) 转换为毫秒数。
+fn time_to_milliseconds(time_str: &str) -> Result<usize, ConversionError> {
+    // 使用 ':' 和 '.' 作为分隔符分割时间字符串
+    let parts: Vec<&str> = time_str.split(&[':', '.'][..]).collect();
+    // ASS 时间格式必须有 4 个部分 (H, MM, SS, cs)
+    if parts.len() != 4 {
+        return Err(ConversionError::InvalidFormat(format!(
+            "无效 ASS 时间格式: '{}', 期望 H:MM:SS.cs", time_str
+        )));
+    }
+
+    // 解析每个部分为数字，并乘以相应的毫秒转换系数
+    // 使用 `?` 操作符处理 ParseIntError，它会自动通过 From trait 转换为 ConversionError
+    let h = parts[0].parse::<usize>()? * MILLISECONDS_PER_HOUR;
+    let m = parts[1].parse::<usize>()? * MILLISECONDS_PER_MINUTE;
+    let s = parts[2].parse::<usize>()? * MILLISECONDS_PER_SECOND;
+    let cs = parts[3].parse::<usize>()? * CENTISECONDS_TO_MILLISECONDS;
+
+    // 返回各部分毫秒数之和
+    Ok(h + m + s + cs)
+}
+
+/// 判断 ASS 事件行是否作为歌词读取:
+/// * Effect 为 fx 的 Dialogue (Aegisub 卡拉 OK 模板生成的行) 以及 Effect 以 template/code 开头的 Comment 跳过；
+/// * Effect 为 karaoke 的 Comment (应用模板后保留的原始歌词) 作为歌词；
+/// * 其他 Comment 只在配置了 include_comments (--include-comments) 时作为歌词；
+/// * 配置了 layers (--layer) 时，其他 Layer 的行跳过。
+///
+/// # Returns
+/// * `Some(Cow<str>)` - 作为歌词的行，Comment 已改写为以 "Dialogue:" 开头，可直接用 ASS_DIALOGUE_REGEX 解析。
+/// * `None` - 其他行 (包括非事件行)。
+fn ass_lyric_event(line: &str) -> Option<Cow<'_, str>> {
+    let (is_comment, body) = match (line.strip_prefix("Dialogue:"), line.strip_prefix("Comment:")) {
+        (Some(body), _) => (false, body),
+        (None, Some(body)) => (true, body),
+        (None, None) => return None,
+    };
+    // Layer,Start,End,Style,Name,MarginL,MarginR,MarginV,Effect,Text
+    let fields: Vec<&str> = body.splitn(10, ',').collect();
+    let effect = fields.get(8).map_or("", |effect| effect.trim()).to_lowercase();
+    let is_templater = if is_comment {
+        TEMPLATER_EFFECT_PREFIXES.iter().any(|prefix| effect.starts_with(prefix))
+    } else {
+        effect == TEMPLATER_FX_EFFECT
+    };
+    if is_templater || (is_comment && effect != KARAOKE_EFFECT && !lyrics_config().include_comments) {
+        return None;
+    }
+    let layers = &lyrics_config().layers;
+    if !layers.is_empty() && !fields[0].trim().parse::<u32>().is_ok_and(|layer| layers.contains(&layer)) {
+        return None;
+    }
+    if is_comment {
+        Some(Cow::Owned(format!("Dialogue:{}", body)))
+    } else {
+        Some(Cow::Borrowed(line))
+    }
+}
+
+/// Dialogue 或 Comment 行的 Effect 字段 (已去除首尾空白)，其他行返回 None。
+fn ass_event_effect(line: &str) -> Option<&str> {
+    let body = line.strip_prefix("Dialogue:").or_else(|| line.strip_prefix("Comment:"))?;
+    body.split(',').nth(8).map(str::trim)
+}
+
+/// Effect 是否参与演唱者规则匹配 (精确毫秒记录和卡拉 OK 模板的 karaoke 标记除外)。
+fn is_rule_effect(effect: &str) -> bool {
+    !effect.is_empty() && !effect.starts_with(EXACT_TIMING_EFFECT_PREFIX) && !effect.eq_ignore_ascii_case(KARAOKE_EFFECT)
+}
+
+/// 检查 ASS 文件中是否存在与演唱者规则 (见 `classify_singer`) 匹配的主歌词 Dialogue 行。
+/// 默认规则下包括 Name 为空、"左"/"v1"/"合"/"v1000"、"右"/"v2"/"x-duet"/"x-anti"、"背"/"x-bg" 的行。
+/// 用于自动模式判断 ASS 文件应转为 LYS (如果存在) 还是 QRC。
+fn check_ass_has_special_names(ass_path: &Path) -> Result<bool, ConversionError> {
+    let file = File::open(ass_path)?;
+    let reader = BufReader::new(file);
+    let mut after_format = false; // 标记是否已找到 [Events] 段的 Format 行
+
+    for line_result in reader.lines() {
+        let line = line_result?;
+
+        // 首先定位到 Format 行
+        if !after_format {
+            if line.trim_start().starts_with("Format: Layer, Start, End, Style, Name,") {
+                after_format = true;
+            }
+            continue;
+        }
+
+        // 在 Format 行之后，只检查主歌词行的 Style、Name 和 Effect
+        if let Some(line) = ass_lyric_event(&line)
+            && let Some(caps) = ASS_DIALOGUE_REGEX.captures(&line)
+            && style_role(&caps["style"]) == StyleRole::Main
+        {
+            let effect = caps["effect"].trim();
+            let effect = is_rule_effect(effect).then_some(effect);
+            if classify_singer(caps["style"].trim(), Some(&caps["name"]), effect).is_some() {
+                return Ok(true); // 只要找到一个匹配规则的行，就认为文件“特殊”，返回 true
+            }
+        }
+    } // 文件读取结束
+
+    // 遍历完所有行都没有找到匹配规则的行，则返回 false
+    Ok(false)
+}
+
+/// 检查歌词中每一行的 K 标签总和与行时长是否一致 (翻译和罗马音行的 K 标签通常不完整，不进行检查)。
+///
+/// # Returns
+/// * `true` - 如果所有行都一致；否则为 `false`，并已为每个不一致的行打印警告。
+fn check_lyrics_time_consistency(lyrics: &Lyrics) -> bool {
+    let mut all_consistent = true;
+    for line in lyrics.lines.iter().filter(|line| !is_auxiliary_style(&line.style)) {
+        all_consistent &= check_time_consistency(line.duration_ms(), line.sum_syllables_ms(), line.line_number);
+    }
+    all_consistent
+}
+
+/// 检查 ASS Dialogue 行定义的总时长与内部所有 {\k} 标签计算出的时长之和是否一致。
+///
+/// # Arguments
+/// * `expected_duration` - Dialogue 行定义的结束时间减去开始时间 (毫秒)。
+/// * `actual_duration` - 从行内所有 {\kX} 标签计算出的 X * 10 的总和 (毫秒)。
+/// * `line_number` - 当前处理的 Dialogue 在原始文件中的行号 (用于日志)。
+///
+/// # Returns
+/// * `true` - 如果时长一致。
+/// * `false` - 如果时长不一致，并会打印警告信息。
+fn check_time_consistency(expected_duration: usize, actual_duration: usize, line_number: usize) -> bool {
+    // 比较期望时长和实际计算时长
+    if expected_duration != actual_duration {
+        // 如果不一致，使用 log_warn! 宏打印带颜色的警告信息
+        log_warn!(
+            "第 {} 行 K tags 时间总和 {}{}{} ms 与行定义持续时间 {}{}{} ms 不匹配",
+            line_number,
+            RED, actual_duration, RESET, // K 标签总和用红色显示
+            GREEN, expected_duration, RESET // 行定义持续时间用绿色显示
+        );
+        return false; // 返回 false 表示时间不一致
+    }
+    true // 时间一致，返回 true
+}
+
+
+/// 解析 ASS 元数据 Comment 行的文本部分 (例如 "musicName:歌曲名")。
+/// 无法识别的键也会保留，以便原样写回。
+///
+/// # Arguments
+/// * `text` - 元数据事件行的 Text 字段。
+///
+/// # Returns
+/// * `Some(MetadataEntry)` - 如果成功解析出键和值。
+/// * `None` - 如果文本中没有冒号，或者键或值为空。
+fn parse_ass_metadata_text(text: &str) -> Option<MetadataEntry> {
+    // 按第一个冒号拆分为键 (key) 和值 (value)，并去除首尾空格
+    let (key, value) = text.split_once(':')?;
+    let (key, value) = (key.trim(), value.trim());
+    if key.is_empty() || value.is_empty() {
+        return None;
+    }
+    Some(MetadataEntry { key: key.to_string(), value: value.to_string() })
+}
+
+
+/// 识别并解析 ASS 中的元数据事件行。
+/// Style 为 "meta" (不区分大小写) 的 Comment 或 Dialogue 都视为元数据，不要求时间、Name 或边距为特定值。
+///
+/// # Returns
+/// * `Some(Ok(MetadataEntry))` - 成功解析的元数据。
+/// * `Some(Err(String))` - 该行看起来像元数据但无法使用 (返回原因)，调用方应发出警告。
+/// * `None` - 该行不是元数据。
+fn parse_ass_metadata_event(line: &str) -> Option<Result<MetadataEntry, String>> {
+    let caps = META_EVENT_REGEX.captures(line)?;
+    let style = caps["style"].trim();
+    let text = strip_ass_tags(&caps["text"]);
+
+    if style.eq_ignore_ascii_case("meta") {
+        return Some(parse_ass_metadata_text(&text).ok_or_else(|| format!("无法解析为 key:value: '{}'", text)));
+    }
+
+    // Style 不是 meta，但 Comment 的内容是已知的元数据键 (例如 Style 被误改为 Default)
+    let entry = parse_ass_metadata_text(&text)?;
+    let is_known_key = METADATA_TAG_MAP.iter().any(|(key, _)| *key == entry.key) || MULTI_VALUE_METADATA_KEYS.contains(&entry.key.as_str());
+    if &caps["kind"] == "Comment" && is_known_key {
+        return Some(Err(format!("'{}:{}' 的 Style 是 '{}' 而不是 'meta'", entry.key, entry.value, style)));
+    }
+    None
+}
+
+
+/// 解析 QRC/LYS 文件头中的 LRC 风格元数据标签 (例如 "[ti:歌曲名]")，并把标签映射回 ASS `meta` 键。
+/// 不在 `METADATA_TAG_MAP` 中的标签直接作为键保留；多值键 (例如 "[ar:歌手A/歌手B]") 会拆分为多条元数据。
+fn parse_lrc_metadata_tag(line: &str) -> Option<Vec<MetadataEntry>> {
+    let caps = LRC_METADATA_TAG_REGEX.captures(line.trim())?;
+    let (tag, value) = (&caps[1], caps[2].trim());
+    if value.is_empty() {
+        return None;
+    }
+    let key = metadata_key_for_tag(tag);
+    let values = split_metadata_values(key, value);
+    Some(values.into_iter().map(|v| MetadataEntry { key: key.to_string(), value: v.to_string() }).collect())
+}
+
+
+/// 把 LRC 风格标签 (例如 "ti") 映射为 ASS `meta` 键 (例如 "musicName")，无法映射时原样返回。
+fn metadata_key_for_tag(tag: &str) -> &str {
+    METADATA_TAG_MAP.iter().find(|(_, known_tag)| *known_tag == tag).map_or(tag, |(key, _)| *key)
+}
+
+
+/// 拆分元数据值：多值键按 `METADATA_VALUE_SEPARATOR` 拆分，其他键保持为单个值。空值会被过滤。
+fn split_metadata_values<'a>(key: &str, value: &'a str) -> Vec<&'a str> {
+    if MULTI_VALUE_METADATA_KEYS.contains(&key) {
+        value.split(METADATA_VALUE_SEPARATOR).map(str::trim).filter(|v| !v.is_empty()).collect()
+    } else {
+        Some(value.trim()).filter(|v| !v.is_empty()).into_iter().collect()
+    }
+}
+
+
+/// 将元数据格式化为 LRC 风格的文件头标签 (例如 "[ti:歌曲名]")，按键首次出现的顺序排列。
+/// 多值键的所有值合并为一个标签。
+///
+/// # Arguments
+/// * `native_only` - 为 true 时只输出 `METADATA_TAG_MAP` 中有原生标签的键 (用于 LRC)，否则保留所有键 (用于 QRC/LYS)。
+fn format_metadata_header(metadata: &[MetadataEntry], native_only: bool) -> Vec<String> {
+    let mut tags = Vec::new();
+    let mut written_keys: Vec<&str> = Vec::new();
+    for entry in metadata {
+        let native_tag = METADATA_TAG_MAP.iter().find(|(key, _)| *key == entry.key).map(|(_, tag)| *tag);
+        if native_only && native_tag.is_none() {
+            continue;
+        }
+        let tag = native_tag.unwrap_or(entry.key.as_str());
+
+        if MULTI_VALUE_METADATA_KEYS.contains(&entry.key.as_str()) {
+            if written_keys.contains(&entry.key.as_str()) {
+                continue; // 该键的所有值已在第一次出现时合并写入
+            }
+            written_keys.push(&entry.key);
+            let values: Vec<&str> = metadata.iter().filter(|e| e.key == entry.key).map(|e| e.value.as_str()).collect();
+            tags.push(format!("[{}:{}]", tag, values.join(METADATA_VALUE_SEPARATOR)));
+        } else {
+            tags.push(format!("[{}:{}]", tag, entry.value));
+        }
+    }
+    tags
+}
+
+
+/// 核心辅助函数：解析单行 ASS Dialogue 字符串，提取所有关键信息存入 `LyricLine` 结构体。
+///
+/// # Arguments
+/// * `line` - 要解析的 ASS Dialogue 行字符串。
+/// * `line_number` - 该行在原始文件中的行号 (用于错误报告)。
+/// * `styles` - 文件中的样式，用于确定行的定位信息。
+///
+/// # Returns
+/// * `Ok(Some(LyricLine))` - 如果成功解析。
+/// * `Ok(None)` - 如果该行不是有效的 Dialogue 行格式。
+/// * `Err(ConversionError)` - 如果解析过程中发生错误 (例如时间格式错误、数字解析错误)。
+fn parse_ass_dialogue_line(line: &str, line_number: usize, styles: &HashMap<String, StylePosition>) -> Result<Option<LyricLine>, ConversionError> {
+    // 1. 尝试匹配整行结构，不匹配则认为不是有效的 Dialogue 行
+    let Some(caps) = ASS_DIALOGUE_REGEX.captures(line) else {
+        return Ok(None);
+    };
+
+    // 2. 从命名捕获组提取时间字符串 (匹配成功则必然存在这些组)
+    let start_time_str = caps.name("start_time").unwrap().as_str();
+    let end_time_str   = caps.name("end_time").unwrap().as_str();
+
+    // 3. 转换时间字符串为毫秒数
+    let start_ms = time_to_milliseconds(start_time_str)
+        .map_err(|e| ConversionError::InvalidFormat(format!("第 {} 行对话开始时间解析失败: {}", line_number, e)))?;
+    let end_ms = time_to_milliseconds(end_time_str)
+         .map_err(|e| ConversionError::InvalidFormat(format!("第 {} 行对话结束时间解析失败: {}", line_number, e)))?;
+
+    let style = caps.name("style").unwrap().as_str().trim().to_string(); // 获取 Style 并 trim
+
+    // 4. 提取 Name 字段内容，如果 Name 字段不为空，则存入 Some(String)，否则为 None
+    //    其中的段落标签 itunes:song-part=... 单独保存，写入 ASS 时再附加到 Name 末尾
+    let name_str = caps.name("name").unwrap().as_str();
+    let (name_str, song_part) = split_song_part_from_name(name_str);
+    let name: Option<String> = if name_str.is_empty() {
+        None
+    } else {
+        Some(name_str)
+    };
+
+    // 5. 提取 Effect (精确毫秒记录不计入) 和 Text 字段内容
+    let effect_str = caps.name("effect").unwrap().as_str().trim();
+    let effect = is_rule_effect(effect_str).then(|| effect_str.to_string());
+    let ass_text = caps.name("text").unwrap().as_str();
+
+    // 6. 解析 Text 字段中的 {\k} 标签和对应的文本段
+    //    ASS 的 K 标签是相对时长，音节的绝对开始时间从行开始时间依次累加
+    let tokens = tokenize_ass_karaoke_text(ass_text)
+        .map_err(|e| ConversionError::InvalidFormat(format!("第 {} 行 {}", line_number, e)))?;
+    let mut syllables = Vec::new();
+    let mut k_values = Vec::new();
+    let mut current_start_ms = start_ms;
+    for token in tokens {
+        let duration_ms = token.k_cs * K_TAG_MULTIPLIER;
+        // 没有文本的 K 标签 (例如 {\k20}) 表示停顿
+        syllables.push(Syllable {
+            is_gap: token.text.is_empty(),
+            text: token.text,
+            start_ms: current_start_ms,
+            duration_ms,
+            ass_tags: token.tags,
+            ruby: None,
+        });
+        k_values.push(token.k_cs);
+        current_start_ms += duration_ms;
+    }
+
+    // 没有 {\k} 标签的行 (例如翻译行) 作为纯文本行，整行文本视为一个音节
+    let plain_text = decode_ass_text(ass_text);
+    let untimed = k_values.is_empty() && !plain_text.is_empty();
+    if untimed {
+        syllables.push(Syllable {
+            text: plain_text,
+            start_ms,
+            duration_ms: end_ms.saturating_sub(start_ms),
+            is_gap: false,
+            ass_tags: String::new(),
+            ruby: None,
+        });
+    }
+
+    // 7. 根据 Style、Margin 和行内标签确定定位信息，构建 LyricLine 结构体
+    let margins = ["margin_l", "margin_r", "margin_v"].map(|group| caps[group].trim().parse().unwrap_or(0));
+    let position = DialoguePosition::from_dialogue(&style, styles.get(&style), margins, ass_text);
+    let mut lyric_line = LyricLine {
+        line_number,
+        start_ms,
+        end_ms,
+        style,
+        name,
+        effect,
+        song_part,
+        syllables,
+        untimed,
+        position: Some(position),
+    };
+
+    // 8. 如果 Effect 字段中有精确毫秒时间记录 (--exact-ms 生成)，还原未修改的时间
+    if let Some(entries) = caps.name("effect").and_then(|m| parse_exact_timing_effect(m.as_str())) {
+        restore_exact_timing(&mut lyric_line, &k_values, &entries);
+    }
+
+    Ok(Some(lyric_line))
+}
+
+
+/// 判断 Style 是否为翻译或罗马音等辅助行 (按配置的样式角色，默认 "ts"/"trans"/"roma"，不区分大小写)。
+/// 这些行不参与 K 标签时间一致性检查，也不会写入 QRC/LYS。
+fn is_auxiliary_style(style: &str) -> bool {
+    matches!(style_role(style), StyleRole::Translation(_) | StyleRole::Romanization(_))
+}
+
+
+// --- 配置文件 (样式角色) ---
+
+impl StyleRole {
+    /// 解析配置文件中的角色字符串 (不区分大小写)，例如 "main"、"translation:zh"、"romanization"、"ignore"。
+    fn parse(text: &str) -> Result<Self, String> {
+        let (role, lang) = match text.split_once(':') {
+            Some((role, lang)) => (role.trim(), Some(lang.trim()).filter(|lang| !lang.is_empty()).map(str::to_string)),
+            None => (text.trim(), None),
+        };
+        match (role.to_lowercase().as_str(), lang) {
+            ("main", None) => Ok(StyleRole::Main),
+            ("ignore", None) => Ok(StyleRole::Ignore),
+            ("translation", lang) => Ok(StyleRole::Translation(lang)),
+            ("romanization", lang) => Ok(StyleRole::Romanization(lang)),
+            _ => Err(format!(
+                "无效的样式角色 '{}'，应为 main、translation[:语言代码]、romanization[:语言代码] 或 ignore",
+                text
+            )),
+        }
+    }
+}
+
+impl LyricsConfig {
+    /// 解析 TOML 格式的配置文件内容。
+    fn from_toml(text: &str) -> Result<Self, String> {
+        let file: ConfigFile = toml::from_str(text).map_err(|e| e.to_string())?;
+        let mut style_roles = HashMap::new();
+        let mut style_names = Vec::new();
+        for (style, role) in &file.styles {
+            let role = StyleRole::parse(role).map_err(|e| format!("[styles] {}: {}", style, e))?;
+            style_roles.insert(style.trim().to_lowercase(), role.clone());
+            // 轨道的语言代码为小写，这里同样统一为小写以便按角色查找 Style
+            let lowercase_role = match role {
+                StyleRole::Translation(lang) => StyleRole::Translation(lang.map(|lang| lang.to_lowercase())),
+                StyleRole::Romanization(lang) => StyleRole::Romanization(lang.map(|lang| lang.to_lowercase())),
+                role => role,
+            };
+            style_names.push((style.trim().to_string(), lowercase_role));
+        }
+        style_names.sort_by(|(a, _), (b, _)| a.cmp(b));
+        for (index, rule) in file.singer_rules.iter().enumerate() {
+            if rule.names.is_empty() && rule.styles.is_empty() && rule.effects.is_empty() {
+                return Err(format!("[[singer_rules]] 第 {} 条规则没有指定 names、styles 或 effects", index + 1));
+            }
+            if rule.side.is_none() && rule.background.is_none() {
+                return Err(format!("[[singer_rules]] 第 {} 条规则没有指定 side 或 background", index + 1));
+            }
+        }
+        let mut singer_rules = file.singer_rules;
+        singer_rules.extend(default_singer_rules());
+        Ok(LyricsConfig {
+            style_roles,
+            style_names,
+            default_language: file.default_language.map(|lang| lang.trim().to_string()).filter(|lang| !lang.is_empty()),
+            singer_rules,
+            include_comments: file.include_comments,
+            layers: file.layers,
+            karaoke_syntax: file.karaoke_syntax.unwrap_or_default(),
+        })
+    }
+}
+
+/// 读取配置文件并设置为全局配置。
+/// `path` 为 None 时尝试读取程序所在目录下的 `DEFAULT_CONFIG_FILE_NAME`，不存在则使用默认配置。
+/// 命令行的 `--include-comments`、`--layer` 和 `--karaoke-syntax` 覆盖配置文件中的对应设置。
+fn load_lyrics_config(
+    path: Option<&Path>,
+    include_comments: bool,
+    layers: &[u32],
+    karaoke_syntax: Option<KaraokeSyntax>,
+) -> Result<(), ConversionError> {
+    let path = match path {
+        Some(path) => Some(clean_path_buf_quotes(path.to_path_buf())),
+        None => std::env::current_exe()
+            .ok()
+            .and_then(|exe| exe.parent().map(|dir| dir.join(DEFAULT_CONFIG_FILE_NAME)))
+            .filter(|path| path.is_file()),
+    };
+    let mut config = match path {
+        Some(path) => {
+            let text = std::fs::read_to_string(&path)?;
+            let config = LyricsConfig::from_toml(&text).map_err(ConversionError::InvalidFormat)?;
+            log_info!("已加载配置文件: {:?}", path);
+            config
+        }
+        None => LyricsConfig::default(),
+    };
+    config.include_comments |= include_comments;
+    if !layers.is_empty() {
+        config.layers = layers.to_vec();
+    }
+    if let Some(karaoke_syntax) = karaoke_syntax {
+        config.karaoke_syntax = karaoke_syntax;
+    }
+    // 只在启动时调用一次，重复设置时保留第一次的配置
+    let _ = LYRICS_CONFIG.set(config);
+    Ok(())
+}
+
+/// 当前的全局配置。
+fn lyrics_config() -> &'static LyricsConfig {
+    LYRICS_CONFIG.get_or_init(LyricsConfig::default)
+}
+
+/// 查找 Style 对应的角色 (不区分大小写)。
+/// 配置中没有的 Style 使用内置规则: "ts"/"trans" 为翻译，"roma" 为罗马音，其他为主歌词。
+fn style_role(style: &str) -> StyleRole {
+    let style = style.trim().to_lowercase();
+    if let Some(role) = lyrics_config().style_roles.get(&style) {
+        return role.clone();
+    }
+    match style.as_str() {
+        "ts" | "trans" => StyleRole::Translation(None),
+        "roma" => StyleRole::Romanization(None),
+        _ => StyleRole::Main,
+    }
+}
+
+/// 确定一行的角色，并补全翻译/罗马音的语言代码 (统一转为小写)。
+/// 语言的优先级: Name 中的 x-lang:<语言代码> > 样式角色中指定的语言 > 配置的 default_language (仅翻译)。
+fn line_role(style: &str, name: Option<&str>) -> StyleRole {
+    let name_lang = name.and_then(|name| LANG_TAG_REGEX.captures(name.trim())).map(|caps| caps["lang_code"].to_string());
+    let resolve = |style_lang: Option<String>| name_lang.clone().or(style_lang).map(|lang| lang.to_lowercase());
+    match style_role(style) {
+        StyleRole::Translation(lang) => {
+            StyleRole::Translation(resolve(lang).or_else(|| lyrics_config().default_language.as_ref().map(|lang| lang.to_lowercase())))
+        }
+        StyleRole::Romanization(lang) => StyleRole::Romanization(resolve(lang)),
+        role => role,
+    }
+}
+
+
+/// 内置的演唱者规则 (排在配置文件中的规则之后):
+/// 空 Name、"左"/"v1"/"合"/"v1000" 为无背景左侧；"右"/"v2"/"x-duet"/"x-anti" 为无背景右侧；"背"/"x-bg" 为背景人声 (方向由前一行决定)。
+fn default_singer_rules() -> Vec<SingerRule> {
+    let rule = |names: &[&str], side: Option<DuetSide>, background: bool| SingerRule {
+        names: names.iter().map(|name| name.to_string()).collect(),
+        side,
+        background: Some(background),
+        ..SingerRule::default()
+    };
+    vec![
+        rule(&["左", "v1", "合", "v1000", ""], Some(DuetSide::Left), false),
+        rule(&["右", "v2", "x-duet", "x-anti"], Some(DuetSide::Right), false),
+        rule(&["背", "x-bg"], None, true),
+    ]
+}
+
+impl SingerRule {
+    /// 判断规则是否匹配一行的 Style、Name 和 Effect。
+    fn matches(&self, style: &str, name: Option<&str>, effect: Option<&str>) -> bool {
+        let name_tokens: Vec<&str> = name.map(|name| name.split_whitespace().collect()).unwrap_or_default();
+        let name_matches = if name_tokens.is_empty() {
+            self.names.iter().any(|rule_name| rule_name.trim().is_empty())
+        } else {
+            self.names.iter().any(|rule_name| name_tokens.contains(&rule_name.trim()))
+        };
+        name_matches
+            || self.styles.iter().any(|rule_style| rule_style.trim().eq_ignore_ascii_case(style.trim()))
+            || effect.is_some_and(|effect| {
+                effect
+                    .split_whitespace()
+                    .any(|token| self.effects.iter().any(|rule_effect| rule_effect.trim().eq_ignore_ascii_case(token)))
+            })
+    }
+}
+
+impl SingerInfo {
+    /// 对应的 LYS 属性值 (0-8)。背景人声未定方向时为 6，由 `calculate_lys_property` 根据前一行补全。
+    fn lys_property(self) -> usize {
+        match (self.background, self.side) {
+            (None, None) => LYS_PROPERTY_UNSET,
+            (None, Some(DuetSide::Left)) => LYS_PROPERTY_LEFT,
+            (None, Some(DuetSide::Right)) => LYS_PROPERTY_RIGHT,
+            (Some(false), None) => LYS_PROPERTY_NO_BACK_UNSET,
+            (Some(false), Some(DuetSide::Left)) => LYS_PROPERTY_NO_BACK_LEFT,
+            (Some(false), Some(DuetSide::Right)) => LYS_PROPERTY_NO_BACK_RIGHT,
+            (Some(true), None) => LYS_PROPERTY_BACK_UNSET,
+            (Some(true), Some(DuetSide::Left)) => LYS_PROPERTY_BACK_LEFT,
+            (Some(true), Some(DuetSide::Right)) => LYS_PROPERTY_BACK_RIGHT,
+        }
+    }
+
+    /// 由 LYS 属性值得到演唱者信息 (无法识别的值视为 0)。
+    fn from_lys_property(property: usize) -> Self {
+        let side = match property {
+            LYS_PROPERTY_LEFT | LYS_PROPERTY_NO_BACK_LEFT | LYS_PROPERTY_BACK_LEFT => Some(DuetSide::Left),
+            LYS_PROPERTY_RIGHT | LYS_PROPERTY_NO_BACK_RIGHT | LYS_PROPERTY_BACK_RIGHT => Some(DuetSide::Right),
+            _ => None,
+        };
+        let background = match property {
+            LYS_PROPERTY_NO_BACK_UNSET..=LYS_PROPERTY_NO_BACK_RIGHT => Some(false),
+            LYS_PROPERTY_BACK_UNSET..=LYS_PROPERTY_BACK_RIGHT => Some(true),
+            _ => None,
+        };
+        SingerInfo { side, background }
+    }
+
+    /// 是否为背景人声行。
+    fn is_background(self) -> bool {
+        self.background == Some(true)
+    }
+}
+
+/// 按演唱者规则 (配置的规则在前，内置规则在后) 判断一行的对唱方向和背景人声标记。
+/// 每一项取第一条设置了该项的匹配规则的值；没有任何规则匹配时返回 `None`。
+/// Name 中有 x-lys:<属性> 标签时直接使用该属性对应的信息。
+fn classify_singer(style: &str, name: Option<&str>, effect: Option<&str>) -> Option<SingerInfo> {
+    if let Some(property) = lys_property_from_name(name) {
+        return Some(SingerInfo::from_lys_property(property));
+    }
+    let mut info = SingerInfo::default();
+    let mut matched = false;
+    for rule in lyrics_config().singer_rules.iter().filter(|rule| rule.matches(style, name, effect)) {
+        matched = true;
+        info.side = info.side.or(rule.side);
+        info.background = info.background.or(rule.background);
+    }
+    matched.then_some(info)
+}
+
+/// 读取 Name 中的 x-lys:<属性> 标签 (只接受 0-8)。
+fn lys_property_from_name(name: Option<&str>) -> Option<usize> {
+    name?
+        .split_whitespace()
+        .filter_map(|token| token.strip_prefix(LYS_PROPERTY_NAME_PREFIX))
+        .find_map(|value| value.parse::<usize>().ok().filter(|&property| property <= LYS_PROPERTY_BACK_RIGHT))
+}
+
+/// `classify_singer` 的歌词行版本。
+fn classify_line_singer(line: &LyricLine) -> Option<SingerInfo> {
+    classify_singer(&line.style, line.name.as_deref(), line.effect.as_deref())
+}
+
+/// 为演唱者信息选择写入 ASS 的 Name: 取第一条方向和背景标记都相同的规则中的第一个非空 Name 词；
+/// 没有时只按方向匹配 (背景人声未定方向时只匹配同为背景人声的规则)。找不到则为空 Name。
+fn singer_name_for(info: SingerInfo) -> Option<String> {
+    let rules = &lyrics_config().singer_rules;
+    let first_name = |rule: &SingerRule| rule.names.iter().map(|name| name.trim()).find(|name| !name.is_empty()).map(str::to_string);
+    let exact = rules.iter().filter(|rule| rule.side == info.side && rule.background == info.background).find_map(first_name);
+    exact.or_else(|| match info.side {
+        Some(_) => rules.iter().filter(|rule| rule.side == info.side).find_map(first_name),
+        None if info.is_background() => {
+            rules.iter().filter(|rule| rule.side.is_none() && rule.background == Some(true)).find_map(first_name)
+        }
+        None => None,
+    })
+}
+
+/// 核心辅助函数：计算当前歌词行对应的 LYS 属性值。
+///
+/// # Arguments
+/// * `current_dialogue` - 当前正在处理的歌词行。
+/// * `previous_dialogue` - 上一个已处理的主歌词行 (如果是第一行则为 None)。
+/// * `last_calculated_property` - 上一行最终计算得到的 LYS 属性值 (用于处理连续背景人声行的情况)。
+///
+/// # Returns
+/// * `(usize, bool)` - 计算得到的 LYS 属性值，以及是否因无法识别的 Name 发出了警告。
+fn calculate_lys_property(
+    current_dialogue: &LyricLine,
+    previous_dialogue: Option<&LyricLine>,
+    last_calculated_property: usize,
+) -> (usize, bool) {
+    // Name 中记录了原始属性 (x-lys:<属性>) 时原样使用
+    if let Some(property) = lys_property_from_name(current_dialogue.name.as_deref()) {
+        return (property, false);
+    }
+
+    let Some(info) = classify_line_singer(current_dialogue) else {
+        // 没有任何规则匹配 (例如 "路人甲" 等非预定义的 Name)，记录警告并按默认方式处理
+        log_warn!(
+            "遇到未定义的 ASS Name 字段值 '{}'，将按默认方式处理。",
+            current_dialogue.name.as_deref().unwrap_or_default()
+        );
+        return (LYS_PROPERTY_UNSET, true);
+    };
+
+    // 背景人声行没有指定方向时，根据上一行决定
+    if info.is_background() && info.side.is_none() {
+        let previous_info = previous_dialogue.and_then(classify_line_singer);
+        let property = match previous_info {
+            Some(previous) if previous.is_background() => last_calculated_property, // 前一行也是背景人声 -> 继承上次计算结果
+            Some(SingerInfo { side: Some(side), .. }) => SingerInfo { side: Some(side), ..info }.lys_property(),
+            _ => LYS_PROPERTY_BACK_UNSET, // 没有前一行或前一行方向未定 -> 有背景未定左右
+        };
+        return (property, false);
+    }
+    (info.lys_property(), false)
+}
+
+/// 移除字符串中所有 ASS 标签 (形如 {\...} 的部分)，转义的花括号 `\{`、`\}` 保留。
+fn strip_ass_tags(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some((open, close)) = find_override_block(rest) {
+        result.push_str(&rest[..open]);
+        rest = &rest[close + 1..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// 查找第一个覆盖块 `{...}`，返回 '{' 和 '}' 的字节位置。
+/// 前面紧跟反斜杠的 '{' 是转义的花括号 (`\{`)，不是覆盖块的开始；没有闭合的 '{' 按普通文本处理 (与渲染器一致)。
+fn find_override_block(text: &str) -> Option<(usize, usize)> {
+    let open = text.char_indices().find(|&(index, c)| c == '{' && !text[..index].ends_with('\\'))?.0;
+    let close = open + text[open..].find('}')?;
+    Some((open, close))
+}
+
+/// ASS Text 字段中的一个卡拉 OK 音节：一个 \k 类标签及其后直到下一个 \k 标签的文本。
+struct AssKaraokeToken {
+    k_cs: usize,  // K 值 (厘秒)
+    text: String, // 已转换转义序列的文本 (见 `decode_ass_escapes`)
+    tags: String, // 属于该音节的其他覆盖标签，按出现顺序拼接
+}
+
+/// 把 ASS 覆盖块 `{...}` 的内容拆分为单个标签 (例如 "\k20"、"\1c&HFF0000&"、"\t(0,100,\fs20)")。
+/// 括号内的反斜杠属于同一个标签；第一个反斜杠之前的内容是注释，不返回。
+fn split_override_tags(block: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let mut depth = 0usize; // 括号嵌套深度
+    let mut tag_start: Option<usize> = None;
+    for (index, c) in block.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth = depth.saturating_sub(1),
+            '\\' if depth == 0 => {
+                if let Some(start) = tag_start {
+                    tags.push(block[start..index].trim_end());
+                }
+                tag_start = Some(index);
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = tag_start {
+        tags.push(block[start..].trim_end());
+    }
+    tags
+}
+
+/// ASS Text 字段中所有覆盖块的标签，按出现顺序排列 (见 `split_override_tags`)。
+fn ass_override_tags(text: &str) -> Vec<&str> {
+    let mut tags = Vec::new();
+    let mut rest = text;
+    while let Some((open, close)) = find_override_block(rest) {
+        tags.extend(split_override_tags(&rest[open + 1..close]));
+        rest = &rest[close + 1..];
+    }
+    tags
+}
+
+/// 解析 ASS Text 字段中的卡拉 OK 音节。
+/// K 标签可以位于覆盖块中的任意位置 (例如 `{\fad(100,0)\k20\1c&HFF0000&}`)，同一音节的其他标签
+/// (包括第一个 K 标签之前的标签) 保存在音节的 `tags` 中；第一个 K 标签之前的文本并入第一个音节。
+/// 没有闭合的 `{` 按普通文本处理 (与渲染器一致)。
+///
+/// # Returns
+/// * `Ok(Vec<AssKaraokeToken>)` - 所有音节，没有 K 标签时为空。
+/// * `Err(String)` - K 数值无法解析。
+fn tokenize_ass_karaoke_text(text: &str) -> Result<Vec<AssKaraokeToken>, String> {
+    let mut tokens: Vec<AssKaraokeToken> = Vec::new();
+    let mut leading_tags = String::new(); // 第一个 K 标签之前的标签
+    let mut leading_text = String::new(); // 第一个 K 标签之前的文本
+    let mut rest = text;
+
+    while !rest.is_empty() {
+        let (literal, block) = match find_override_block(rest) {
+            Some((open, close)) => (&rest[..open], Some(&rest[open + 1..close])),
+            None => (rest, None), // 没有覆盖块，剩余部分都是文本
+        };
+        match tokens.last_mut() {
+            Some(token) => token.text.push_str(literal),
+            None => leading_text.push_str(literal),
+        }
+        let Some(block) = block else { break };
+        rest = &rest[literal.len() + block.len() + 2..];
+
+        for tag in split_override_tags(block) {
+            if let Some(caps) = K_TAG_REGEX.captures(tag) {
+                let k_cs: usize = caps[1].parse().map_err(|e| format!("K 数值解析失败 ('{}'): {}", tag, e))?;
+                tokens.push(AssKaraokeToken { k_cs, text: String::new(), tags: String::new() });
+            } else {
+                match tokens.last_mut() {
+                    Some(token) => token.tags.push_str(tag),
+                    None => leading_tags.push_str(tag),
+                }
+            }
+        }
+    }
+
+    if let Some(first) = tokens.first_mut() {
+        first.tags.insert_str(0, &leading_tags);
+        first.text.insert_str(0, &leading_text);
+    }
+    for token in &mut tokens {
+        token.text = decode_ass_escapes(&token.text);
+    }
+    Ok(tokens)
+}
+
+/// 转换 ASS 文本中的转义序列：\N (强制换行) 转为 '\n'，\n (软换行，默认换行方式下显示为空格) 转为空格，
+/// \h (不换行空格) 转为 U+00A0，\{ 和 \} 转为花括号。写入 ASS 时由 `encode_ass_escapes` 转换回来。
+fn decode_ass_escapes(text: &str) -> String {
+    text.replace("\\N", "\n")
+        .replace("\\n", " ")
+        .replace("\\h", "\u{a0}")
+        .replace("\\{", "{")
+        .replace("\\}", "}")
+}
+
+/// `decode_ass_escapes` 的逆操作，用于把纯文本写入 ASS Text 字段 (文本中的花括号不会被当作覆盖块)。
+/// ASS 无法表示紧跟 N、n、h 或花括号的反斜杠，这样的反斜杠改为全角 '＼'，以免被当作转义序列。
+fn encode_ass_escapes(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => result.push_str("\\N"),
+            '\u{a0}' => result.push_str("\\h"),
+            '{' => result.push_str("\\{"),
+            '}' => result.push_str("\\}"),
+            '\\' if matches!(chars.peek(), Some('N' | 'n' | 'h' | '{' | '}')) => result.push('＼'),
+            c => result.push(c),
+        }
+    }
+    result
+}
+
+/// 移除 ASS 标签并转换转义序列，得到 ASS Text 字段的纯文本。
+fn decode_ass_text(text: &str) -> String {
+    decode_ass_escapes(&strip_ass_tags(text))
+}
+
+/// 把歌词文本中的换行和不换行空格转为普通空格，用于只支持单行纯文本的格式 (QRC、LYS、LRC)。
+fn to_single_line_text(text: &str) -> String {
+    text.replace(['\n', '\u{a0}'], " ")
+}
+
+/// 处理 Aegisub 卡拉 OK 的多段高亮和注音语法:
+/// * 文本为 "#" (或全角 "＃") 的音节延续前一个音节的高亮，时长并入前一个音节 ("#|じ" 的注音追加到前一个音节)；
+/// * "漢字|かんじ" 拆分为正文 "漢字" 和注音 "かんじ"；
+/// * "字|<じ" 表示该音节属于前一个音节的注音，读音追加到前一个注音，音节的注音记为空字符串。
+fn apply_karaoke_furigana(line: &mut LyricLine) {
+    let mut syllables: Vec<Syllable> = Vec::with_capacity(line.syllables.len());
+    for mut syllable in std::mem::take(&mut line.syllables) {
+        let (base, ruby) = match syllable.text.split_once('|') {
+            Some((base, ruby)) => (base.to_string(), Some(ruby.to_string())),
+            None => (syllable.text.clone(), None),
+        };
+
+        // 多段高亮: 去掉两者之间的停顿，延长前一个音节
+        if matches!(base.trim(), "#" | "＃")
+            && let Some(previous_index) = syllables.iter().rposition(|s| !s.is_gap)
+        {
+            syllables.truncate(previous_index + 1);
+            let previous = &mut syllables[previous_index];
+            previous.duration_ms = syllable.end_ms().saturating_sub(previous.start_ms);
+            if let Some(reading) = ruby.as_deref().map(|ruby| ruby.trim_start_matches('<')).filter(|ruby| !ruby.is_empty()) {
+                match ruby_owner(&syllables) {
+                    Some(owner) => syllables[owner].ruby.get_or_insert_with(String::new).push_str(reading),
+                    None => syllables[previous_index].ruby = Some(reading.to_string()),
+                }
+            }
+            continue;
+        }
+
+        syllable.text = base;
+        let owner = ruby_owner(&syllables);
+        syllable.ruby = match (ruby, owner) {
+            (Some(ruby), Some(owner)) if ruby.starts_with('<') => {
+                syllables[owner].ruby.get_or_insert_with(String::new).push_str(&ruby[1..]);
+                Some(String::new())
+            }
+            (Some(ruby), _) => Some(ruby.trim_start_matches('<').to_string()).filter(|ruby| !ruby.is_empty()),
+            (None, _) => None,
+        };
+        syllable.is_gap = syllable.text.is_empty() && syllable.ruby.is_none();
+        syllables.push(syllable);
+    }
+    line.syllables = syllables;
+}
+
+/// 丢弃所有音节的注音 (--furigana strip)，只有注音的空音节变为停顿。
+fn strip_furigana(lyrics: &mut Lyrics) {
+    for syllable in lyrics.lines.iter_mut().flat_map(|line| line.syllables.iter_mut()) {
+        if syllable.ruby.take().is_some() && syllable.text.is_empty() {
+            syllable.is_gap = true;
+        }
+    }
+}
+
+/// 找到最后一个音节所属注音的音节 (跳过停顿和属于该注音的 "|<" 音节)。
+/// 最后一个音节没有注音时返回 `None`。
+fn ruby_owner(syllables: &[Syllable]) -> Option<usize> {
+    for (index, syllable) in syllables.iter().enumerate().rev().filter(|(_, s)| !s.is_gap) {
+        match syllable.ruby.as_deref() {
+            Some("") => continue,
+            Some(_) => return Some(index),
+            None => return None,
+        }
+    }
+    None
+}
+
+/// 将毫秒数转换为 SRT 时间格式字符串 HH:MM:SS,mmm。
+fn milliseconds_to_srt_time(ms: usize) -> String {
+    let hours = ms / MILLISECONDS_PER_HOUR;
+    let minutes = (ms % MILLISECONDS_PER_HOUR) / MILLISECONDS_PER_MINUTE;
+    let seconds = (ms % MILLISECONDS_PER_MINUTE) / MILLISECONDS_PER_SECOND;
+    format!("{:02}:{:02}:{:02},{:03}", hours, minutes, seconds, ms % MILLISECONDS_PER_SECOND)
+}
+
+/// 将毫秒数转换为 LRC 时间格式字符串 [mm:ss.xx] (注意 xx 是百分秒)。
+fn milliseconds_to_lrc_time(ms: usize) -> String {
+    let minutes = ms / MILLISECONDS_PER_MINUTE; // 计算分钟
+    let seconds = (ms % MILLISECONDS_PER_MINUTE) / MILLISECONDS_PER_SECOND; // 计算秒
+    // 计算百分秒 (毫秒除以 10)
+    let hundredths = (ms % MILLISECONDS_PER_SECOND) / 10;
+    // 格式化输出，MM:SS.xx，注意补零
+    format!("[{:02}:{:02}.{:02}]", minutes, seconds, hundredths)
+}
+
+/// 从 ASS 文件中提取翻译和罗马音，按 `--extract-format` 写为对应格式 (默认 LRC)。
+/// 指定了 `--merged-lrc` 时还会额外生成合并的多语言 LRC。
+///
+/// # Returns
+/// * `bool` - 提取过程中是否出错或有警告 (需要暂停)。
+fn run_auxiliary_extraction(ass_path: &Path, options: &ConversionOptions) -> bool {
+    let mut needs_wait = false;
+    if options.merged_lrc {
+        match write_merged_lrc_file(ass_path, options) {
+            Ok(warned) => needs_wait |= warned,
+            Err(e) => {
+                log_error!("生成合并 LRC 时出错: {}", e);
+                needs_wait = true;
+            }
+        }
+    }
+
+    match extract_auxiliary_tracks(ass_path, options.extract_format, options) {
+        Ok(warned) => needs_wait |= warned,
+        Err(e) => {
+            log_error!("提取翻译和罗马音时出错: {}", e);
+            needs_wait = true;
+        }
+    }
+    needs_wait
+}
+
+
+/// 把 ASS 中的主歌词、翻译和罗马音合并写入一个 LRC 文件 (`<stem>.merged.lrc`)：
+/// 每个主歌词行之后依次写出与其时间重叠最多的各轨道行，所有行使用主歌词行的时间标签。
+///
+/// # Arguments
+/// 轨道顺序取自 `options.merged_lrc_order`，元素为 "main"、"roma" (或 "roma-<语言代码>") 或语言代码；
+/// 为空时使用 main、roma 和其他轨道 (按出现顺序)。未列出的轨道不会写出。
+/// 背景人声行按 `options.background_mode` 处理 (见 `merge_background_lines`)，指定了 `options.song_part_tags` 时写出段落标签。
+///
+/// # Returns
+/// * `Ok(bool)` - 是否有没有语言或无法配对的翻译/罗马音行 (或读取时发生了警告)。
+fn write_merged_lrc_file(ass_path: &Path, options: &ConversionOptions) -> Result<bool, ConversionError> {
+    let (mut lyrics, mut warning_occurred) = read_ass_file(ass_path)?;
+    warning_occurred |= merge_background_lines(&mut lyrics, options.background_mode);
+    let order = &options.merged_lrc_order;
+
+    // 区分主歌词行和各轨道的行
+    let mut main_lines: Vec<&LyricLine> = Vec::new();
+    let mut auxiliary_lines: Vec<(AuxiliaryTrack, &LyricLine)> = Vec::new();
+    for line in &lyrics.lines {
+        if !is_auxiliary_style(&line.style) {
+            main_lines.push(line);
+        } else if let Some(track) = AuxiliaryTrack::from_line(&line.style, line.name.as_deref()) {
+            auxiliary_lines.push((track, line));
+        } else {
+            // 没有语言的翻译行无法归入任何轨道
+            log_warn!("第 {} 行翻译 '{}' 没有语言 (Name 中的 x-lang:<语言代码> 或配置的语言)，已忽略。", line.line_number, line.text());
+            warning_occurred = true;
+        }
+    }
+    main_lines.sort_by_key(|line| line.start_ms);
+
+    // 确定轨道顺序 ("main" 表示主歌词)
+    let order: Vec<String> = if order.is_empty() {
+        let mut default_order = vec!["main".to_string(), "roma".to_string()];
+        for (track, _) in &auxiliary_lines {
+            let key = track.key();
+            if !default_order.contains(&key) {
+                default_order.push(key);
+            }
+        }
+        default_order
+    } else {
+        order.iter().map(|token| token.trim().to_lowercase()).collect()
+    };
+
+    // 按时间重叠配对: 每个翻译/罗马音行归属于与其重叠时长最多的主歌词行
+    let mut paired: HashMap<usize, Vec<(AuxiliaryTrack, &LyricLine)>> = HashMap::new();
+    for (track, line) in auxiliary_lines {
+        let best = main_lines
+            .iter()
+            .enumerate()
+            .map(|(index, main)| (index, line.end_ms.min(main.end_ms).saturating_sub(line.start_ms.max(main.start_ms))))
+            .filter(|(_, overlap)| *overlap > 0)
+            .max_by_key(|(index, overlap)| (*overlap, std::cmp::Reverse(*index)));
+        match best {
+            Some((index, _)) => paired.entry(index).or_default().push((track, line)),
+            None => {
+                log_warn!("第 {} 行{} '{}' 没有与之时间重叠的主歌词行，已忽略。", line.line_number, track, line.text());
+                warning_occurred = true;
+            }
+        }
+    }
+
+    let lrc_path = ass_path.with_file_name(format!("{}.merged.lrc", ass_path.file_stem().unwrap_or_default().to_string_lossy()));
+    log_info!("正在生成合并 LRC 文件: {:?}", lrc_path.file_name().unwrap_or_default());
+    let mut writer = BufWriter::new(File::create(&lrc_path)?);
+    for tag in format_metadata_header(&lyrics.metadata, true) {
+        writeln!(writer, "{}", tag)?;
+    }
+    let mut current_part: Option<&str> = None;
+    for (index, main) in main_lines.iter().enumerate() {
+        if options.song_part_tags {
+            write_song_part_tag(&mut writer, &mut current_part, main)?;
+        }
+        let lrc_time = milliseconds_to_lrc_time(main.start_ms);
+        let partners = paired.get(&index).map(Vec::as_slice).unwrap_or_default();
+        for token in &order {
+            let text = if token == "main" {
+                to_single_line_text(&main.text())
+            } else {
+                // 同一轨道有多行与该主歌词行配对时，用空格连接
+                partners
+                    .iter()
+                    .filter(|(track, _)| track.key() == *token)
+                    .map(|(_, line)| to_single_line_text(&line.text()))
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            };
+            if !text.is_empty() {
+                writeln!(writer, "{}{}", lrc_time, text)?;
+            }
+        }
+    }
+    writer.flush()?;
+
+    log_success!("成功生成合并 LRC 文件。");
+    Ok(warning_occurred)
+}
+
+
+/// 从 ASS 文件中提取翻译 (按语言) 和罗马音，并用指定格式的写入器分别输出为 `<stem>.<语言代码>.<ext>` 和 `<stem>.roma.<ext>`。
+/// 带 {\k} 标签的行在 QRC/LYS/ASS 中保留逐字时间 (LRC 和 SRT 只有行级时间)；没有 K 标签的行在 QRC/LYS 中写为覆盖整行的单个音节。
+///
+/// # Returns
+/// * `Ok(bool)` - 读取或写入时是否发生了警告。
+fn extract_auxiliary_tracks(ass_path: &Path, format: ExtractFormat, options: &ConversionOptions) -> Result<bool, ConversionError> {
+    log_info!("开始从 {:?} 提取翻译和罗马音 ({})...", ass_path.file_name().unwrap_or_default(), format.extension());
+    let (lyrics, mut warning_occurred) = read_ass_file(ass_path)?;
+
+    // 按轨道分组，保持轨道首次出现的顺序
+    let mut tracks: Vec<(AuxiliaryTrack, Vec<LyricLine>)> = Vec::new();
+    for line in &lyrics.lines {
+        let Some(track) = AuxiliaryTrack::from_line(&line.style, line.name.as_deref()) else {
+            continue;
+        };
+
+        let mut line = line.clone();
+        if format != ExtractFormat::Ass {
+            // 其他格式中没有 Style/Name 的概念，按普通歌词行输出
+            line.style = "Default".to_string();
+            line.name = None;
+            line.untimed = false;
+        }
+        match tracks.iter_mut().find(|(existing, _)| *existing == track) {
+            Some((_, lines)) => lines.push(line),
+            None => tracks.push((track, vec![line])),
+        }
+    }
+
+    if tracks.is_empty() {
+        log_info!("在文件中未找到翻译或罗马音行。");
+        return Ok(warning_occurred);
+    }
+
+    let stem = ass_path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+    for (track, mut lines) in tracks {
+        lines.sort_by_key(|line| line.start_ms);
+        let track_lyrics = Lyrics { metadata: lyrics.metadata.clone(), lines };
+        let output_path = ass_path.with_file_name(format!("{}.{}.{}", stem, track.key(), format.extension()));
+
+        log_info!("正在生成{}文件: {:?}", track, output_path.file_name().unwrap_or_default());
+        match format {
+            ExtractFormat::Lrc => write_lrc_file(&track_lyrics, &output_path)?,
+            ExtractFormat::Qrc => write_qrc_file(&track_lyrics, &output_path, options)?,
+            ExtractFormat::Lys => warning_occurred |= write_lys_file(&track_lyrics, &output_path, options)?,
+            ExtractFormat::Ass => write_ass_file(&track_lyrics, &output_path, options)?,
+            ExtractFormat::Srt => write_srt_file(&track_lyrics, &output_path)?,
+        }
+    }
+
+    log_success!("翻译和罗马音提取完成。");
+    Ok(warning_occurred)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 构造一个没有注音和额外标签的音节。
+    fn syllable(text: &str, start_ms: usize, duration_ms: usize) -> Syllable {
+        Syllable {
+            text: text.to_string(),
+            start_ms,
+            duration_ms,
+            is_gap: text.is_empty(),
+            ass_tags: String::new(),
+            ruby: None,
+        }
+    }
+
+    /// 构造一个 Default 样式的歌词行。
+    fn line(start_ms: usize, end_ms: usize, syllables: Vec<Syllable>) -> LyricLine {
+        LyricLine {
+            line_number: 1,
+            start_ms,
+            end_ms,
+            style: "Default".to_string(),
+            name: None,
+            effect: None,
+            song_part: None,
+            syllables,
+            untimed: false,
+            position: None,
+        }
+    }
+
+    #[test]
+    fn time_warp_interpolates_between_anchors_and_offsets_outside() {
+        let warp = TimeWarp::new(vec![(3000, 3000), (1000, 2000)]).unwrap();
+        assert_eq!(warp.map(2000), 2500);
+        assert_eq!(warp.map(1000), 2000);
+        // 锚点范围之外按最近锚点的偏移平移
+        assert_eq!(warp.map(500), 1500);
+        assert_eq!(warp.map(4000), 4000);
+    }
+
+    #[test]
+    fn time_warp_rejects_invalid_anchors() {
+        assert!(TimeWarp::new(Vec::new()).is_err());
+        assert!(TimeWarp::new(vec![(1000, 1000), (1000, 2000)]).is_err());
+        assert!(TimeWarp::new(vec![(1000, 2000), (2000, 1500)]).is_err());
+    }
+
+    #[test]
+    fn time_warp_parses_anchor_formats() {
+        assert_eq!(TimeWarp::parse_anchor("29264->30000").unwrap(), (29264, 30000));
+        assert_eq!(TimeWarp::parse_anchor("100 = 200").unwrap(), (100, 200));
+        assert_eq!(TimeWarp::parse_anchor("0:00:29.26->0:00:30.00").unwrap(), (29260, 30000));
+        assert!(TimeWarp::parse_anchor("29264").is_err());
+    }
+
+    #[test]
+    fn time_warp_scales_syllables_and_reports_removed_regions() {
+        let warp = TimeWarp::new(vec![(0, 0), (1000, 2000), (2000, 2000)]).unwrap();
+        let mut lyrics = Lyrics {
+            lines: vec![line(0, 1000, vec![syllable("a", 0, 500), syllable("b", 500, 500)])],
+            ..Lyrics::default()
+        };
+        assert!(!apply_time_warp(&mut lyrics, &warp));
+        let warped = &lyrics.lines[0];
+        assert_eq!((warped.start_ms, warped.end_ms), (0, 2000));
+        assert_eq!((warped.syllables[1].start_ms, warped.syllables[1].duration_ms), (1000, 1000));
+
+        // 1000 - 2000 ms 被删除，落在其中的行会被报告
+        assert_eq!(warp.removed_region_overlapping(1500, 1800), Some((1000, 2000)));
+        let mut lyrics = Lyrics { lines: vec![line(1500, 1800, Vec::new())], ..Lyrics::default() };
+        assert!(apply_time_warp(&mut lyrics, &warp));
+        assert_eq!((lyrics.lines[0].start_ms, lyrics.lines[0].end_ms), (2000, 2000));
+    }
+
+    #[test]
+    fn rounding_strategies_convert_milliseconds() {
+        assert_eq!(RoundingStrategy::Truncate.to_centiseconds(29264), 2926);
+        assert_eq!(RoundingStrategy::Truncate.to_centiseconds(29269), 2926);
+        assert_eq!(RoundingStrategy::Nearest.to_centiseconds(29265), 2927);
+        assert_eq!(RoundingStrategy::Cumulative.to_centiseconds(29264), 2926);
+    }
+
+    #[test]
+    fn cumulative_rounding_keeps_k_sum_equal_to_line_duration() {
+        let line = line(0, 60, (0..4).map(|i| syllable("a", i * 15, 15)).collect());
+        let k_values = |rounding| build_ass_karaoke_segments(&line, rounding, false).iter().map(|s| s.k_cs).collect::<Vec<_>>();
+
+        assert_eq!(k_values(RoundingStrategy::Cumulative), vec![2, 1, 2, 1]);
+        // 各段单独取整时误差会累积
+        assert_eq!(k_values(RoundingStrategy::Nearest), vec![2, 2, 2, 2]);
+        assert_eq!(k_values(RoundingStrategy::Truncate), vec![1, 1, 1, 1]);
+    }
+
+    #[test]
+    fn cumulative_rounding_writes_gaps_between_syllables() {
+        let line = line(1000, 1600, vec![syllable("a", 1004, 196), syllable("b", 1404, 196)]);
+        let segments = build_ass_karaoke_segments(&line, RoundingStrategy::Cumulative, false);
+        let summary: Vec<(usize, &str)> = segments.iter().map(|s| (s.k_cs, s.text.as_str())).collect();
+        assert_eq!(summary, vec![(20, "a"), (20, ""), (20, "b")]);
+        assert_eq!(segments.iter().map(|s| s.k_cs).sum::<usize>(), 60);
+    }
+
+    /// 音节在 900 ms 处结束、行在 1000 ms 处结束的歌词。
+    fn mismatched_lyrics() -> Lyrics {
+        Lyrics {
+            lines: vec![line(0, 1000, vec![syllable("a", 0, 300), syllable("b", 300, 600)])],
+            ..Lyrics::default()
+        }
+    }
+
+    #[test]
+    fn fix_timing_stretch_moves_line_end() {
+        let mut lyrics = mismatched_lyrics();
+        assert_eq!(fix_line_timing(&mut lyrics, TimingFixStrategy::Stretch), 1);
+        assert_eq!(lyrics.lines[0].end_ms, 900);
+        // 已一致的行不再计数
+        assert_eq!(fix_line_timing(&mut lyrics, TimingFixStrategy::Stretch), 0);
+    }
+
+    #[test]
+    fn fix_timing_pad_extends_last_syllable() {
+        let mut lyrics = mismatched_lyrics();
+        assert_eq!(fix_line_timing(&mut lyrics, TimingFixStrategy::Pad), 1);
+        assert_eq!(lyrics.lines[0].end_ms, 1000);
+        assert_eq!(lyrics.lines[0].syllables[1].duration_ms, 700);
+    }
+
+    #[test]
+    fn fix_timing_pad_falls_back_to_stretch_when_last_syllable_starts_late() {
+        let mut lyrics = Lyrics {
+            lines: vec![line(0, 500, vec![syllable("a", 0, 300), syllable("b", 600, 300)])],
+            ..Lyrics::default()
+        };
+        assert_eq!(fix_line_timing(&mut lyrics, TimingFixStrategy::Pad), 1);
+        assert_eq!(lyrics.lines[0].end_ms, 900);
+        assert_eq!(lyrics.lines[0].syllables[1].duration_ms, 300);
+    }
+
+    #[test]
+    fn fix_timing_scale_rounds_syllable_boundaries() {
+        let mut lyrics = Lyrics {
+            lines: vec![line(0, 1000, vec![syllable("a", 0, 100), syllable("b", 100, 200), syllable("c", 300, 300)])],
+            ..Lyrics::default()
+        };
+        assert_eq!(fix_line_timing(&mut lyrics, TimingFixStrategy::Scale), 1);
+        let times: Vec<(usize, usize)> = lyrics.lines[0].syllables.iter().map(|s| (s.start_ms, s.duration_ms)).collect();
+        assert_eq!(times, vec![(0, 167), (167, 333), (500, 500)]);
+    }
+
+    #[test]
+    fn fix_timing_tighten_removes_leading_and_trailing_gaps() {
+        let mut lyrics = Lyrics {
+            lines: vec![line(
+                0,
+                1200,
+                vec![syllable("", 0, 200), syllable("a", 200, 300), syllable("b", 500, 300), syllable("", 800, 400)],
+            )],
+            ..Lyrics::default()
+        };
+        assert_eq!(fix_line_timing(&mut lyrics, TimingFixStrategy::Tighten), 1);
+        let tightened = &lyrics.lines[0];
+        assert_eq!((tightened.start_ms, tightened.end_ms), (200, 800));
+        let texts: Vec<&str> = tightened.syllables.iter().map(|s| s.text.as_str()).collect();
+        assert_eq!(texts, vec!["a", "b"]);
+    }
+
+    #[test]
+    fn exact_timing_effect_parses_records() {
+        assert_eq!(parse_exact_timing_effect("x-ms:2911=29114/2946=29464/35=352"), Some(vec![(2911, 29114), (2946, 29464), (35, 352)]));
+        // 至少需要行起止时间，格式无效时不是精确时间记录
+        assert_eq!(parse_exact_timing_effect("x-ms:2911=29114"), None);
+        assert_eq!(parse_exact_timing_effect("x-ms:2911=a/2946=29464"), None);
+        assert_eq!(parse_exact_timing_effect("karaoke"), None);
+    }
+
+    #[test]
+    fn exact_timing_restores_unmodified_times() {
+        let entries = parse_exact_timing_effect("x-ms:2911=29114/2946=29464/15=152/20=198").unwrap();
+        let mut restored = line(29110, 29460, vec![syllable("a", 29110, 150), syllable("b", 29260, 200)]);
+        restore_exact_timing(&mut restored, &[15, 20], &entries);
+        assert_eq!((restored.start_ms, restored.end_ms), (29114, 29464));
+        let times: Vec<(usize, usize)> = restored.syllables.iter().map(|s| (s.start_ms, s.duration_ms)).collect();
+        assert_eq!(times, vec![(29114, 152), (29266, 198)]);
+    }
+
+    #[test]
+    fn exact_timing_keeps_times_edited_in_aegisub() {
+        let entries = parse_exact_timing_effect("x-ms:2911=29114/2946=29464/15=152/20=198").unwrap();
+
+        // 行开始时间和第二个 K 值被修改过，只还原未修改的部分
+        let mut edited = line(29000, 29460, vec![syllable("a", 29000, 150), syllable("b", 29150, 250)]);
+        restore_exact_timing(&mut edited, &[15, 25], &entries);
+        assert_eq!((edited.start_ms, edited.end_ms), (29000, 29464));
+        let times: Vec<(usize, usize)> = edited.syllables.iter().map(|s| (s.start_ms, s.duration_ms)).collect();
+        assert_eq!(times, vec![(29000, 152), (29152, 250)]);
+
+        // K 分段数量与记录不一致时只还原行起止时间
+        let mut split = line(29110, 29460, vec![syllable("a", 29110, 150), syllable("b", 29260, 100), syllable("c", 29360, 100)]);
+        restore_exact_timing(&mut split, &[15, 10, 10], &entries);
+        assert_eq!((split.start_ms, split.end_ms), (29114, 29464));
+        let durations: Vec<usize> = split.syllables.iter().map(|s| s.duration_ms).collect();
+        assert_eq!(durations, vec![150, 100, 100]);
+    }
+
+    #[test]
+    fn exact_timing_restores_untimed_lines() {
+        // 纯文本行没有 K 分段，记录中也只有行起止时间
+        let entries = parse_exact_timing_effect("x-ms:2911=29114/3256=32560").unwrap();
+        let mut untimed = line(29110, 32560, vec![syllable("translation", 29110, 3450)]);
+        untimed.untimed = true;
+        restore_exact_timing(&mut untimed, &[], &entries);
+        assert_eq!((untimed.start_ms, untimed.end_ms), (29114, 32560));
+        assert_eq!(untimed.syllables[0].start_ms, 29114);
+    }
+
+    /// 只启用 `rule` 校验 `lines` (行号依次为 1、2、…)，返回报告了问题的行号。
+    fn validation_lines(mut lines: Vec<LyricLine>, format: LyricFormat, rule: ValidationRule) -> Vec<usize> {
+        for (index, line) in lines.iter_mut().enumerate() {
+            line.line_number = index + 1;
+        }
+        let config = ValidationConfig { enabled_rules: vec![rule], max_syllable_ms: 10000, overlap_tolerance_ms: 0 };
+        validate_lyrics(&Lyrics { lines, ..Lyrics::default() }, format, &config)
+            .iter()
+            .map(|issue| issue.line_number)
+            .collect()
+    }
+
+    /// 构造一个带有 Name 的歌词行。
+    fn named_line(name: &str, start_ms: usize, end_ms: usize, syllables: Vec<Syllable>) -> LyricLine {
+        LyricLine { name: Some(name.to_string()), ..line(start_ms, end_ms, syllables) }
+    }
+
+    #[test]
+    fn validation_reports_k_sum_mismatch_only_for_ass() {
+        let short = || vec![line(0, 1000, vec![syllable("a", 0, 900)])];
+        assert_eq!(validation_lines(short(), LyricFormat::Ass, ValidationRule::KSumMismatch), vec![1]);
+        assert!(validation_lines(short(), LyricFormat::Qrc, ValidationRule::KSumMismatch).is_empty());
+        let exact = vec![line(0, 1000, vec![syllable("a", 0, 1000)])];
+        assert!(validation_lines(exact, LyricFormat::Ass, ValidationRule::KSumMismatch).is_empty());
+    }
+
+    #[test]
+    fn validation_reports_overlapping_main_lines() {
+        let rule = ValidationRule::OverlappingLines;
+        let overlapping = vec![line(0, 1000, vec![syllable("a", 0, 1000)]), line(900, 2000, vec![syllable("b", 900, 1100)])];
+        assert_eq!(validation_lines(overlapping, LyricFormat::Ass, rule), vec![2]);
+        let adjacent = vec![line(0, 1000, vec![syllable("a", 0, 1000)]), line(1000, 2000, vec![syllable("b", 1000, 1000)])];
+        assert!(validation_lines(adjacent, LyricFormat::Ass, rule).is_empty());
+        // 背景人声行与主歌词行重叠是正常的
+        let background = vec![line(0, 1000, vec![syllable("a", 0, 1000)]), named_line("背", 500, 1500, vec![syllable("b", 500, 1000)])];
+        assert!(validation_lines(background, LyricFormat::Ass, rule).is_empty());
+    }
+
+    #[test]
+    fn validation_reports_syllable_timing_problems() {
+        let rule = ValidationRule::NonMonotonicSyllables;
+        let backwards = vec![line(0, 1000, vec![syllable("a", 0, 500), syllable("b", 400, 600)])];
+        assert_eq!(validation_lines(backwards, LyricFormat::Qrc, rule), vec![1]);
+        let ordered = vec![line(0, 1000, vec![syllable("a", 0, 500), syllable("b", 500, 500)])];
+        assert!(validation_lines(ordered, LyricFormat::Qrc, rule).is_empty());
+
+        let rule = ValidationRule::SyllableOutOfBounds;
+        let outside = vec![line(100, 1000, vec![syllable("a", 0, 200)])];
+        assert_eq!(validation_lines(outside, LyricFormat::Qrc, rule), vec![1]);
+        let inside = vec![line(100, 1000, vec![syllable("a", 100, 900)])];
+        assert!(validation_lines(inside, LyricFormat::Qrc, rule).is_empty());
+    }
+
+    #[test]
+    fn validation_reports_syllable_durations() {
+        let zero = || vec![line(0, 1000, vec![syllable("a", 0, 0), syllable("b", 0, 1000)])];
+        assert_eq!(validation_lines(zero(), LyricFormat::Ass, ValidationRule::ZeroSyllable), vec![1]);
+        assert!(validation_lines(zero(), LyricFormat::Ass, ValidationRule::LongSyllable).is_empty());
+        // 停顿不是歌词音节，时长为 0 也不报告
+        let gap = vec![line(0, 1000, vec![syllable("", 0, 0), syllable("a", 0, 1000)])];
+        assert!(validation_lines(gap, LyricFormat::Ass, ValidationRule::ZeroSyllable).is_empty());
+
+        let long = || vec![line(0, 20000, vec![syllable("a", 0, 20000)])];
+        assert_eq!(validation_lines(long(), LyricFormat::Ass, ValidationRule::LongSyllable), vec![1]);
+        assert!(validation_lines(long(), LyricFormat::Ass, ValidationRule::ZeroSyllable).is_empty());
+    }
+
+    #[test]
+    fn validation_reports_names() {
+        let rule = ValidationRule::OrphanBackground;
+        let orphan = vec![named_line("背", 0, 1000, vec![syllable("a", 0, 1000)])];
+        assert_eq!(validation_lines(orphan, LyricFormat::Ass, rule), vec![1]);
+        let attached = vec![line(0, 1000, vec![syllable("a", 0, 1000)]), named_line("x-bg", 0, 1000, vec![syllable("b", 0, 1000)])];
+        assert!(validation_lines(attached, LyricFormat::Ass, rule).is_empty());
+
+        let rule = ValidationRule::UnknownName;
+        let unknown = vec![named_line("路人甲", 0, 1000, vec![syllable("a", 0, 1000)])];
+        assert_eq!(validation_lines(unknown, LyricFormat::Ass, rule), vec![1]);
+        let known = vec![named_line("右", 0, 1000, vec![syllable("a", 0, 1000)])];
+        assert!(validation_lines(known, LyricFormat::Ass, rule).is_empty());
+
+        let rule = ValidationRule::MissingLang;
+        let translation = |name: &str| LyricLine { style: "ts".to_string(), ..named_line(name, 0, 1000, vec![syllable("译", 0, 1000)]) };
+        assert_eq!(validation_lines(vec![translation("")], LyricFormat::Ass, rule), vec![1]);
+        assert!(validation_lines(vec![translation("x-lang:zh-Hans")], LyricFormat::Ass, rule).is_empty());
+    }
+
+    #[test]
+    fn align_sequences_keeps_common_items_around_an_insertion() {
+        let aligned = align_sequences(&[1, 2, 3], &[1, 9, 2, 3], |a, b| a == b);
+        assert_eq!(aligned, vec![(Some(0), Some(0)), (None, Some(1)), (Some(1), Some(2)), (Some(2), Some(3))]);
+        // 没有公共项时两边的项都不匹配
+        let aligned = align_sequences(&[1], &[2], |a, b| a == b);
+        assert_eq!(aligned, vec![(Some(0), None), (None, Some(0))]);
+    }
+
+    /// 每个歌词行一个音节，依次从 0 ms 开始每行 1000 ms。
+    fn lyrics_from_texts(texts: &[&str]) -> Lyrics {
+        let lines = texts
+            .iter()
+            .enumerate()
+            .map(|(index, text)| line(index * 1000, (index + 1) * 1000, vec![syllable(text, index * 1000, 1000)]))
+            .collect();
+        Lyrics { lines, ..Lyrics::default() }
+    }
+
+    /// 把对比结果简写为 "=A"、"~A>B"、"-A"、"+A" 的形式。
+    fn diff_summary(diffs: &[LineDiff]) -> Vec<String> {
+        diffs
+            .iter()
+            .map(|diff| match diff {
+                LineDiff::Same(old, _) => format!("={}", old.text()),
+                LineDiff::Changed(old, new) => format!("~{}>{}", old.text(), new.text()),
+                LineDiff::Removed(old) => format!("-{}", old.text()),
+                LineDiff::Added(new) => format!("+{}", new.text()),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn diff_pairs_overlapping_edits_and_reports_insertions() {
+        let old = lyrics_from_texts(&["A", "B", "C"]);
+        // 在 A、B 之间插入一行 (之后的行时间随之后移，但文本相同)
+        let new = lyrics_from_texts(&["A", "X", "B", "C"]);
+        assert_eq!(diff_summary(&diff_lyric_lines(&old, &new, false)), vec!["=A", "+X", "=B", "=C"]);
+        // 最后一行改了文本，时间仍与原来的 C 重叠
+        let new = lyrics_from_texts(&["A", "B", "D"]);
+        assert_eq!(diff_summary(&diff_lyric_lines(&old, &new, false)), vec!["=A", "=B", "~C>D"]);
+        // 删除的行与新增的行时间不重叠时不视为修改
+        let mut new = lyrics_from_texts(&["A", "B"]);
+        new.lines.push(line(4000, 5000, vec![syllable("D", 4000, 1000)]));
+        assert_eq!(diff_summary(&diff_lyric_lines(&old, &new, false)), vec!["=A", "=B", "-C", "+D"]);
+    }
+
+    #[test]
+    fn diff_of_identical_lyrics_has_no_changes() {
+        let lyrics = lyrics_from_texts(&["A", "B"]);
+        assert_eq!(diff_summary(&diff_lyric_lines(&lyrics, &lyrics, false)), vec!["=A", "=B"]);
+    }
+
+    #[test]
+    fn round_trip_without_losses() {
+        let lyrics = Lyrics {
+            lines: vec![line(0, 1000, vec![syllable("a", 0, 400), syllable("b", 400, 600)])],
+            ..Lyrics::default()
+        };
+        let (result, _) = round_trip_in_memory(&lyrics, LyricFormat::Ass, &ConversionOptions::default()).unwrap();
+        assert!(collect_round_trip_losses(&lyrics, &result).is_empty());
+    }
+
+    #[test]
+    fn round_trip_reports_lost_precision_and_lines() {
+        // ASS 只能精确到厘秒
+        let lyrics = Lyrics {
+            lines: vec![line(0, 1005, vec![syllable("a", 0, 1005)])],
+            ..Lyrics::default()
+        };
+        let (result, _) = round_trip_in_memory(&lyrics, LyricFormat::Ass, &ConversionOptions::default()).unwrap();
+        let losses = collect_round_trip_losses(&lyrics, &result);
+        assert!(losses.iter().any(|loss| loss.contains("行时间 0 - 1005 ms")), "{:?}", losses);
+
+        // QRC 没有样式，无法原样保存翻译行
+        let mut lyrics = Lyrics {
+            lines: vec![line(0, 1000, vec![syllable("a", 0, 1000)]), line(0, 1000, vec![syllable("译", 0, 1000)])],
+            ..Lyrics::default()
+        };
+        lyrics.lines[1].style = "ts".to_string();
+        let (result, _) = round_trip_in_memory(&lyrics, LyricFormat::Qrc, &ConversionOptions::default()).unwrap();
+        let losses = collect_round_trip_losses(&lyrics, &result);
+        assert!(losses.iter().any(|loss| loss.contains("'译'")), "{:?}", losses);
+    }
+
+    /// 构造一个 LRC 行。
+    fn lrc_entry(time_ms: usize, text: &str) -> LrcEntry {
+        LrcEntry { line_number: 1, time_ms, text: text.to_string() }
+    }
+
+    #[test]
+    fn lrc_entries_match_nearest_line_within_tolerance() {
+        let entries = [lrc_entry(1050, "a"), lrc_entry(2600, "b"), lrc_entry(5000, "c")];
+        // 5000 ms 与最近的主歌词行 (3000 ms) 相差超出容差
+        assert_eq!(match_lrc_entries_to_lines(&entries, &[1000, 3000], 500), vec![Some(0), Some(1), None]);
+    }
+
+    #[test]
+    fn lrc_entries_do_not_share_a_main_line() {
+        // 两个 LRC 行都最接近第一行：先到的匹配，后一个只能选容差内的其他行
+        let entries = [lrc_entry(1000, "a"), lrc_entry(1100, "b")];
+        assert_eq!(match_lrc_entries_to_lines(&entries, &[1000, 1500], 500), vec![Some(0), Some(1)]);
+        assert_eq!(match_lrc_entries_to_lines(&entries, &[1000, 3000], 500), vec![Some(0), None]);
+    }
+
+    #[test]
+    fn lrc_track_matches_only_main_lines() {
+        let mut lines = vec![
+            line(1000, 2000, vec![syllable("a", 1000, 1000)]),
+            line(1000, 2000, vec![syllable("译", 1000, 1000)]),
+            line(3000, 4000, vec![syllable("b", 3000, 1000)]),
+        ];
+        lines[1].style = "ts".to_string();
+        lines[2].name = Some("背".to_string());
+        let entries = [lrc_entry(1000, "x"), lrc_entry(3000, "y")];
+        let track = AuxiliaryTrack::Translation("zh".to_string());
+        let (matched, unmatched) = match_lrc_track(&lines, Path::new("song.zh.lrc"), &track, &entries, 500);
+        // 翻译行和背景人声行不参与匹配
+        let matched: Vec<(&str, usize)> = matched.iter().map(|(entry, index)| (entry.text.as_str(), *index)).collect();
+        assert_eq!(matched, vec![("x", 0)]);
+        assert!(unmatched);
+    }
+
+    #[test]
+    fn import_replaces_existing_track() {
+        let dir = std::env::temp_dir().join(format!("lyrics-import-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ass_path = dir.join("song.ass");
+        let lrc_path = dir.join("song.zh.lrc");
+        let output_path = dir.join("song.out.ass");
+        std::fs::write(
+            &ass_path,
+            "[Events]\n\
+             Format: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n\
+             Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{\\k100}一\n\
+             Dialogue: 0,0:00:01.00,0:00:02.00,ts,x-lang:zh,0,0,0,,旧翻译\n\
+             Dialogue: 0,0:00:03.00,0:00:04.00,Default,,0,0,0,,{\\k100}二\n",
+        )
+        .unwrap();
+        std::fs::write(&lrc_path, "[00:01.00]译一\n[00:03.05]译二\n").unwrap();
+
+        let result = import_lrc_into_ass(&ass_path, &[lrc_path], &output_path, 500);
+        let output = std::fs::read_to_string(&output_path);
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!result.unwrap(), "所有 LRC 行都应匹配");
+        let translations: Vec<&str> = output.as_ref().unwrap().lines().filter(|line| line.contains(",ts,")).collect();
+        assert_eq!(
+            translations,
+            vec![
+                "Dialogue: 0,0:00:01.00,0:00:02.00,ts,x-lang:zh,0,0,0,,译一",
+                "Dialogue: 0,0:00:03.00,0:00:04.00,ts,x-lang:zh,0,0,0,,译二",
+            ]
+        );
+    }
+
+    /// 把 `events` (ASS 的 Dialogue 行) 写入临时目录下的 `<name>.ass`，生成合并 LRC，
+    /// 返回是否发生了警告和合并 LRC 中的歌词行 (不含文件头)。
+    fn merged_lrc(name: &str, events: &str, order: &[&str]) -> (bool, Vec<String>) {
+        let dir = std::env::temp_dir().join(format!("lyrics-merged-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ass_path = dir.join(format!("{}.ass", name));
+        std::fs::write(
+            &ass_path,
+            format!("[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n{}", events),
+        )
+        .unwrap();
+        let order: Vec<String> = order.iter().map(|token| token.to_string()).collect();
+        let warned = write_merged_lrc_file(&ass_path, &ConversionOptions { merged_lrc_order: order, ..ConversionOptions::default() }).unwrap();
+        let lrc_path = dir.join(format!("{}.merged.lrc", name));
+        let lrc = std::fs::read_to_string(&lrc_path).unwrap();
+        std::fs::remove_file(&ass_path).unwrap();
+        std::fs::remove_file(&lrc_path).unwrap();
+        (warned, lrc.lines().filter(|line| line.starts_with("[0")).map(str::to_string).collect())
+    }
+
+    /// 两个主歌词行：A (0 - 2 秒) 和 B (2 - 4 秒)。
+    const MERGED_MAIN_EVENTS: &str = "Dialogue: 0,0:00:00.00,0:00:02.00,Default,,0,0,0,,{\\k200}A\n\
+                                      Dialogue: 0,0:00:02.00,0:00:04.00,Default,,0,0,0,,{\\k200}B\n";
+
+    #[test]
+    fn merged_lrc_pairs_by_largest_overlap() {
+        // 第一行翻译与 B 重叠更多；第二行与 A、B 重叠相同，归属于较早的 A
+        let events = format!(
+            "{}Dialogue: 0,0:00:01.50,0:00:03.00,ts,x-lang:zh,0,0,0,,译B\n\
+             Dialogue: 0,0:00:01.00,0:00:03.00,ts,x-lang:zh,0,0,0,,译A\n",
+            MERGED_MAIN_EVENTS
+        );
+        let (warned, lines) = merged_lrc("overlap", &events, &[]);
+        assert!(!warned);
+        assert_eq!(lines, vec!["[00:00.00]A", "[00:00.00]译A", "[00:02.00]B", "[00:02.00]译B"]);
+    }
+
+    #[test]
+    fn merged_lrc_warns_about_unpaired_lines() {
+        let events = format!("{}Dialogue: 0,0:00:05.00,0:00:06.00,roma,,0,0,0,,ro\n", MERGED_MAIN_EVENTS);
+        let (warned, lines) = merged_lrc("unpaired", &events, &[]);
+        assert!(warned);
+        assert_eq!(lines, vec!["[00:00.00]A", "[00:02.00]B"]);
+
+        // 没有语言的翻译行不属于任何轨道
+        let events = format!("{}Dialogue: 0,0:00:00.00,0:00:02.00,ts,,0,0,0,,译\n", MERGED_MAIN_EVENTS);
+        let (warned, lines) = merged_lrc("no-language", &events, &[]);
+        assert!(warned);
+        assert_eq!(lines, vec!["[00:00.00]A", "[00:02.00]B"]);
+    }
+
+    #[test]
+    fn merged_lrc_writes_only_listed_tracks() {
+        let events = format!(
+            "{}Dialogue: 0,0:00:00.00,0:00:02.00,roma,,0,0,0,,ro\n\
+             Dialogue: 0,0:00:00.00,0:00:02.00,ts,x-lang:zh,0,0,0,,译\n",
+            MERGED_MAIN_EVENTS
+        );
+        let (_, lines) = merged_lrc("order", &events, &["zh", "roma"]);
+        assert_eq!(lines, vec!["[00:00.00]译", "[00:00.00]ro"]);
+    }
+
+    #[test]
+    fn extract_writes_lrc_by_default() {
+        let dir = std::env::temp_dir().join(format!("lyrics-extract-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let ass_path = dir.join("song.ass");
+        std::fs::write(
+            &ass_path,
+            format!(
+                "[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n{}\
+                 Dialogue: 0,0:00:02.00,0:00:04.00,ts,x-lang:zh,0,0,0,,{{\\k100}}译{{\\k100}}B\n\
+                 Dialogue: 0,0:00:00.00,0:00:02.00,ts,x-lang:zh,0,0,0,,译A\n\
+                 Dialogue: 0,0:00:02.00,0:00:04.00,roma,,0,0,0,,ro\n",
+                MERGED_MAIN_EVENTS
+            ),
+        )
+        .unwrap();
+
+        let result = extract_auxiliary_tracks(&ass_path, ExtractFormat::default(), &ConversionOptions::default());
+        let translation = std::fs::read_to_string(dir.join("song.zh.lrc"));
+        let roma = std::fs::read_to_string(dir.join("song.roma.lrc"));
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert!(!result.unwrap());
+        // 行按时间排序，K 标签被去掉
+        assert_eq!(translation.unwrap(), "[00:00.00]译A\n[00:02.00]译B\n");
+        assert_eq!(roma.unwrap(), "[00:02.00]ro\n");
+    }
+
+    #[test]
+    fn singer_rules_match_any_name_token() {
+        let right = SingerInfo { side: Some(DuetSide::Right), background: Some(false) };
+        assert_eq!(classify_singer("Default", Some("itunes:song-part=Verse 右"), None), Some(right));
+        let left = SingerInfo { side: Some(DuetSide::Left), background: Some(false) };
+        assert_eq!(classify_singer("Default", Some("左 itunes:song-part=Verse"), None), Some(left));
+        // 空 Name 匹配 "" 规则，非空但没有已知词的 Name 不匹配任何规则
+        assert_eq!(classify_singer("Default", None, None), Some(left));
+        assert_eq!(classify_singer("Default", Some("路人甲"), None), None);
+    }
+
+    #[test]
+    fn singer_rules_match_style_and_effect() {
+        let rule = SingerRule {
+            styles: vec!["Chorus".to_string()],
+            effects: vec!["bg".to_string()],
+            background: Some(true),
+            ..SingerRule::default()
+        };
+        assert!(rule.matches("chorus", Some("路人甲"), None));
+        assert!(rule.matches("Default", None, Some("karaoke BG")));
+        assert!(!rule.matches("Default", Some("Chorus"), Some("karaoke")));
+    }
+
+    #[test]
+    fn lys_properties_survive_ass_round_trip() {
+        // 属性 0-8 各一行：按 Name 重新计算时 1、2 会变为 4、5，背景人声行 (6-8) 的方向会按前一行推断
+        let source: String = (0..=8).map(|property| format!("[{}]词{}({},1000)\n", property, property, property * 1000)).collect();
+        let (lyrics, _) = parse_lys_lyrics(source.as_bytes(), source.len()).unwrap();
+
+        let options = ConversionOptions::default();
+        let mut ass = Vec::new();
+        write_ass_lyrics(&lyrics, &mut ass, &options).unwrap();
+        let (lyrics, _) = parse_ass_lyrics(ass.as_slice(), ass.len()).unwrap();
+        let mut lys = Vec::new();
+        write_lys_lyrics(&lyrics, &mut lys, &options).unwrap();
+
+        let lys = String::from_utf8(lys).unwrap();
+        let lines: Vec<&str> = lys.lines().filter(|line| line.contains('(')).collect();
+        assert_eq!(lines, source.lines().collect::<Vec<_>>());
+    }
+
+    /// 拆分后每行的 (Name, 开始时间, 结束时间, 文本)。
+    fn split_summary(lines: &[LyricLine]) -> Vec<(Option<&str>, usize, usize, String)> {
+        lines.iter().map(|line| (line.name.as_deref(), line.start_ms, line.end_ms, line.text())).collect()
+    }
+
+    #[test]
+    fn inline_background_splits_single_full_width_syllable() {
+        let mut lyrics = Lyrics {
+            lines: vec![line(0, 1500, vec![syllable("我", 0, 500), syllable("爱", 500, 500), syllable("（啊）", 1000, 500)])],
+            ..Lyrics::default()
+        };
+        split_inline_background(&mut lyrics, InlineBackgroundMode::Split);
+        assert_eq!(
+            split_summary(&lyrics.lines),
+            vec![(None, 0, 1000, "我爱".to_string()), (Some("背"), 1000, 1500, "啊".to_string())]
+        );
+    }
+
+    #[test]
+    fn inline_background_splits_multi_syllable_run() {
+        let mut lyrics = Lyrics {
+            lines: vec![line(0, 1200, vec![syllable("la", 0, 500), syllable(" (oh", 500, 300), syllable(" yeah)", 800, 400)])],
+            ..Lyrics::default()
+        };
+        split_inline_background(&mut lyrics, InlineBackgroundMode::Split);
+        assert_eq!(
+            split_summary(&lyrics.lines),
+            vec![(None, 0, 500, "la".to_string()), (Some("背"), 500, 1200, "oh yeah".to_string())]
+        );
+    }
+
+    #[test]
+    fn inline_background_line_without_main_syllables() {
+        let bracketed = || Lyrics {
+            lines: vec![line(0, 1000, vec![syllable("（啊", 0, 500), syllable("啊）", 500, 500)])],
+            ..Lyrics::default()
+        };
+        // 整行都在括号内时只保留背景人声行
+        let mut lyrics = bracketed();
+        split_inline_background(&mut lyrics, InlineBackgroundMode::Split);
+        assert_eq!(split_summary(&lyrics.lines), vec![(Some("背"), 0, 1000, "啊啊".to_string())]);
+
+        let mut lyrics = bracketed();
+        split_inline_background(&mut lyrics, InlineBackgroundMode::Drop);
+        assert!(lyrics.lines.is_empty());
+
+        // 默认按原样保留括号
+        let mut lyrics = bracketed();
+        split_inline_background(&mut lyrics, InlineBackgroundMode::default());
+        assert_eq!(split_summary(&lyrics.lines), vec![(None, 0, 1000, "（啊啊）".to_string())]);
+    }
+
+    /// 解析 `events` (ASS 的 Dialogue 行)，推断对唱方向后返回每行的 Name。
+    fn inferred_names(events: &str) -> Vec<Option<String>> {
+        let ass = format!("[Events]\nFormat: Layer, Start, End, Style, Name, MarginL, MarginR, MarginV, Effect, Text\n{}", events);
+        let (mut lyrics, _) = parse_ass_lyrics(ass.as_bytes(), ass.len()).unwrap();
+        infer_duet_singers(&mut lyrics);
+        lyrics.lines.into_iter().map(|line| line.name).collect()
+    }
+
+    /// 把 `&[Option<&str>]` 转为 `inferred_names` 的返回类型，便于比较。
+    fn names(names: &[Option<&str>]) -> Vec<Option<String>> {
+        names.iter().map(|name| name.map(str::to_string)).collect()
+    }
+
+    #[test]
+    fn infer_duet_background_from_style_font_size_and_margin_v() {
+        let style = "Dialogue: 0,0:00:00.00,0:00:02.00,Default,,0,0,0,,{\\k200}a\n\
+                     Dialogue: 0,0:00:01.00,0:00:03.00,Chorus BG,,0,0,0,,{\\k200}b\n";
+        assert_eq!(inferred_names(style), names(&[None, Some("背")]));
+
+        let font_size = "Dialogue: 0,0:00:00.00,0:00:02.00,Default,,0,0,0,,{\\fs40\\k200}a\n\
+                         Dialogue: 0,0:00:01.00,0:00:03.00,Default,,0,0,0,,{\\fs30\\k200}b\n\
+                         Dialogue: 0,0:00:03.00,0:00:04.00,Default,,0,0,0,,{\\fs40\\k100}c\n";
+        assert_eq!(inferred_names(font_size), names(&[None, Some("背"), None]));
+
+        let margin_v = "Dialogue: 0,0:00:00.00,0:00:02.00,Default,,0,0,10,,{\\k200}a\n\
+                        Dialogue: 0,0:00:01.00,0:00:03.00,Default,,0,0,60,,{\\k200}b\n\
+                        Dialogue: 0,0:00:03.00,0:00:04.00,Default,,0,0,10,,{\\k100}c\n";
+        assert_eq!(inferred_names(margin_v), names(&[None, Some("背"), None]));
+
+        // 只是与前一行重叠不足以判断为背景人声
+        let overlap_only = "Dialogue: 0,0:00:00.00,0:00:02.00,Default,,0,0,0,,{\\k200}a\n\
+                            Dialogue: 0,0:00:01.00,0:00:03.00,Default,,0,0,0,,{\\k200}b\n";
+        assert_eq!(inferred_names(overlap_only), names(&[None, None]));
+    }
+
+    #[test]
+    fn infer_duet_side_from_alignment_and_colour() {
+        // 恰好两种颜色时先出现的为左
+        let colours = "Dialogue: 0,0:00:00.00,0:00:02.00,Default,,0,0,0,,{\\c&H0000FF&\\k200}a\n\
+                       Dialogue: 0,0:00:02.00,0:00:03.00,Default,,0,0,0,,{\\c&HFF0000&\\k100}b\n\
+                       Dialogue: 0,0:00:03.00,0:00:04.00,Default,,0,0,0,,{\\c&H0000FF&\\k100}c\n";
+        assert_eq!(inferred_names(colours), names(&[Some("左"), Some("右"), Some("左")]));
+
+        // 只在重叠的行中出现的颜色视为背景人声，方向仍取自对齐方式
+        let alignment = "Dialogue: 0,0:00:00.00,0:00:02.00,Default,,0,0,0,,{\\an1\\k200}a\n\
+                         Dialogue: 0,0:00:02.00,0:00:03.00,Default,,0,0,0,,{\\an3\\k100}b\n\
+                         Dialogue: 0,0:00:02.50,0:00:03.50,Default,,0,0,0,,{\\an3\\c&H00FF00&\\k100}c\n\
+                         Dialogue: 0,0:00:04.00,0:00:05.00,Default,,0,0,0,,{\\an3\\c&H0000FF&\\k100}d\n";
+        assert_eq!(inferred_names(alignment), names(&[Some("左"), Some("右"), Some("右 x-lys:8"), Some("右")]));
+    }
+
+    #[test]
+    fn override_tags_keep_parenthesised_arguments_together() {
+        assert_eq!(split_override_tags(r"\fad(100,0)\k20\t(0,100,\fs20)\1c&HFF0000&"), vec![r"\fad(100,0)", r"\k20", r"\t(0,100,\fs20)", r"\1c&HFF0000&"]);
+        // 第一个反斜杠之前的内容是注释
+        assert_eq!(split_override_tags(r"comment\k10"), vec![r"\k10"]);
+    }
+
+    #[test]
+    fn tokenizer_splits_karaoke_syllables_with_their_tags() {
+        let tokens = tokenize_ass_karaoke_text(r"{\fad(100,0)}lead{\k20\1c&HFF0000&}a{\k0}{\kf30}b\Nc").unwrap();
+        let summary: Vec<(usize, &str, &str)> = tokens.iter().map(|t| (t.k_cs, t.text.as_str(), t.tags.as_str())).collect();
+        assert_eq!(summary, vec![(20, "leada", r"\fad(100,0)\1c&HFF0000&"), (0, "", ""), (30, "b\nc", "")]);
+    }
+
+    #[test]
+    fn tokenizer_treats_escaped_and_unclosed_braces_as_text() {
+        let tokens = tokenize_ass_karaoke_text(r"{\k10}a\{b\}{\k20}c{d").unwrap();
+        let texts: Vec<&str> = tokens.iter().map(|t| t.text.as_str()).collect();
+        assert_eq!(texts, vec!["a{b}", "c{d"]);
+        assert!(tokenize_ass_karaoke_text("plain text").unwrap().is_empty());
+        assert!(tokenize_ass_karaoke_text(r"{\k99999999999999999999999}a").is_err());
+    }
+
+    #[test]
+    fn ass_escapes_round_trip() {
+        assert_eq!(decode_ass_escapes(r"a\Nb\nc\hd\{e\}"), "a\nb c\u{a0}d{e}");
+        assert_eq!(encode_ass_escapes("a\nb\u{a0}c{d}"), r"a\Nb\hc\{d\}");
+        // 无法表示的反斜杠改为全角，避免被当作转义序列
+        assert_eq!(encode_ass_escapes(r"C:\Name \x"), r"C:＼Name \x");
+        for text in ["a{\\fs80}b} \\N c", "x\ny\u{a0}z", "50\\% off"] {
+            assert_eq!(decode_ass_escapes(&encode_ass_escapes(text)), text.replace("\\N", "＼N"));
+        }
+        assert_eq!(strip_ass_tags(r"{\k10}a\{b\}{\k20}c"), r"a\{b\}c");
+    }
+
+    #[test]
+    fn ass_lyric_event_skips_templater_output_and_keeps_karaoke_comments() {
+        let karaoke = "Comment: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,karaoke,{\\k100}a";
+        assert_eq!(ass_lyric_event(karaoke).as_deref(), Some("Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,karaoke,{\\k100}a"));
+        let dialogue = "Dialogue: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{\\k100}a";
+        assert_eq!(ass_lyric_event(dialogue).as_deref(), Some(dialogue));
+
+        assert_eq!(ass_lyric_event("Dialogue: 1,0:00:01.00,0:00:02.00,Default,,0,0,0,fx,{\\k100}a"), None);
+        assert_eq!(ass_lyric_event("Comment: 0,0:00:00.00,0:00:00.00,Default,,0,0,0,template syl,{\\pos($x,$y)}"), None);
+        // 默认不读取其他 Comment
+        assert_eq!(ass_lyric_event("Comment: 0,0:00:01.00,0:00:02.00,Default,,0,0,0,,{\\k100}a"), None);
+        assert_eq!(ass_lyric_event("Style: Default,Arial,20"), None);
+    }
+
+    /// 音节的 (文本, 注音, 开始时间, 时长)，用于比较注音处理结果。
+    fn ruby_summary(line: &LyricLine) -> Vec<(&str, Option<&str>, usize, usize)> {
+        line.syllables.iter().map(|s| (s.text.as_str(), s.ruby.as_deref(), s.start_ms, s.duration_ms)).collect()
+    }
+
+    #[test]
+    fn furigana_splits_base_text_and_reading() {
+        let mut furigana = line(0, 600, vec![syllable("漢字|かんじ", 0, 300), syllable("が", 300, 100), syllable("明|あ", 400, 100), syllable("日|<した", 500, 100)]);
+        apply_karaoke_furigana(&mut furigana);
+        assert_eq!(
+            ruby_summary(&furigana),
+            vec![("漢字", Some("かんじ"), 0, 300), ("が", None, 300, 100), ("明", Some("あした"), 400, 100), ("日", Some(""), 500, 100)]
+        );
+        assert_eq!(furigana.syllables[3].karaoke_text(), "日|<");
+    }
+
+    #[test]
+    fn furigana_multi_highlight_extends_previous_syllable() {
+        let mut highlighted = line(0, 600, vec![syllable("漢|かん", 0, 200), syllable("", 200, 100), syllable("#|じ", 300, 200), syllable("＃", 500, 100)]);
+        apply_karaoke_furigana(&mut highlighted);
+        assert_eq!(ruby_summary(&highlighted), vec![("漢", Some("かんじ"), 0, 600)]);
+    }
+
+    #[test]
+    fn furigana_strip_drops_readings() {
+        let mut furigana = line(0, 300, vec![syllable("漢字|かんじ", 0, 200), syllable("|<じ", 200, 100)]);
+        apply_karaoke_furigana(&mut furigana);
+        let mut lyrics = Lyrics { lines: vec![furigana], ..Lyrics::default() };
+        strip_furigana(&mut lyrics);
+        let stripped = &lyrics.lines[0];
+        assert!(stripped.syllables.iter().all(|s| s.ruby.is_none()));
+        // 只有注音的音节变为停顿
+        assert!(stripped.syllables[1].is_gap);
+        assert_eq!(stripped.text(), "漢字");
+    }
+
+    #[test]
+    fn karaoke_syntax_mode_decides_whether_furigana_is_parsed() {
+        assert!(KaraokeSyntax::Auto.enabled(true));
+        assert!(!KaraokeSyntax::Auto.enabled(false));
+        assert!(KaraokeSyntax::On.enabled(false));
+        assert!(!KaraokeSyntax::Off.enabled(true));
+    }
+}